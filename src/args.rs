@@ -0,0 +1,237 @@
+#![warn(clippy::all, clippy::pedantic)]
+use crate::FileBuffer;
+use std::{
+    ffi::OsString,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+pub const USAGE: &str = "\
+Usage: rflx [OPTIONS] [FILE]...
+
+Open each FILE in its own buffer. A FILE that does not exist yet
+is created when it is first saved.
+
+Positions:
+  +N FILE           Open FILE with the cursor on line N
+  + FILE            Open FILE with the cursor on the last line
+  FILE:LINE[:COL]   Open FILE with the cursor on LINE (and COL)
+
+Options:
+  -h, --help        Print this help and exit
+  -V, --version     Print the version and exit
+  --                Treat every following argument as a file";
+
+/// A file to open, along with where to place the cursor.
+/// Lines and columns are counted from 1, and `usize::MAX`
+/// stands for the last line.
+pub struct FileArg {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+/// What the command line asked Reflex to do.
+pub enum Action {
+    Edit(Vec<FileArg>),
+    Help,
+    Version,
+}
+
+/// Takes the command line arguments, without the program name.
+/// Returns the `Action` they describe. File names are kept as they
+/// are, so they need not be valid UTF-8.
+///
+/// # Errors
+///
+/// Returns an error message for unknown options, malformed
+/// positions, or a `+N` that is not followed by a file.
+pub fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Action, String> {
+    let mut files = Vec::new();
+    let mut pending_line = None;
+    let mut only_files = false;
+    for arg in args {
+        if !only_files {
+            let text = arg.to_string_lossy();
+            match text.as_ref() {
+                "-h" | "--help" => return Ok(Action::Help),
+                "-V" | "--version" => return Ok(Action::Version),
+                "--" => {
+                    only_files = true;
+                    continue;
+                }
+                _ => (),
+            }
+            if let Some(line) = text.strip_prefix('+') {
+                pending_line = Some(if line.is_empty() {
+                    usize::MAX
+                } else {
+                    line.parse::<usize>()
+                        .map_err(|_| format!("invalid line number: {text}"))?
+                });
+                continue;
+            }
+            if text.starts_with('-') && text.len() > 1 {
+                return Err(format!("unknown option: {text}"));
+            }
+        }
+        let mut file = split_position(arg);
+        if let Some(line) = pending_line.take() {
+            file.line = Some(line);
+        }
+        files.push(file);
+    }
+    if pending_line.is_some() {
+        return Err(String::from("a line number must be followed by a file"));
+    }
+    Ok(Action::Edit(files))
+}
+
+/// Takes a file argument.
+/// Splits a trailing `:LINE` or `:LINE:COL` off the path, unless
+/// a file with the full name exists or the name is not valid UTF-8.
+fn split_position(arg: OsString) -> FileArg {
+    let Some(arg) = arg.to_str().map(str::to_string) else {
+        return FileArg {
+            path: PathBuf::from(arg),
+            line: None,
+            column: None,
+        };
+    };
+    let whole = FileArg {
+        path: PathBuf::from(&arg),
+        line: None,
+        column: None,
+    };
+    if Path::new(&arg).exists() {
+        return whole;
+    }
+    let mut parts = arg.rsplitn(3, ':');
+    let last = parts.next().and_then(|part| part.parse::<usize>().ok());
+    let middle = parts.next();
+    let first = parts.next();
+    match (first, middle, last) {
+        (Some(path), Some(line), Some(column)) if !path.is_empty() => {
+            if let Ok(line) = line.parse::<usize>() {
+                FileArg {
+                    path: PathBuf::from(path),
+                    line: Some(line),
+                    column: Some(column),
+                }
+            } else {
+                FileArg {
+                    path: PathBuf::from(format!("{path}:{line}")),
+                    line: Some(column),
+                    column: None,
+                }
+            }
+        }
+        (None, Some(path), Some(line)) if !path.is_empty() => FileArg {
+            path: PathBuf::from(path),
+            line: Some(line),
+            column: None,
+        },
+        _ => whole,
+    }
+}
+
+/// Takes the files given on the command line.
+/// Opens a `FileBuffer` for each of them, moving the cursor to the
+/// requested position. Files that do not exist get an empty buffer
+/// that saves to their path.
+///
+/// # Errors
+///
+/// Returns an error message naming the first file that exists
+/// but cannot be read.
+pub fn open_files(files: &[FileArg]) -> Result<Vec<FileBuffer>, String> {
+    let mut file_buffers = Vec::with_capacity(files.len());
+    for file in files {
+        let mut file_buffer = match FileBuffer::open(&file.path) {
+            Ok(file_buffer) => file_buffer,
+            Err(error) if error.kind() == ErrorKind::NotFound => FileBuffer::with_path(&file.path),
+            Err(error) => return Err(format!("cannot open {}: {error}", file.path.display())),
+        };
+        if let Some(line) = file.line {
+            file_buffer.goto(
+                line.saturating_sub(1),
+                file.column.unwrap_or(1).saturating_sub(1),
+            );
+        }
+        file_buffers.push(file_buffer);
+    }
+    if file_buffers.is_empty() {
+        file_buffers.push(FileBuffer::default());
+    }
+    Ok(file_buffers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Action, String> {
+        parse(args.iter().map(OsString::from))
+    }
+
+    fn files(args: &[&str]) -> Vec<(PathBuf, Option<usize>, Option<usize>)> {
+        let Ok(Action::Edit(files)) = parse_args(args) else {
+            panic!("expected files to edit");
+        };
+        files
+            .into_iter()
+            .map(|file| (file.path, file.line, file.column))
+            .collect()
+    }
+
+    #[test]
+    fn parses_files_and_positions() {
+        assert_eq!(
+            files(&["a.rs", "+3", "b.rs", "+", "c.rs", "d.rs:4:5", "e.rs:6"]),
+            [
+                (PathBuf::from("a.rs"), None, None),
+                (PathBuf::from("b.rs"), Some(3), None),
+                (PathBuf::from("c.rs"), Some(usize::MAX), None),
+                (PathBuf::from("d.rs"), Some(4), Some(5)),
+                (PathBuf::from("e.rs"), Some(6), None),
+            ]
+        );
+        assert_eq!(files(&["f:g:7"]), [(PathBuf::from("f:g"), Some(7), None)]);
+    }
+
+    #[test]
+    fn double_dash_ends_options() {
+        assert_eq!(
+            files(&["--", "-h", "+1"]),
+            [
+                (PathBuf::from("-h"), None, None),
+                (PathBuf::from("+1"), None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_help_and_version() {
+        assert!(matches!(parse_args(&["a", "--help"]), Ok(Action::Help)));
+        assert!(matches!(parse_args(&["-V"]), Ok(Action::Version)));
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse_args(&["--frobnicate"]).is_err());
+        assert!(parse_args(&["+x", "a"]).is_err());
+        assert!(parse_args(&["a", "+2"]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_file_names_that_are_not_utf8() {
+        use std::os::unix::ffi::OsStringExt;
+        let name = OsString::from_vec(b"caf\xe9:3".to_vec());
+        let Ok(Action::Edit(files)) = parse([name.clone()]) else {
+            panic!("expected files to edit");
+        };
+        assert_eq!(files[0].path, PathBuf::from(name));
+        assert_eq!(files[0].line, None);
+    }
+}
@@ -0,0 +1,301 @@
+#![warn(clippy::all, clippy::pedantic)]
+use regex::{Regex, RegexBuilder};
+use std::{mem, ops::RangeInclusive};
+
+/// The lines a command refers to, used to resolve ranges.
+pub struct LineContext {
+    pub current_line: usize,
+    pub last_line: usize,
+    pub visual_lines: Option<(usize, usize)>,
+}
+
+/// Takes a string slice and the line context.
+/// Parses a single line address at the start of the input, such as
+/// `12`, `.`, `$`, `'<` or `'>`. Returns the zero-based line and the
+/// rest of the input, or `None` if the input does not start with an
+/// address.
+fn parse_address<'a>(
+    input: &'a str,
+    context: &LineContext,
+) -> Result<Option<(usize, &'a str)>, String> {
+    if let Some(rest) = input.strip_prefix('.') {
+        Ok(Some((context.current_line, rest)))
+    } else if let Some(rest) = input.strip_prefix('$') {
+        Ok(Some((context.last_line, rest)))
+    } else if let Some(rest) = input.strip_prefix("'<") {
+        let (start, _) = context.visual_lines.ok_or("No visual selection")?;
+        Ok(Some((start, rest)))
+    } else if let Some(rest) = input.strip_prefix("'>") {
+        let (_, end) = context.visual_lines.ok_or("No visual selection")?;
+        Ok(Some((end, rest)))
+    } else {
+        let digits = input.chars().take_while(char::is_ascii_digit).count();
+        if digits == 0 {
+            return Ok(None);
+        }
+        let line: usize = input[..digits]
+            .parse()
+            .map_err(|_| String::from("Invalid line number"))?;
+        Ok(Some((line.saturating_sub(1), &input[digits..])))
+    }
+}
+
+/// Takes a string slice and the line context.
+/// Parses an optional line range at the start of a command, such as
+/// `%`, `10,20` or `'<,'>`. Returns the zero-based inclusive range of
+/// lines, if there was one, and the rest of the command.
+///
+/// # Errors
+///
+/// Returns an error message if an address cannot be resolved or the
+/// range is backwards.
+pub fn parse_range<'a>(
+    input: &'a str,
+    context: &LineContext,
+) -> Result<(Option<RangeInclusive<usize>>, &'a str), String> {
+    if let Some(rest) = input.strip_prefix('%') {
+        return Ok((Some(0..=context.last_line), rest));
+    }
+    let Some((start, rest)) = parse_address(input, context)? else {
+        return Ok((None, input));
+    };
+    let (end, rest) = if let Some(rest) = rest.strip_prefix(',') {
+        parse_address(rest, context)?.ok_or("Missing end of range")?
+    } else {
+        (start, rest)
+    };
+    if start > end {
+        return Err(String::from("Backwards range given"));
+    }
+    if end > context.last_line {
+        return Err(String::from("Invalid range"));
+    }
+    Ok((Some(start..=end), rest))
+}
+
+/// A parsed `:w` or `:wq` command, such as `:w! path` or `:w >> path`.
+pub struct WriteCommand<'a> {
+    /// Whether to overwrite an existing file or write part of the
+    /// buffer to its own file.
+    pub force: bool,
+    /// Whether to add to the end of the file rather than replace it.
+    pub append: bool,
+    /// Whether to close the window once the file is written.
+    pub quit: bool,
+    /// The file to write to, if not the buffer's own.
+    pub path: Option<&'a str>,
+}
+
+impl<'a> WriteCommand<'a> {
+    /// Takes a command without its range.
+    /// Builds a `WriteCommand` from it, or returns `None` if it is
+    /// not a write command.
+    #[must_use]
+    pub fn parse(command: &'a str) -> Option<Self> {
+        let (rest, quit) = if let Some(rest) = command.strip_prefix("write") {
+            (rest, false)
+        } else if let Some(rest) = command.strip_prefix("wq") {
+            (rest, true)
+        } else {
+            (command.strip_prefix('w')?, false)
+        };
+        let (force, rest) = match rest.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        // Other commands starting with `w`, such as `wa`.
+        if !(rest.is_empty() || rest.starts_with(char::is_whitespace) || rest.starts_with(">>")) {
+            return None;
+        }
+        let rest = rest.trim_start();
+        let (append, rest) = match rest.strip_prefix(">>") {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, rest),
+        };
+        Some(Self {
+            force,
+            append,
+            quit,
+            path: Some(rest).filter(|path| !path.is_empty()),
+        })
+    }
+}
+
+/// A parsed `:s/pattern/replacement/flags` command.
+pub struct Substitution {
+    pub regex: Regex,
+    pub replacement: String,
+    pub global: bool,
+    pub confirm: bool,
+}
+
+impl Substitution {
+    /// Takes the part of a substitute command after the `s`.
+    /// Builds a `Substitution` from it. Any non-alphanumeric char
+    /// may be used as the delimiter.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if the command is malformed, the
+    /// pattern is not a valid regex or an unknown flag is given.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut chars = input.chars();
+        let delimiter = chars.next().ok_or("Missing pattern")?;
+        if delimiter.is_alphanumeric() || delimiter == '\\' || delimiter.is_whitespace() {
+            return Err(String::from("Invalid delimiter"));
+        }
+        let mut parts: Vec<String> = Vec::with_capacity(3);
+        let mut current = String::new();
+        let mut escaped = false;
+        for c in chars {
+            if escaped {
+                if c != delimiter {
+                    current.push('\\');
+                }
+                current.push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == delimiter && parts.len() < 2 {
+                parts.push(mem::take(&mut current));
+            } else {
+                current.push(c);
+            }
+        }
+        if escaped {
+            current.push('\\');
+        }
+        parts.push(current);
+        let pattern = &parts[0];
+        if pattern.is_empty() {
+            return Err(String::from("Missing pattern"));
+        }
+        let replacement = parts.get(1).map_or("", String::as_str);
+        let flags = parts.get(2).map_or("", String::as_str);
+        let mut global = false;
+        let mut confirm = false;
+        let mut case_insensitive = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'c' => confirm = true,
+                'i' => case_insensitive = true,
+                _ => return Err(format!("Unknown flag: {flag}")),
+            }
+        }
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|error| format!("Invalid regex: {error}"))?;
+        Ok(Self {
+            regex,
+            replacement: expand_replacement(replacement),
+            global,
+            confirm,
+        })
+    }
+}
+
+/// Takes a replacement string written with Vim-style escapes.
+/// Returns it in the syntax used by `regex::Captures::expand`, so that
+/// `&` and `\0` refer to the whole match and `\1`-`\9` to capture
+/// groups. Any other char, including `$`, stands for itself, and a
+/// backslash makes `&` or `\` literal.
+fn expand_replacement(replacement: &str) -> String {
+    let mut expanded = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => expanded.push_str("${0}"),
+            '$' => expanded.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => {
+                    expanded.push_str("${");
+                    expanded.push(digit);
+                    expanded.push('}');
+                }
+                Some('n') => expanded.push('\n'),
+                Some('t') => expanded.push('\t'),
+                Some('$') => expanded.push_str("$$"),
+                Some(other) => expanded.push(other),
+                None => expanded.push('\\'),
+            },
+            _ => expanded.push(c),
+        }
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTEXT: LineContext = LineContext {
+        current_line: 4,
+        last_line: 9,
+        visual_lines: Some((2, 3)),
+    };
+
+    #[test]
+    fn parse_range_resolves_addresses() {
+        assert_eq!(
+            parse_range("%s/a/b/", &CONTEXT),
+            Ok((Some(0..=9), "s/a/b/"))
+        );
+        assert_eq!(parse_range("2,$d", &CONTEXT), Ok((Some(1..=9), "d")));
+        assert_eq!(parse_range(".s", &CONTEXT), Ok((Some(4..=4), "s")));
+        assert_eq!(parse_range("'<,'>s", &CONTEXT), Ok((Some(2..=3), "s")));
+        assert_eq!(parse_range("w", &CONTEXT), Ok((None, "w")));
+    }
+
+    #[test]
+    fn parse_range_rejects_bad_ranges() {
+        assert!(parse_range("5,2s", &CONTEXT).is_err());
+        assert!(parse_range("1,11s", &CONTEXT).is_err());
+        assert!(parse_range("1,s", &CONTEXT).is_err());
+        let context = LineContext {
+            visual_lines: None,
+            ..CONTEXT
+        };
+        assert!(parse_range("'<,'>s", &context).is_err());
+    }
+
+    #[test]
+    fn parse_write_commands() {
+        let write = WriteCommand::parse("w! >> out.txt").unwrap();
+        assert!(write.force && write.append && !write.quit);
+        assert_eq!(write.path, Some("out.txt"));
+        let write = WriteCommand::parse("wq").unwrap();
+        assert!(write.quit && write.path.is_none());
+        assert!(WriteCommand::parse("wa").is_none());
+    }
+
+    #[test]
+    fn parse_substitution_parts_and_flags() {
+        let substitution = Substitution::parse("#a/b#c#gi").unwrap();
+        assert_eq!(substitution.regex.as_str(), "a/b");
+        assert_eq!(substitution.replacement, "c");
+        assert!(substitution.global && !substitution.confirm);
+        assert!(substitution.regex.is_match("A/B"));
+        let substitution = Substitution::parse(r"/a\/b/c").unwrap();
+        assert_eq!(substitution.regex.as_str(), "a/b");
+        assert!(!substitution.global);
+    }
+
+    #[test]
+    fn parse_substitution_errors() {
+        assert!(Substitution::parse("").is_err());
+        assert!(Substitution::parse("//b/").is_err());
+        assert!(Substitution::parse("xaxbx").is_err());
+        assert!(Substitution::parse("/(/b/").is_err());
+        assert!(Substitution::parse("/a/b/q").is_err());
+    }
+
+    #[test]
+    fn replacement_escapes() {
+        assert_eq!(expand_replacement(r"<&>\1\n"), "<${0}>${1}\n");
+        assert_eq!(expand_replacement(r"\&\\"), r"&\");
+        assert_eq!(expand_replacement("$USD"), "$$USD");
+        assert_eq!(expand_replacement(r"\$1"), "$$1");
+    }
+}
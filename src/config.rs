@@ -0,0 +1,527 @@
+#![warn(clippy::all, clippy::pedantic)]
+use crate::{
+    encoding::{Encoding, LineEnding},
+    gutter::{Gutter, LineNumbers},
+    highlight::Language,
+    keymap::{self, Keymap},
+    Mode,
+};
+use std::{collections::HashMap, env, fs, io::ErrorKind, path::PathBuf};
+use toml::{Table, Value};
+
+/// Options that apply to the whole editor.
+#[derive(Clone)]
+pub struct Options {
+    pub scrolloff: usize,
+    pub theme: String,
+    pub alt_screen: bool,
+    pub line_numbers: LineNumbers,
+    pub marker_column: bool,
+    pub wrap_indicator: String,
+    pub backup: bool,
+}
+
+impl Default for Options {
+    /// Takes nothing.
+    /// Builds the `Options` used when the config leaves them unset.
+    fn default() -> Self {
+        Self {
+            scrolloff: 0,
+            theme: String::from("default"),
+            alt_screen: true,
+            line_numbers: LineNumbers::Absolute,
+            marker_column: false,
+            wrap_indicator: String::new(),
+            backup: false,
+        }
+    }
+}
+
+impl Options {
+    const NAMES: [&'static str; 7] = [
+        "scrolloff",
+        "theme",
+        "alt_screen",
+        "line_numbers",
+        "marker_column",
+        "wrap_indicator",
+        "backup",
+    ];
+
+    /// Takes itself, the name of an option and its new value.
+    /// Sets the option, returning `Ok(false)` if no editor option
+    /// has that name.
+    fn set(&mut self, name: &str, value: &str) -> Result<bool, String> {
+        match name {
+            "scrolloff" => self.scrolloff = parse_number(name, value)?,
+            "theme" => self.theme = value.to_string(),
+            "alt_screen" => self.alt_screen = parse_switch(name, value)?,
+            "line_numbers" => {
+                self.line_numbers = value.parse().map_err(|()| {
+                    format!(
+                        "Invalid value for {name}: {value} (none, absolute, relative or hybrid)"
+                    )
+                })?;
+            }
+            "marker_column" => self.marker_column = parse_switch(name, value)?,
+            "wrap_indicator" => self.wrap_indicator = value.to_string(),
+            "backup" => self.backup = parse_switch(name, value)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Takes itself and the name of an option.
+    /// Returns the value of the option, if it is an editor option.
+    fn get(&self, name: &str) -> Option<String> {
+        match name {
+            "scrolloff" => Some(self.scrolloff.to_string()),
+            "theme" => Some(self.theme.clone()),
+            "alt_screen" => Some(self.alt_screen.to_string()),
+            "line_numbers" => Some(self.line_numbers.to_string()),
+            "marker_column" => Some(self.marker_column.to_string()),
+            "wrap_indicator" => Some(self.wrap_indicator.clone()),
+            "backup" => Some(self.backup.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Takes itself.
+    /// Returns the `Gutter` the options describe.
+    #[must_use]
+    pub fn gutter(&self) -> Gutter {
+        Gutter {
+            line_numbers: self.line_numbers,
+            marker_column: self.marker_column,
+        }
+    }
+}
+
+/// Options that each `FileBuffer` holds its own copy of, and that
+/// may be overridden per language.
+#[derive(Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct BufferOptions {
+    pub indent_width: usize,
+    pub expand_tabs: bool,
+    pub tab_width: usize,
+    pub detect_indent: bool,
+    pub soft_wrap: bool,
+    pub wrap_at_words: bool,
+    pub fileformat: LineEnding,
+    pub encoding: Encoding,
+    /// The size in MiB above which a file is too large for
+    /// highlighting, soft wrap and indentation detection.
+    pub large_file_size: usize,
+}
+
+impl Default for BufferOptions {
+    /// Takes nothing.
+    /// Builds the `BufferOptions` used when the config leaves them unset.
+    fn default() -> Self {
+        Self {
+            indent_width: 4,
+            expand_tabs: true,
+            tab_width: 4,
+            detect_indent: true,
+            soft_wrap: false,
+            wrap_at_words: true,
+            fileformat: LineEnding::Lf,
+            encoding: Encoding::Utf8,
+            large_file_size: 64,
+        }
+    }
+}
+
+impl BufferOptions {
+    const NAMES: [&'static str; 9] = [
+        "indent_width",
+        "expand_tabs",
+        "tab_width",
+        "detect_indent",
+        "soft_wrap",
+        "wrap_at_words",
+        "fileformat",
+        "encoding",
+        "large_file_size",
+    ];
+
+    /// Takes itself, the name of an option and its new value.
+    /// Sets the option, returning `Ok(false)` if no buffer option
+    /// has that name. The option is left as it was if the value is
+    /// invalid.
+    fn set(&mut self, name: &str, value: &str) -> Result<bool, String> {
+        match name {
+            "indent_width" => self.indent_width = parse_width(name, value)?,
+            "expand_tabs" => self.expand_tabs = parse_switch(name, value)?,
+            "tab_width" => self.tab_width = parse_width(name, value)?,
+            "detect_indent" => self.detect_indent = parse_switch(name, value)?,
+            "soft_wrap" => self.soft_wrap = parse_switch(name, value)?,
+            "wrap_at_words" => self.wrap_at_words = parse_switch(name, value)?,
+            "fileformat" => {
+                self.fileformat = value.parse().map_err(|()| {
+                    format!("Invalid value for {name}: {value} (unix, dos or mac)")
+                })?;
+            }
+            "encoding" => {
+                self.encoding = value.parse().map_err(|()| {
+                    format!(
+                        "Invalid value for {name}: {value} (utf-8, utf-8-bom, utf-16le, utf-16be or latin1)"
+                    )
+                })?;
+            }
+            "large_file_size" => self.large_file_size = parse_number(name, value)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Takes itself and the name of an option.
+    /// Returns the value of the option, if it is a buffer option.
+    fn get(&self, name: &str) -> Option<String> {
+        match name {
+            "indent_width" => Some(self.indent_width.to_string()),
+            "expand_tabs" => Some(self.expand_tabs.to_string()),
+            "tab_width" => Some(self.tab_width.to_string()),
+            "detect_indent" => Some(self.detect_indent.to_string()),
+            "soft_wrap" => Some(self.soft_wrap.to_string()),
+            "wrap_at_words" => Some(self.wrap_at_words.to_string()),
+            "fileformat" => Some(self.fileformat.to_string()),
+            "encoding" => Some(self.encoding.to_string()),
+            "large_file_size" => Some(self.large_file_size.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// One item of a `:set` command.
+pub enum Setting<'a> {
+    /// Show the value of an option, written `name?` or, for
+    /// options that are not switches, `name`.
+    Show(&'a str),
+    /// Give an option a value, written `name=value`, or `name` and
+    /// `noname` for switches.
+    Assign(&'a str, &'a str),
+}
+
+impl<'a> Setting<'a> {
+    /// Takes one whitespace-separated item of a `:set` command.
+    /// Returns what the item asks for.
+    #[must_use]
+    pub fn parse(item: &'a str) -> Self {
+        if let Some(name) = item.strip_suffix('?') {
+            Setting::Show(name)
+        } else if let Some((name, value)) = item.split_once('=') {
+            Setting::Assign(name, value)
+        } else if is_switch(item) {
+            Setting::Assign(item, "true")
+        } else if let Some(name) = item.strip_prefix("no").filter(|name| is_switch(name)) {
+            Setting::Assign(name, "false")
+        } else {
+            Setting::Show(item)
+        }
+    }
+}
+
+/// The settings read from `config.toml`.
+#[derive(Default)]
+pub struct Config {
+    pub options: Options,
+    pub buffer_options: BufferOptions,
+    pub keymap: Keymap,
+    languages: HashMap<String, Vec<(String, String)>>,
+}
+
+impl Config {
+    /// Takes nothing.
+    /// Reads `$XDG_CONFIG_HOME/reflex/config.toml`, falling back to
+    /// the defaults if there is no such file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if the file cannot be read or holds
+    /// an invalid option.
+    pub fn load() -> Result<Self, String> {
+        let Some(path) = config_dir().map(|dir| dir.join("config.toml")) else {
+            return Ok(Self::default());
+        };
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => return Err(format!("{}: {error}", path.display())),
+        };
+        Self::parse(&source).map_err(|message| format!("{}: {message}", path.display()))
+    }
+
+    /// Takes the contents of a config file.
+    /// Returns the config it describes, or an error message if the
+    /// file is not valid TOML or holds an invalid option.
+    fn parse(source: &str) -> Result<Self, String> {
+        let table: Table = source
+            .parse()
+            .map_err(|error: toml::de::Error| error.message().to_string())?;
+        let mut config = Self::default();
+        for (key, value) in &table {
+            match (key.as_str(), value) {
+                ("language", Value::Table(languages)) => {
+                    for (language, overrides) in languages {
+                        let Value::Table(overrides) = overrides else {
+                            return Err(format!("language.{language} must be a table"));
+                        };
+                        let overrides = config.parse_overrides(language, overrides)?;
+                        config.languages.insert(language.clone(), overrides);
+                    }
+                }
+                ("language", _) => return Err(String::from("language must be a table")),
+                ("keys", Value::Table(modes)) => {
+                    // Normal mode bindings also apply in Visual mode,
+                    // so Visual mode ones are added last to override
+                    // them.
+                    let mut modes: Vec<_> = modes.iter().collect();
+                    modes.sort_by_key(|(mode, _)| mode.as_str() == "visual");
+                    for (mode, bindings) in modes {
+                        let Value::Table(bindings) = bindings else {
+                            return Err(format!("keys.{mode} must be a table"));
+                        };
+                        config.parse_bindings(mode, bindings)?;
+                    }
+                }
+                ("keys", _) => return Err(String::from("keys must be a table")),
+                _ => config.set_global(key, &toml_to_string(key, value)?)?,
+            }
+        }
+        Ok(config)
+    }
+
+    /// Takes itself, the name of a language and its section of the
+    /// config.
+    /// Returns the buffer options the section overrides, checking
+    /// that each of them is valid.
+    fn parse_overrides(
+        &self,
+        language: &str,
+        overrides: &Table,
+    ) -> Result<Vec<(String, String)>, String> {
+        let mut buffer_options = self.buffer_options.clone();
+        let mut parsed = Vec::with_capacity(overrides.len());
+        for (name, value) in overrides {
+            let value = toml_to_string(name, value)?;
+            if !buffer_options.set(name, &value)? {
+                return Err(format!(
+                    "language.{language}: {name} cannot be set per language"
+                ));
+            }
+            parsed.push((name.clone(), value));
+        }
+        Ok(parsed)
+    }
+
+    /// Takes itself, the name of a mode and its section of the config.
+    /// Adds the key bindings of the section to the keymap. Normal
+    /// mode bindings are added to Visual mode too, which shares
+    /// them by default.
+    fn parse_bindings(&mut self, mode_name: &str, bindings: &Table) -> Result<(), String> {
+        let mode = keymap::parse_mode(mode_name).map_err(|message| format!("keys: {message}"))?;
+        let modes: &[Mode] = if mode == Mode::Normal {
+            &[Mode::Normal, Mode::Visual]
+        } else {
+            &[mode]
+        };
+        for (keys, command) in bindings {
+            let Value::String(command) = command else {
+                return Err(format!("keys.{mode_name}.{keys} must be a command name"));
+            };
+            for &mode in modes {
+                self.keymap
+                    .bind(mode, keys, command)
+                    .map_err(|message| format!("keys.{mode_name}.{keys}: {message}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes itself and the language of a buffer.
+    /// Returns the buffer options for the language, with its
+    /// overrides applied to the global ones.
+    #[must_use]
+    pub fn buffer_options_for(&self, language: Option<Language>) -> BufferOptions {
+        let mut buffer_options = self.buffer_options.clone();
+        if let Some(overrides) = language.and_then(|language| self.languages.get(language.name())) {
+            for (name, value) in overrides {
+                // Overrides were checked when the config was parsed.
+                let _ = buffer_options.set(name, value);
+            }
+        }
+        buffer_options
+    }
+
+    /// Takes itself, the name of an option, its new value and the
+    /// options of the current buffer.
+    /// Sets the option. Buffer options are set both for the current
+    /// buffer and for buffers opened later.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if there is no such option or the
+    /// value does not suit it.
+    pub fn set(
+        &mut self,
+        name: &str,
+        value: &str,
+        buffer_options: &mut BufferOptions,
+    ) -> Result<(), String> {
+        // Options are only changed once their value is known to be
+        // valid, so an error leaves both sets of options as they were.
+        self.set_global(name, value)?;
+        buffer_options.set(name, value)?;
+        Ok(())
+    }
+
+    /// Takes itself, the name of an option and its new value.
+    /// Sets the option for the whole editor, or for buffers opened
+    /// later if it is a buffer option.
+    fn set_global(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if self.buffer_options.set(name, value)? || self.options.set(name, value)? {
+            Ok(())
+        } else {
+            Err(format!("Unknown option: {name}"))
+        }
+    }
+
+    /// Takes itself, the name of an option and the options of the
+    /// current buffer.
+    /// Returns the value of the option, if there is one by that name.
+    #[must_use]
+    pub fn get(&self, name: &str, buffer_options: &BufferOptions) -> Option<String> {
+        buffer_options.get(name).or_else(|| self.options.get(name))
+    }
+
+    /// Takes nothing.
+    /// Returns the names of every option.
+    #[must_use]
+    pub fn names() -> Vec<&'static str> {
+        let mut names: Vec<&str> = Options::NAMES
+            .into_iter()
+            .chain(BufferOptions::NAMES)
+            .collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// Takes nothing.
+/// Returns the directory Reflex reads its configuration from:
+/// `$XDG_CONFIG_HOME/reflex`, or `~/.config/reflex`.
+#[must_use]
+pub fn config_dir() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("reflex"))
+}
+
+/// Takes the name of an option.
+/// Returns a bool representing whether it is a switch, which `:set`
+/// turns on with `name` and off with `noname`.
+fn is_switch(name: &str) -> bool {
+    matches!(
+        name,
+        "expand_tabs"
+            | "detect_indent"
+            | "soft_wrap"
+            | "wrap_at_words"
+            | "alt_screen"
+            | "marker_column"
+            | "backup"
+    )
+}
+
+/// Takes the name of an option and its value in the config file.
+/// Returns the value as `:set` would be given it.
+fn toml_to_string(name: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Integer(_) | Value::Boolean(_) => Ok(value.to_string()),
+        _ => Err(format!("Invalid value for {name}: {value}")),
+    }
+}
+
+/// Takes the name of an option and its value.
+/// Returns the value as a number.
+fn parse_number(name: &str, value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {name}: {value}"))
+}
+
+/// Takes the name of an option and its value.
+/// Returns the value as a width, which must be at least 1.
+fn parse_width(name: &str, value: &str) -> Result<usize, String> {
+    match parse_number(name, value)? {
+        0 => Err(format!("{name} must be at least 1")),
+        width => Ok(width),
+    }
+}
+
+/// Takes the name of an option and its value.
+/// Returns the value as a bool.
+fn parse_switch(name: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "on" | "yes" => Ok(true),
+        "false" | "off" | "no" => Ok(false),
+        _ => Err(format!("Invalid value for {name}: {value}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymap::{parse_keys, Command, Lookup};
+
+    #[test]
+    fn normal_bindings_apply_in_visual_mode_unless_overridden() {
+        let config = Config::parse(
+            "[keys.visual]\nx = \"delete\"\n[keys.normal]\nx = \"yank\"\nq = \"undo\"\n",
+        )
+        .unwrap();
+        let lookup = |mode, keys| config.keymap.lookup(mode, &parse_keys(keys).unwrap());
+        assert!(matches!(
+            lookup(Mode::Normal, "x"),
+            Lookup::Command(Command::Yank)
+        ));
+        assert!(matches!(
+            lookup(Mode::Visual, "x"),
+            Lookup::Command(Command::Delete)
+        ));
+        assert!(matches!(
+            lookup(Mode::VisualLine, "q"),
+            Lookup::Command(Command::Undo)
+        ));
+    }
+
+    #[test]
+    fn invalid_values_leave_options_unchanged() {
+        let mut config = Config::default();
+        let mut buffer_options = BufferOptions::default();
+        assert!(config.set("tab_width", "0", &mut buffer_options).is_err());
+        assert!(config
+            .set("indent_width", "0", &mut buffer_options)
+            .is_err());
+        assert!(config.set("tab_width", "x", &mut buffer_options).is_err());
+        assert_eq!(config.buffer_options.tab_width, 4);
+        assert_eq!(buffer_options.tab_width, 4);
+        assert_eq!(buffer_options.indent_width, 4);
+        assert!(config.set("tab_width", "8", &mut buffer_options).is_ok());
+        assert_eq!(config.buffer_options.tab_width, 8);
+        assert_eq!(buffer_options.tab_width, 8);
+    }
+
+    #[test]
+    fn set_rejects_unknown_options() {
+        let mut buffer_options = BufferOptions::default();
+        let result = Config::default().set("nope", "1", &mut buffer_options);
+        assert_eq!(result, Err(String::from("Unknown option: nope")));
+        assert!(Config::parse("tab_width = 0").is_err());
+    }
+}
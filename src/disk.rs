@@ -0,0 +1,233 @@
+#![warn(clippy::all, clippy::pedantic)]
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Read},
+    path::Path,
+    time::SystemTime,
+};
+
+/// The size of the pieces files are read in, so that reading one
+/// never takes more memory than what it is read into.
+pub const CHUNK_LEN: usize = 64 << 10;
+
+/// Files larger than this are not hashed, and count as changed as
+/// soon as their modification time or size do.
+const HASH_MAX_LEN: u64 = 64 << 20;
+
+/// What a file looked like on disk when a buffer last read or
+/// wrote it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    /// The hash of the contents, unless they were too large to be
+    /// worth hashing.
+    hash: Option<u64>,
+}
+
+impl Stamp {
+    /// Takes the path of a file.
+    /// Builds a `Stamp` from the file as it is now.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        if metadata.len() > HASH_MAX_LEN {
+            Ok(Self {
+                modified: metadata.modified().ok(),
+                len: metadata.len(),
+                hash: None,
+            })
+        } else {
+            read_chunks(path, |_| true)
+        }
+    }
+
+    /// Takes itself.
+    /// Returns the size of the file in bytes.
+    #[must_use]
+    pub fn len(self) -> u64 {
+        self.len
+    }
+}
+
+/// How a file has changed on disk since its stamp was taken.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DiskChange {
+    Unchanged,
+    /// The contents are different, or the file was created.
+    Modified,
+    Deleted,
+}
+
+/// Takes the path of a file and a function to pass its contents
+/// to.
+/// Reads the file in pieces of `CHUNK_LEN` bytes, the last of
+/// which may be shorter, and passes them to the function until it
+/// returns false. Returns the stamp of the file, taken from the
+/// same open file so that they agree, which only covers the whole
+/// file if the function never returned false.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read.
+pub fn read_chunks(path: &Path, mut f: impl FnMut(&[u8]) -> bool) -> io::Result<Stamp> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let mut chunk = Vec::with_capacity(CHUNK_LEN);
+    let mut hash = FNV_OFFSET_BASIS;
+    loop {
+        chunk.clear();
+        if (&mut file).take(CHUNK_LEN as u64).read_to_end(&mut chunk)? == 0 {
+            break;
+        }
+        hash = fnv_hash(hash, &chunk);
+        if !f(&chunk) {
+            break;
+        }
+    }
+    Ok(Stamp {
+        modified: metadata.modified().ok(),
+        len: metadata.len(),
+        hash: (metadata.len() <= HASH_MAX_LEN).then_some(hash),
+    })
+}
+
+/// Takes the stamp of a file, or `None` if it did not exist, and
+/// the path of the file.
+/// Returns how the file has changed since the stamp was taken. The
+/// contents are only hashed when the modification time or size
+/// differ, and a file that was touched without being changed gets
+/// a fresh stamp. Files whose stamp has no hash count as modified
+/// as soon as either differs, and files that cannot be read count
+/// as unchanged.
+pub fn check(stamp: &mut Option<Stamp>, path: &Path) -> DiskChange {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            return if stamp.is_some() {
+                DiskChange::Deleted
+            } else {
+                DiskChange::Unchanged
+            };
+        }
+        Err(_) => return DiskChange::Unchanged,
+    };
+    let Some(old) = *stamp else {
+        return DiskChange::Modified;
+    };
+    if metadata.modified().ok() == old.modified && metadata.len() == old.len {
+        return DiskChange::Unchanged;
+    }
+    if old.hash.is_none() {
+        return DiskChange::Modified;
+    }
+    let Ok(new) = read_chunks(path, |_| true) else {
+        return DiskChange::Unchanged;
+    };
+    if new.len == old.len && new.hash == old.hash {
+        *stamp = Some(new);
+        DiskChange::Unchanged
+    } else {
+        DiskChange::Modified
+    }
+}
+
+/// The hash of no bytes at all, which `fnv_hash` starts from.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Takes the hash of the contents of a file so far and the next
+/// piece of them.
+/// Returns the 64-bit FNV-1a hash of the contents up to the end of
+/// the piece.
+fn fnv_hash(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempFile;
+    use std::time::Duration;
+
+    fn stamp(file: &TempFile) -> Stamp {
+        Stamp::read(&file.0).unwrap()
+    }
+
+    #[test]
+    fn untouched_file_is_unchanged() {
+        let file = TempFile::new("disk-untouched", "a\n");
+        let mut stamp = Some(stamp(&file));
+        assert!(check(&mut stamp, &file.0) == DiskChange::Unchanged);
+    }
+
+    #[test]
+    fn new_contents_are_modified() {
+        let file = TempFile::new("disk-modified", "a\n");
+        let mut stamp = Some(stamp(&file));
+        fs::write(&file.0, "b\n").unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(5));
+        assert!(check(&mut stamp, &file.0) == DiskChange::Modified);
+        fs::write(&file.0, "abc\n").unwrap();
+        assert!(check(&mut stamp, &file.0) == DiskChange::Modified);
+    }
+
+    #[test]
+    fn touched_file_gets_a_fresh_stamp() {
+        let file = TempFile::new("disk-touched", "a\n");
+        let mut stamp = Some(stamp(&file));
+        let modified = SystemTime::now() + Duration::from_secs(5);
+        file.set_modified(modified);
+        assert!(check(&mut stamp, &file.0) == DiskChange::Unchanged);
+        assert!(stamp.is_some_and(|stamp| stamp.modified == Some(modified)));
+    }
+
+    #[test]
+    fn unhashed_file_is_modified_when_touched() {
+        let file = TempFile::new("disk-unhashed", "a\n");
+        let mut stamp = Some(Stamp {
+            hash: None,
+            ..stamp(&file)
+        });
+        assert!(check(&mut stamp, &file.0) == DiskChange::Unchanged);
+        file.set_modified(SystemTime::now() + Duration::from_secs(5));
+        assert!(check(&mut stamp, &file.0) == DiskChange::Modified);
+    }
+
+    #[test]
+    fn deleted_and_created_files() {
+        let file = TempFile::new("disk-deleted", "a\n");
+        let mut stamp = Some(stamp(&file));
+        fs::remove_file(&file.0).unwrap();
+        assert!(check(&mut stamp, &file.0) == DiskChange::Deleted);
+        let mut stamp = None;
+        assert!(check(&mut stamp, &file.0) == DiskChange::Unchanged);
+        fs::write(&file.0, "b\n").unwrap();
+        assert!(check(&mut stamp, &file.0) == DiskChange::Modified);
+    }
+
+    #[test]
+    fn files_are_read_in_whole_pieces() {
+        let file = TempFile::new("disk-pieces", "a".repeat(2 * CHUNK_LEN + 1));
+        let mut lens = Vec::new();
+        let read = read_chunks(&file.0, |bytes| {
+            lens.push(bytes.len());
+            true
+        })
+        .unwrap();
+        assert_eq!(lens, [CHUNK_LEN, CHUNK_LEN, 1]);
+        let contents = fs::read(&file.0).unwrap();
+        assert_eq!(read.hash, Some(fnv_hash(FNV_OFFSET_BASIS, &contents)));
+        let mut lens = Vec::new();
+        read_chunks(&file.0, |bytes| {
+            lens.push(bytes.len());
+            false
+        })
+        .unwrap();
+        assert_eq!(lens, [CHUNK_LEN]);
+    }
+}
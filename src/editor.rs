@@ -1,18 +1,54 @@
 #![warn(clippy::all, clippy::pedantic)]
 
-use crate::{FileBuffer, Position, Terminal};
-use crossterm::{event::{KeyCode, KeyEventKind}, style::Stylize};
-use ropey::RopeSlice;
-use std::{cmp, env, fmt};
+use crate::{
+    command::{self, LineContext, Substitution, WriteCommand},
+    config::{Config, Setting},
+    disk::DiskChange,
+    filebuffer::ScreenLine,
+    grapheme::{self, Wrap},
+    highlight::Scope,
+    keymap::{Command, Key, Lookup},
+    movement::Motion,
+    register::{Registers, UNNAMED_REGISTER},
+    save,
+    terminal::Input,
+    theme::{self, ColorSupport, Theme},
+    window::{self, Layout, Rect, Side, SplitDirection, Window},
+    FileBuffer, Frame, Position, Selection, Size, Terminal, View,
+};
+use crossterm::{
+    event::{KeyCode, KeyEventKind, KeyModifiers},
+    style::ContentStyle,
+};
+use regex::Regex;
+use std::{
+    cmp, fmt,
+    io::ErrorKind,
+    mem,
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+    slice,
+    time::Duration,
+};
+use unicode_width::UnicodeWidthStr;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-#[derive(Default)]
+/// How long the editor waits for a key before checking whether the
+/// open files have changed on disk.
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the editor waits for the next key of a longer binding
+/// before running the command bound to the keys typed so far.
+const KEY_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Mode {
     #[default]
     Normal,
     Insert,
     Visual,
+    VisualLine,
     Command,
 }
 
@@ -22,13 +58,53 @@ impl fmt::Display for Mode {
             Mode::Normal => write!(f, "NORMAL"),
             Mode::Insert => write!(f, "INSERT"),
             Mode::Visual => write!(f, "VISUAL"),
+            Mode::VisualLine => write!(f, "VISUAL LINE"),
             Mode::Command => write!(f, "COMMAND"),
         }
     }
 }
 
+impl Mode {
+    /// Takes itself.
+    /// Returns the name a theme styles the mode's status bar by.
+    fn theme_key(self) -> &'static str {
+        match self {
+            Mode::Normal => "normal",
+            Mode::Insert => "insert",
+            Mode::Visual => "visual",
+            Mode::VisualLine => "visual.line",
+            Mode::Command => "command",
+        }
+    }
+}
+
+/// The kind of input the commandline is reading.
+#[derive(Default, Clone, Copy)]
+enum Prompt {
+    #[default]
+    Command,
+    SearchForward,
+    SearchBackward,
+    Select,
+}
+
+impl fmt::Display for Prompt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Prompt::Command => write!(f, ":"),
+            Prompt::SearchForward => write!(f, "/"),
+            Prompt::SearchBackward => write!(f, "?"),
+            Prompt::Select => write!(f, "select:"),
+        }
+    }
+}
+
 struct CommandLine {
+    prompt: Prompt,
+    return_mode: Mode,
     command: String,
+    /// The byte index of the cursor in `command`, always on a char
+    /// boundary.
     cursor_pos: usize,
     command_history: Vec<String>,
 }
@@ -38,6 +114,8 @@ impl Default for CommandLine {
     /// Builds a `CommandLine` to store commandline state.
     fn default() -> Self {
         Self {
+            prompt: Prompt::default(),
+            return_mode: Mode::default(),
             command: String::new(),
             cursor_pos: 0,
             command_history: Vec::new(),
@@ -45,47 +123,127 @@ impl Default for CommandLine {
     }
 }
 
+impl CommandLine {
+    /// Takes itself.
+    /// Returns the byte index of the char before the cursor, or the
+    /// start of the commandline if the cursor is already there.
+    fn prev_char_pos(&self) -> usize {
+        self.command[..self.cursor_pos]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(idx, _)| idx)
+    }
+
+    /// Takes itself.
+    /// Returns the byte index just past the char under the cursor,
+    /// or the end of the commandline if the cursor is already there.
+    fn next_char_pos(&self) -> usize {
+        self.command[self.cursor_pos..]
+            .chars()
+            .next()
+            .map_or(self.cursor_pos, |c| self.cursor_pos + c.len_utf8())
+    }
+}
+
+/// How a span of text in a row is highlighted.
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum CellStyle {
+    Plain,
+    Selected,
+    PrimarySelected,
+    SearchMatch,
+}
+
 pub struct Editor {
     should_quit: bool,
     terminal: Terminal,
     file_buffers: Vec<FileBuffer>,
     current_file_buffer_idx: usize,
+    windows: Vec<Window>,
+    layout: Layout,
+    current_window_idx: usize,
     mode: Mode,
     command_line: CommandLine,
+    pending_keys: Vec<Key>,
+    awaiting_char: Option<Command>,
+    registers: Registers,
+    selected_register: Option<char>,
+    last_search: Option<Regex>,
+    last_search_backward: bool,
+    message: Option<String>,
+    config: Config,
+    theme: Theme,
 }
 
-impl Default for Editor {
-    /// Takes nothing.
+impl Editor {
+    /// Takes the `FileBuffer`s to edit.
     /// Builds an `Editor` to store program state.
-    fn default() -> Self {
-        let args: Vec<String> = env::args().collect();
-        let default_buffer = if args.len() > 1 {
-            let file_name = &args[1];
-            let wrapped_doc = FileBuffer::open(file_name);
-            if let Ok(document) = wrapped_doc {
-                document
-            } else {
-                FileBuffer::default()
-            }
-        } else {
-            FileBuffer::default()
+    #[must_use]
+    pub fn new(file_buffers: Vec<FileBuffer>) -> Self {
+        let (config, config_error) = match Config::load() {
+            Ok(config) => (config, None),
+            Err(message) => (Config::default(), Some(message)),
         };
-        Self {
+        let (theme, theme_error) = match Theme::load(&config.options.theme, ColorSupport::detect())
+        {
+            Ok(theme) => (theme, None),
+            Err(message) => (Theme::default(), Some(message)),
+        };
+        let mut editor = Self {
             should_quit: false,
-            terminal: Terminal::new().expect("Failed to initialize terminal"),
-            file_buffers: vec![default_buffer],
+            terminal: Terminal::new(config.options.alt_screen)
+                .expect("Failed to initialize terminal"),
+            file_buffers,
             current_file_buffer_idx: 0,
+            windows: Vec::new(),
+            layout: Layout::Window(0),
+            current_window_idx: 0,
             mode: Mode::default(),
             command_line: CommandLine::default(),
+            pending_keys: Vec::new(),
+            awaiting_char: None,
+            registers: Registers::default(),
+            selected_register: None,
+            last_search: None,
+            last_search_backward: false,
+            message: config_error.or(theme_error),
+            config,
+            theme,
+        };
+        if editor.file_buffers.is_empty() {
+            editor.file_buffers.push(FileBuffer::default());
         }
+        editor.windows.push(Window {
+            file_buffer_idx: 0,
+            view: editor.file_buffers[0].view(),
+        });
+        let size = editor.window_size();
+        for file_buffer_idx in 0..editor.file_buffers.len() {
+            let file_buffer = &editor.file_buffers[file_buffer_idx];
+            let options = editor.config.buffer_options_for(file_buffer.language());
+            let gutter_width = editor.gutter_width(file_buffer, usize::from(size.width));
+            editor.file_buffers[file_buffer_idx].set_options(options);
+            let file_buffer = &editor.file_buffers[file_buffer_idx];
+            if editor.message.is_none() {
+                editor.message = file_buffer.open_warning();
+            }
+            let text_width = usize::from(size.width).saturating_sub(gutter_width);
+            let wrap = editor.wrap(file_buffer, text_width);
+            editor.file_buffers[file_buffer_idx].shift_viewport(
+                &size,
+                gutter_width,
+                editor.config.options.scrolloff,
+                wrap,
+            );
+        }
+        editor
     }
-}
 
-impl Editor {
     /// Takes itself.
     /// Runs the editor.
     pub fn run(&mut self) {
         loop {
+            self.update_highlights();
             if let Err(error) = self.refresh_screen() {
                 die(&error);
             }
@@ -95,19 +253,39 @@ impl Editor {
             if let Err(error) = self.process_keypress() {
                 die(&error);
             }
+            self.map_views();
         }
     }
 
     /// Takes itself.
-    /// Redraws the screen.
+    /// Moves the views that windows other than the current one keep
+    /// of each buffer along with the edits made to the buffer, so that
+    /// they stay on the same text.
+    fn map_views(&mut self) {
+        for (file_buffer_idx, file_buffer) in self.file_buffers.iter_mut().enumerate() {
+            file_buffer.map_views(
+                self.windows
+                    .iter_mut()
+                    .enumerate()
+                    .filter(|(window_idx, window)| {
+                        *window_idx != self.current_window_idx
+                            && window.file_buffer_idx == file_buffer_idx
+                    })
+                    .map(|(_, window)| &mut window.view),
+            );
+        }
+    }
+
+    /// Takes itself.
+    /// Redraws the screen, writing only what changed since the last
+    /// time it was drawn.
     ///
     /// # Errors
     ///
-    /// Returns an error if the `Terminal` cannot flush stdout.
-    fn refresh_screen(&self) -> Result<(), std::io::Error> {
-        Terminal::cursor_hide();
-        Terminal::cursor_position(&Position::default());
+    /// Returns an error if the `Terminal` cannot write to stdout.
+    fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
         if self.should_quit {
+            Terminal::cursor_position(&Position::default());
             Terminal::cursor_show();
             Terminal::clear_screen();
             if self.terminal.alt_screen {
@@ -117,253 +295,840 @@ impl Editor {
                 Terminal::exit_raw_mode()?;
             }
             println!("Goodbye.\r");
-        } else {
-            self.draw_rows();
-            self.draw_cursors();
-            let height = match self.mode {
-                Mode::Command => self.terminal.size().height.saturating_sub(1),
-                _ => self.terminal.size().height,
-            };
-            Terminal::cursor_position(&Position {
-                x: 0,
+            return Terminal::flush();
+        }
+        let size = self.terminal.size();
+        let mut frame = Frame::new(
+            usize::from(size.width),
+            usize::from(size.height).saturating_add(1),
+        );
+        let area = self.layout_area();
+        let (windows, separators) = self.layout.rects(area);
+        for (window_idx, rect) in &windows {
+            self.draw_window(&mut frame, *window_idx, *rect);
+        }
+        for separator in &separators {
+            self.draw_separator(&mut frame, *separator);
+        }
+        let bottom_row = usize::from(area.height);
+        frame.move_to(0, bottom_row);
+        if let Mode::Command = self.mode {
+            self.draw_command_line(&mut frame);
+            frame.cursor = Some(Position {
+                x: self.command_line.prompt.to_string().width().saturating_add(
+                    self.command_line.command[..self.command_line.cursor_pos].width(),
+                ),
                 x_preferred: 0,
-                y: height as usize,
+                y: bottom_row,
             });
-            self.draw_status_bar();
-            if let Mode::Command = self.mode {
-                self.draw_command_line();
-                Terminal::cursor_position(&Position {
-                    x: self.command_line.cursor_pos.saturating_add(1),
-                    x_preferred: 0,
-                    y: self.terminal.size().height.saturating_add(1) as usize,
-                });
-            } else {
+        } else {
+            let style = self.theme.get("ui.commandline");
+            let width = usize::from(area.width);
+            match &self.message {
+                Some(message) => {
+                    for (i, line) in message.lines().enumerate() {
+                        frame.move_to(0, bottom_row.saturating_add(i));
+                        frame.print(&format!("{line:<width$}"), style);
+                    }
+                }
+                None => frame.print(&" ".repeat(width), style),
+            }
+            if let (Mode::Insert, Some(rect)) =
+                (self.mode, self.layout.rect(self.current_window_idx, area))
+            {
                 let file_buffer = &self.file_buffers[self.current_file_buffer_idx];
-                Terminal::cursor_position(&file_buffer.get_primary_selection_cursor_pos());
+                let selection = &file_buffer.selections[file_buffer.primary_selection_idx];
+                let size = self.window_size();
+                let gutter_width = self.gutter_width(file_buffer, usize::from(size.width));
+                let text_width = usize::from(size.width).saturating_sub(gutter_width);
+                let wrap = self.wrap(file_buffer, text_width);
+                frame.cursor = file_buffer
+                    .get_screen_cursor_pos(&selection.cursor, &size, gutter_width, wrap)
+                    .map(|cursor| Position {
+                        x: cursor.x.saturating_add(usize::from(rect.x)),
+                        x_preferred: 0,
+                        y: cursor.y.saturating_add(usize::from(rect.y)),
+                    });
             }
         }
-        match self.mode {
-            Mode::Insert | Mode::Command => Terminal::cursor_show(),
-            _ => (),
+        self.terminal.draw(frame)
+    }
+
+    /// Takes itself.
+    /// Brings the highlighting of every visible buffer up to date
+    /// with the rows its windows show.
+    fn update_highlights(&mut self) {
+        let (rects, _) = self.layout.rects(self.layout_area());
+        for (window_idx, rect) in rects {
+            let (file_buffer_idx, offset) = if window_idx == self.current_window_idx {
+                let file_buffer = &self.file_buffers[self.current_file_buffer_idx];
+                (self.current_file_buffer_idx, file_buffer.offset)
+            } else {
+                let window = &self.windows[window_idx];
+                (window.file_buffer_idx, window.view.offset)
+            };
+            self.file_buffers[file_buffer_idx]
+                .update_highlights(offset.y.saturating_add(usize::from(rect.height)));
+        }
+    }
+
+    /// Takes itself.
+    /// Returns the area of the screen shared by the windows, leaving
+    /// room at the bottom for the commandline or a message.
+    fn layout_area(&self) -> Rect {
+        let size = self.terminal.size();
+        let message_lines = match (&self.message, self.mode) {
+            (Some(message), mode) if mode != Mode::Command => message.lines().count(),
+            _ => 0,
+        };
+        let bottom_lines = u16::try_from(cmp::max(message_lines, 1)).unwrap_or(u16::MAX);
+        Rect {
+            x: 0,
+            y: 0,
+            width: size.width,
+            height: size.height.saturating_add(1).saturating_sub(bottom_lines),
+        }
+    }
+
+    /// Takes itself.
+    /// Returns the size of the text area of the current window.
+    fn window_size(&self) -> Size {
+        let rect = self
+            .layout
+            .rect(self.current_window_idx, self.layout_area())
+            .unwrap_or_default();
+        Size {
+            width: rect.width,
+            height: rect.height.saturating_sub(1),
         }
-        Terminal::flush()
     }
 
     /// Takes itself.
+    /// Scrolls the current window so that its primary selection
+    /// is in view.
+    fn shift_viewport(&mut self) {
+        let size = self.window_size();
+        let file_buffer = &self.file_buffers[self.current_file_buffer_idx];
+        let gutter_width = self.gutter_width(file_buffer, usize::from(size.width));
+        let text_width = usize::from(size.width).saturating_sub(gutter_width);
+        let wrap = self.wrap(file_buffer, text_width);
+        self.file_buffers[self.current_file_buffer_idx].shift_viewport(
+            &size,
+            gutter_width,
+            self.config.options.scrolloff,
+            wrap,
+        );
+    }
+
+    /// Takes itself, the `FileBuffer` of a window and the width of
+    /// its text area.
+    /// Returns how the rows of the buffer are wrapped, if soft wrap
+    /// is on for it.
+    fn wrap(&self, file_buffer: &FileBuffer, text_width: usize) -> Option<Wrap> {
+        file_buffer.options.soft_wrap.then(|| Wrap {
+            width: text_width,
+            indicator_width: self.config.options.wrap_indicator.width(),
+            at_words: file_buffer.options.wrap_at_words,
+        })
+    }
+
+    /// Takes itself, the `FileBuffer` of a window and the width of
+    /// the window.
+    /// Returns the width of the gutter of the window, leaving at
+    /// least one column for the text.
+    fn gutter_width(&self, file_buffer: &FileBuffer, width: usize) -> usize {
+        cmp::min(
+            self.config.options.gutter().width(file_buffer.len()),
+            width.saturating_sub(1),
+        )
+    }
+
+    /// Takes the `Frame` to draw into, the width of a window and the
+    /// style to draw in.
     /// Draws the welcome message.
-    fn draw_welcome_msg(&self) {
+    fn draw_welcome_msg(frame: &mut Frame, width: usize, style: ContentStyle) {
         let mut welcome_msg = format!("REFLEX -- v{VERSION}");
-        let width = self.terminal.size().width as usize;
         let len = welcome_msg.len();
         let padding = width.saturating_sub(len) / 2;
         let spaces = " ".repeat(padding.saturating_sub(1));
         welcome_msg = format!("~{spaces}{welcome_msg}");
         welcome_msg.truncate(width);
-        println!("{welcome_msg}\r");
+        frame.print(&format!("{welcome_msg:<width$}"), style);
     }
 
-    /// Takes itself and a `RopeSlice`.
-    /// Draws a single row of the editor.
-    pub fn draw_row(&self, row: RopeSlice) {
-        let file_buffer = &self.file_buffers[self.current_file_buffer_idx];
-        let start = file_buffer.offset.x;
-        let width = self.terminal.size().width as usize;
-        let end = file_buffer.offset.x + width;
-        let mut row_len = row.len_bytes();
-        if row.slice(row_len.saturating_sub(1)..row_len).eq("\n") {
-            row_len = row_len.saturating_sub(1);
+    /// Takes itself, the `Frame` to draw into, the `FileBuffer` and
+    /// `View` of a window, whether selections cover whole lines, the
+    /// `ScreenLine` to draw and the width to fill.
+    /// Draws a single row of the window, highlighting any
+    /// selected text.
+    pub fn draw_row(
+        &self,
+        frame: &mut Frame,
+        file_buffer: &FileBuffer,
+        view: &View,
+        linewise: bool,
+        screen_line: &ScreenLine,
+        width: usize,
+    ) {
+        let row_idx = screen_line.row_idx;
+        let Some(row) = file_buffer.row(row_idx) else {
+            return;
+        };
+        let primary_selection = view
+            .selections
+            .get(view.primary_selection_idx)
+            .map(slice::from_ref)
+            .unwrap_or_default();
+        let primary = file_buffer.selected_columns(primary_selection, row_idx, linewise);
+        let selected = file_buffer.selected_columns(&view.selections, row_idx, linewise);
+        let matches = self
+            .last_search
+            .as_ref()
+            .map_or_else(Vec::new, |regex| file_buffer.search_columns(regex, row_idx));
+        let spans = file_buffer.highlight_spans(row_idx);
+        let style_at = |column: usize| {
+            let cell_style = if primary.iter().any(|range| range.contains(&column)) {
+                CellStyle::PrimarySelected
+            } else if selected.iter().any(|range| range.contains(&column)) {
+                CellStyle::Selected
+            } else if matches.iter().any(|range| range.contains(&column)) {
+                CellStyle::SearchMatch
+            } else {
+                CellStyle::Plain
+            };
+            let scope = spans
+                .iter()
+                .find(|span| span.range.contains(&column))
+                .map(|span| span.scope);
+            (cell_style, scope)
+        };
+        let text_style = self.theme.get("ui.text");
+        let content_style = |(cell_style, scope): (CellStyle, Option<Scope>)| {
+            let content_style = scope.map_or(text_style, |scope| {
+                theme::overlay(text_style, self.theme.get(scope.name()))
+            });
+            let cell_style_name = match cell_style {
+                CellStyle::Plain => None,
+                CellStyle::Selected => Some("ui.selection"),
+                CellStyle::PrimarySelected => Some("ui.selection.primary"),
+                CellStyle::SearchMatch => Some("ui.search"),
+            };
+            cell_style_name.map_or(content_style, |name| {
+                theme::overlay(content_style, self.theme.get(name))
+            })
+        };
+        let Range { start, end } = screen_line.columns.clone();
+        let mut column = start;
+        let mut segment = String::new();
+        let mut segment_style = None;
+        for grapheme in grapheme::graphemes_in(row, file_buffer.options.tab_width, start..end) {
+            if grapheme.line_break {
+                break;
+            }
+            let grapheme_end = grapheme.column + grapheme.width;
+            // Wide graphemes cut off by either edge of the window are
+            // drawn as spaces.
+            let text = if grapheme.column < start || grapheme_end > end {
+                " ".repeat(cmp::min(grapheme_end, end) - cmp::max(grapheme.column, start))
+            } else {
+                grapheme.text
+            };
+            let style = style_at(grapheme.start);
+            if let Some(segment_style) = segment_style.filter(|&previous| previous != style) {
+                frame.print(&segment, content_style(segment_style));
+                segment.clear();
+            }
+            segment_style = Some(style);
+            segment.push_str(&text);
+            column = grapheme_end;
+        }
+        if let Some(style) = segment_style {
+            frame.print(&segment, content_style(style));
         }
-        let end = cmp::min(end, row_len);
-        let start = cmp::min(start, end);
-        let row = row.slice(start..end).to_string();
-        println!("{row}\r");
+        let drawn = cmp::min(column, end).saturating_sub(start);
+        frame.print(&" ".repeat(width.saturating_sub(drawn)), text_style);
     }
 
-    /// Takes itself.
-    /// Draws all of the text rows of the editor.
-    fn draw_rows(&self) {
-        let file_buffer = &self.file_buffers[self.current_file_buffer_idx];
-        let height = match self.mode {
-            Mode::Command => self.terminal.size().height.saturating_sub(1),
-            _ => self.terminal.size().height,
+    /// Takes itself, the `Frame` to draw into, the index of a window
+    /// and its area.
+    /// Draws the text rows, status bar and cursors of the window.
+    fn draw_window(&self, frame: &mut Frame, window_idx: usize, rect: Rect) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        let focused = window_idx == self.current_window_idx;
+        let (file_buffer_idx, view) = if focused {
+            (
+                self.current_file_buffer_idx,
+                self.file_buffers[self.current_file_buffer_idx].view(),
+            )
+        } else {
+            let window = &self.windows[window_idx];
+            (window.file_buffer_idx, window.view.clone())
         };
-        for terminal_row in 0..height {
-            Terminal::clear_current_line();
-            if let Some(row) = file_buffer.row(terminal_row as usize + file_buffer.offset.y) {
-                self.draw_row(row);
-            } else if self.file_buffers[self.current_file_buffer_idx].buffer_is_empty
-                && terminal_row == height / 3
-            {
-                self.draw_welcome_msg();
+        let file_buffer = &self.file_buffers[file_buffer_idx];
+        let width = usize::from(rect.width);
+        let height = usize::from(rect.height.saturating_sub(1));
+        let linewise = focused && self.mode == Mode::VisualLine;
+        let gutter_width = self.gutter_width(file_buffer, width);
+        let cursor_row = view
+            .selections
+            .get(view.primary_selection_idx)
+            .map_or(0, |selection| selection.cursor.y);
+        let text_style = self.theme.get("ui.text");
+        let nontext_style = theme::overlay(text_style, self.theme.get("ui.nontext"));
+        let wrap_style = theme::overlay(text_style, self.theme.get("ui.nontext.wrap"));
+        let text_width = width.saturating_sub(gutter_width);
+        let wrap = self.wrap(file_buffer, text_width);
+        let indicator: String = self
+            .config
+            .options
+            .wrap_indicator
+            .chars()
+            .take(text_width.saturating_sub(1))
+            .collect();
+        let screen_lines = file_buffer.screen_lines(&view, wrap, text_width, height);
+        for terminal_row in 0..=height {
+            frame.move_to(
+                usize::from(rect.x),
+                usize::from(rect.y).saturating_add(terminal_row),
+            );
+            if terminal_row == height {
+                self.draw_status_bar(frame, file_buffer_idx, focused, width);
+            } else if let Some(screen_line) = screen_lines.get(terminal_row) {
+                let row_idx = screen_line.row_idx;
+                let mut text_width = text_width;
+                if screen_line.continuation {
+                    frame.print(&" ".repeat(gutter_width), text_style);
+                    frame.print(&indicator, wrap_style);
+                    text_width = text_width.saturating_sub(indicator.width());
+                } else {
+                    self.draw_gutter(frame, row_idx, cursor_row, gutter_width);
+                }
+                self.draw_row(frame, file_buffer, &view, linewise, screen_line, text_width);
+            } else if file_buffer.buffer_is_empty && terminal_row == height / 3 {
+                Self::draw_welcome_msg(frame, width, nontext_style);
             } else {
-                println!("~\r");
+                frame.print(&format!("{:<width$}", "~"), nontext_style);
             }
         }
+        if focused {
+            self.draw_cursors(frame, rect);
+        }
+    }
+
+    /// Takes itself, the `Frame` to draw into, the index of a row, the
+    /// row of the primary cursor and the width of the gutter.
+    /// Draws the marker column and line number of the row.
+    fn draw_gutter(&self, frame: &mut Frame, row_idx: usize, cursor_row: usize, width: usize) {
+        let gutter = self.config.options.gutter();
+        let text_style = self.theme.get("ui.text");
+        if gutter.marker_column && width > 0 {
+            let style = theme::overlay(text_style, self.theme.get("ui.gutter"));
+            frame.print(" ", style);
+        }
+        let name = if row_idx == cursor_row {
+            "ui.linenr.selected"
+        } else {
+            "ui.linenr"
+        };
+        let style = theme::overlay(text_style, self.theme.get(name));
+        frame.print(&gutter.number(row_idx, cursor_row, width), style);
+    }
+
+    /// Takes itself, the `Frame` to draw into and the area of a
+    /// separator.
+    /// Draws the line between two side-by-side windows.
+    fn draw_separator(&self, frame: &mut Frame, rect: Rect) {
+        let style = theme::overlay(self.theme.get("ui.text"), self.theme.get("ui.separator"));
+        for terminal_row in 0..rect.height {
+            frame.move_to(
+                usize::from(rect.x),
+                usize::from(rect.y.saturating_add(terminal_row)),
+            );
+            frame.print("│", style);
+        }
     }
 
     /// Takes itself.
-    /// Forwards all keystrokes to the appropriate functions.
+    /// Reads a keystroke and forwards it to the keymap. If the keys
+    /// typed so far are bound but also begin a longer binding, their
+    /// command runs when no key comes within `KEY_TIMEOUT`.
     ///
     /// # Errors
     ///
     /// Returns an error if the `Terminal` cannot read the event.
     fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let key_event = Terminal::read_event()?;
+        let key_event = loop {
+            let timed_command = match self.config.keymap.lookup(self.mode, &self.pending_keys) {
+                Lookup::Pending(Some(command)) if self.awaiting_char.is_none() => {
+                    Some(command.clone())
+                }
+                _ => None,
+            };
+            let timeout = if timed_command.is_some() {
+                KEY_TIMEOUT
+            } else {
+                DISK_CHECK_INTERVAL
+            };
+            match self.terminal.poll_event(timeout)? {
+                Some(Input::Key(key_event)) => break key_event,
+                Some(Input::Resize) => {
+                    self.shift_viewport();
+                    return Ok(());
+                }
+                None => (),
+            }
+            if let Some(command) = timed_command {
+                return self.execute_key_command(command);
+            }
+            if self.check_disk() {
+                return Ok(());
+            }
+        };
         if let KeyEventKind::Release = key_event.kind {
             return Ok(());
         }
-        match key_event.code {
-            KeyCode::Esc => {
-                self.mode = Mode::Normal;
-                self.command_line.command = String::new();
+        self.message = None;
+        self.process_key(Key::from(key_event))
+    }
+
+    /// Takes itself and a `Key`.
+    /// Adds the key to the pending keys and runs the command they
+    /// are bound to in the current mode, if they are complete.
+    /// Keys that are not bound type their char in Insert mode and
+    /// on the commandline.
+    fn process_key(&mut self, key: Key) -> Result<(), std::io::Error> {
+        if let Some(command) = self.awaiting_char.take() {
+            self.pending_keys.clear();
+            if let (KeyCode::Char(c), false) =
+                (key.code, key.modifiers.contains(KeyModifiers::CONTROL))
+            {
+                self.execute_with_char(&command, c);
             }
-            KeyCode::Char(c) => match self.mode {
-                Mode::Normal | Mode::Visual => match c {
-                    ':' => self.mode = Mode::Command,
-                    'i' => self.mode = Mode::Insert,
-                    _ => (),
-                },
-                Mode::Insert => {
-                    self.file_buffers[self.current_file_buffer_idx].insert(c);
-                    self.file_buffers[self.current_file_buffer_idx]
-                        .shift_viewport(self.terminal.size());
-                }
-                Mode::Command => {
-                    self.command_line
-                        .command
-                        .insert(self.command_line.cursor_pos, c);
-                    self.command_line.cursor_pos = self.command_line.cursor_pos.saturating_add(1);
-                }
-            },
-            KeyCode::Enter => match self.mode {
-                Mode::Insert => {
-                    self.file_buffers[self.current_file_buffer_idx].insert('\n');
-                    self.file_buffers[self.current_file_buffer_idx]
-                        .shift_viewport(self.terminal.size());
-                }
-                Mode::Command => self.execute_command()?,
-                _ => (),
-            },
-            KeyCode::Delete => match self.mode {
-                Mode::Insert => {
-                    self.file_buffers[self.current_file_buffer_idx].delete(false);
-                    self.file_buffers[self.current_file_buffer_idx]
-                        .shift_viewport(self.terminal.size());
+            return Ok(());
+        }
+        self.pending_keys.push(key);
+        let command = match self.config.keymap.lookup(self.mode, &self.pending_keys) {
+            Lookup::Command(command) => command.clone(),
+            Lookup::Pending(_) => return Ok(()),
+            Lookup::Unbound => {
+                let mut keys = mem::take(&mut self.pending_keys);
+                let Some(last) = keys.pop() else {
+                    return Ok(());
+                };
+                if keys.is_empty() {
+                    self.type_key(last);
+                    return Ok(());
                 }
-                Mode::Command => {
-                    if self.command_line.cursor_pos < self.command_line.command.len() {
-                        self.command_line
-                            .command
-                            .remove(self.command_line.cursor_pos);
+                // The earlier keys were waiting for a longer binding
+                // that the last key does not continue.
+                match self.config.keymap.lookup(self.mode, &keys) {
+                    Lookup::Command(command) | Lookup::Pending(Some(command)) => {
+                        let command = command.clone();
+                        self.pending_keys = keys;
+                        self.execute_key_command(command)?;
                     }
-                }
-                _ => (),
-            },
-            KeyCode::Backspace => match self.mode {
-                Mode::Insert => {
-                    self.file_buffers[self.current_file_buffer_idx].delete(true);
-                    self.file_buffers[self.current_file_buffer_idx]
-                        .shift_viewport(self.terminal.size());
-                }
-                Mode::Command => {
-                    if self.command_line.cursor_pos > 0 {
-                        self.command_line
-                            .command
-                            .remove(self.command_line.cursor_pos.saturating_sub(1));
-                        self.command_line.cursor_pos =
-                            self.command_line.cursor_pos.saturating_sub(1);
+                    _ => {
+                        for key in keys {
+                            self.type_key(key);
+                        }
                     }
                 }
-                _ => (),
-            },
-            KeyCode::Left => {
-                if let Mode::Command = self.mode {
-                    self.command_line.cursor_pos = self.command_line.cursor_pos.saturating_sub(1);
+                return self.process_key(last);
+            }
+        };
+        self.execute_key_command(command)
+    }
+
+    /// Takes itself and a `Key` that is not bound to a command.
+    /// Types the char of the key in Insert mode or on the commandline.
+    fn type_key(&mut self, key: Key) {
+        let KeyCode::Char(c) = key.code else {
+            return;
+        };
+        if key
+            .modifiers
+            .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+        {
+            return;
+        }
+        match self.mode {
+            Mode::Insert => {
+                self.file_buffers[self.current_file_buffer_idx].insert(c);
+                self.shift_viewport();
+            }
+            Mode::Command => {
+                self.command_line
+                    .command
+                    .insert(self.command_line.cursor_pos, c);
+                self.command_line.cursor_pos = self.command_line.next_char_pos();
+            }
+            _ => (),
+        }
+    }
+
+    /// Takes itself and the `Command` the pending keys are bound to.
+    /// Runs the command. Commands that need a char leave the keys
+    /// pending until the char is typed.
+    fn execute_key_command(&mut self, command: Command) -> Result<(), std::io::Error> {
+        if let Command::SelectRegister | Command::Replace = command {
+            self.awaiting_char = Some(command);
+            return Ok(());
+        }
+        self.pending_keys.clear();
+        match command {
+            Command::NormalMode => self.enter_normal_mode(),
+            Command::InsertMode => self.mode = Mode::Insert,
+            Command::VisualMode => self.toggle_visual_mode(Mode::Visual),
+            Command::VisualLineMode => self.toggle_visual_mode(Mode::VisualLine),
+            Command::OpenCommandline => self.open_prompt(Prompt::Command),
+            Command::SearchForward => self.open_prompt(Prompt::SearchForward),
+            Command::SearchBackward => self.open_prompt(Prompt::SearchBackward),
+            Command::SelectRegex => self.open_prompt(Prompt::Select),
+            Command::SearchNext => self.search_next(false),
+            Command::SearchPrev => self.search_next(true),
+            Command::Undo => {
+                self.file_buffers[self.current_file_buffer_idx].undo();
+                self.shift_viewport();
+            }
+            Command::Redo => {
+                self.file_buffers[self.current_file_buffer_idx].redo();
+                self.shift_viewport();
+            }
+            Command::Delete => self.delete_selections(false),
+            Command::Change => self.delete_selections(true),
+            Command::Yank => {
+                self.yank_selections();
+                self.leave_visual_mode();
+            }
+            Command::PasteAfter => self.paste(false),
+            Command::PasteBefore => self.paste(true),
+            Command::MoveLeft | Command::MoveRight if self.mode == Mode::Command => {
+                self.command_line.cursor_pos = if let Command::MoveLeft = command {
+                    self.command_line.prev_char_pos()
                 } else {
-                    self.file_buffers[self.current_file_buffer_idx].move_cursors(key_event.code);
-                    self.file_buffers[self.current_file_buffer_idx]
-                        .shift_viewport(self.terminal.size());
+                    self.command_line.next_char_pos()
+                };
+            }
+            Command::MoveUp | Command::MoveDown if self.mode == Mode::Command => (),
+            Command::MoveLeft => self.move_cursors(KeyCode::Left),
+            Command::MoveRight => self.move_cursors(KeyCode::Right),
+            Command::MoveUp => self.move_cursors(KeyCode::Up),
+            Command::MoveDown => self.move_cursors(KeyCode::Down),
+            Command::Move(motion) => self.move_selections(motion),
+            Command::CopySelectionBelow
+            | Command::CopySelectionAbove
+            | Command::SplitSelectionOnLines
+            | Command::KeepPrimarySelection
+            | Command::PrevSelection
+            | Command::NextSelection => self.selection_command(&command),
+            Command::WindowSplit
+            | Command::WindowVsplit
+            | Command::WindowNext
+            | Command::WindowPrev
+            | Command::WindowLeft
+            | Command::WindowDown
+            | Command::WindowUp
+            | Command::WindowRight
+            | Command::WindowClose
+            | Command::WindowOnly
+            | Command::WindowTaller
+            | Command::WindowShorter
+            | Command::WindowWider
+            | Command::WindowNarrower
+            | Command::WindowEqualize => self.window_command(&command),
+            Command::InsertNewline => {
+                if let Mode::Insert = self.mode {
+                    self.file_buffers[self.current_file_buffer_idx].insert('\n');
+                    self.shift_viewport();
                 }
             }
-            KeyCode::Right => {
+            Command::InsertIndent => {
+                if let Mode::Insert = self.mode {
+                    self.file_buffers[self.current_file_buffer_idx].insert_indent();
+                    self.shift_viewport();
+                }
+            }
+            Command::DeleteBackward | Command::DeleteForward => {
+                self.delete_char(matches!(command, Command::DeleteBackward));
+            }
+            Command::SubmitCommandline => {
                 if let Mode::Command = self.mode {
-                    if self.command_line.cursor_pos < self.command_line.command.len() {
-                        self.command_line.cursor_pos =
-                            self.command_line.cursor_pos.saturating_add(1);
+                    match self.command_line.prompt {
+                        Prompt::Command => self.execute_command()?,
+                        Prompt::SearchForward | Prompt::SearchBackward | Prompt::Select => {
+                            self.execute_search();
+                        }
                     }
-                } else {
-                    self.file_buffers[self.current_file_buffer_idx].move_cursors(key_event.code);
-                    self.file_buffers[self.current_file_buffer_idx]
-                        .shift_viewport(self.terminal.size());
                 }
             }
-            KeyCode::Down | KeyCode::Up => {
-                if let Mode::Command = self.mode {
-                } else {
-                    self.file_buffers[self.current_file_buffer_idx].move_cursors(key_event.code);
-                    self.file_buffers[self.current_file_buffer_idx]
-                        .shift_viewport(self.terminal.size());
+            Command::Ex(input) => {
+                self.leave_visual_mode();
+                self.run_command(&input)?;
+            }
+            Command::SelectRegister | Command::Replace | Command::NoOp => (),
+        }
+        Ok(())
+    }
+
+    /// Takes itself and a selection `Command`.
+    /// Adds, splits or cycles through the selections.
+    fn selection_command(&mut self, command: &Command) {
+        let file_buffer = &mut self.file_buffers[self.current_file_buffer_idx];
+        match command {
+            Command::CopySelectionBelow => file_buffer.copy_selections_to_adjacent_line(false),
+            Command::CopySelectionAbove => file_buffer.copy_selections_to_adjacent_line(true),
+            Command::SplitSelectionOnLines => file_buffer.split_selections_on_lines(),
+            Command::KeepPrimarySelection => file_buffer.keep_primary_selection(),
+            Command::PrevSelection => file_buffer.cycle_primary_selection(true),
+            Command::NextSelection => file_buffer.cycle_primary_selection(false),
+            _ => (),
+        }
+        self.shift_viewport();
+    }
+
+    /// Takes itself, a `Command` that needs a char and the char typed.
+    /// Runs the command with the char.
+    fn execute_with_char(&mut self, command: &Command, c: char) {
+        match command {
+            Command::SelectRegister if Registers::is_valid_name(c) => {
+                self.selected_register = Some(c);
+            }
+            Command::Replace => {
+                self.file_buffers[self.current_file_buffer_idx].replace_selections(c);
+                self.file_buffers[self.current_file_buffer_idx].commit();
+                self.leave_visual_mode();
+            }
+            _ => (),
+        }
+    }
+
+    /// Takes itself and whether to delete the char before the cursor.
+    /// Deletes a char from the buffer in Insert mode, or from the
+    /// commandline.
+    fn delete_char(&mut self, backspace: bool) {
+        match self.mode {
+            Mode::Insert => {
+                self.file_buffers[self.current_file_buffer_idx].delete(backspace);
+                self.shift_viewport();
+            }
+            Mode::Command => {
+                let command_line = &mut self.command_line;
+                if backspace && command_line.cursor_pos > 0 {
+                    command_line.cursor_pos = command_line.prev_char_pos();
+                    command_line.command.remove(command_line.cursor_pos);
+                } else if !backspace && command_line.cursor_pos < command_line.command.len() {
+                    command_line.command.remove(command_line.cursor_pos);
                 }
             }
             _ => (),
         }
-        Ok(())
     }
 
     /// Takes itself.
-    /// Draws the status bar underneath the text bars.
-    fn draw_status_bar(&self) {
-        let mut status;
-        let width = self.terminal.size().width as usize;
-        let mut file_name = "[No Name]".to_string();
-        if let Some(name) = &self.file_buffers[self.current_file_buffer_idx].file_path {
-            file_name = name.clone();
-            file_name.truncate(20);
+    /// Returns to Normal mode, ending any Insert mode transaction
+    /// and discarding any Visual mode selections.
+    fn enter_normal_mode(&mut self) {
+        match self.mode {
+            Mode::Insert => self.file_buffers[self.current_file_buffer_idx].commit(),
+            Mode::Visual | Mode::VisualLine => {
+                self.file_buffers[self.current_file_buffer_idx].mark_visual_lines();
+                self.file_buffers[self.current_file_buffer_idx].collapse_selections();
+            }
+            _ => (),
+        }
+        self.mode = Mode::Normal;
+        self.command_line.command = String::new();
+        self.command_line.cursor_pos = 0;
+        self.pending_keys.clear();
+        self.awaiting_char = None;
+        self.selected_register = None;
+    }
+
+    /// Takes itself and the arrow key entered.
+    /// Moves the cursors of the current `FileBuffer`,
+    /// extending the selections when in Visual mode.
+    fn move_cursors(&mut self, key_code: KeyCode) {
+        let extend = self.is_extending();
+        let size = self.window_size();
+        let file_buffer = &self.file_buffers[self.current_file_buffer_idx];
+        let gutter_width = self.gutter_width(file_buffer, usize::from(size.width));
+        let wrap = self.wrap(
+            file_buffer,
+            usize::from(size.width).saturating_sub(gutter_width),
+        );
+        self.file_buffers[self.current_file_buffer_idx].move_cursors(key_code, extend, wrap);
+        self.shift_viewport();
+    }
+
+    /// Takes itself.
+    /// Returns to Normal mode if a Visual mode is active.
+    fn leave_visual_mode(&mut self) {
+        if self.is_extending() {
+            self.enter_normal_mode();
         }
+    }
+
+    /// Takes itself.
+    /// Copies the text of every selection into the selected register.
+    fn yank_selections(&mut self) {
+        let linewise = matches!(self.mode, Mode::VisualLine);
+        let register = self.selected_register.take().unwrap_or(UNNAMED_REGISTER);
+        let values = self.file_buffers[self.current_file_buffer_idx].selection_texts(linewise);
+        self.registers.write(register, values);
+    }
+
+    /// Takes itself and whether to enter Insert mode afterwards.
+    /// Yanks and then deletes the text of every selection. When
+    /// changing, the deletion and the following Insert mode session
+    /// are undone together.
+    fn delete_selections(&mut self, change: bool) {
+        let linewise = matches!(self.mode, Mode::VisualLine);
+        self.yank_selections();
+        self.file_buffers[self.current_file_buffer_idx].delete_selections(linewise, change);
+        self.shift_viewport();
+        if change {
+            self.mode = Mode::Insert;
+        } else {
+            self.file_buffers[self.current_file_buffer_idx].commit();
+            self.leave_visual_mode();
+        }
+    }
+
+    /// Takes itself and whether to paste before the selections.
+    /// Pastes the contents of the selected register.
+    fn paste(&mut self, before: bool) {
+        let register = self.selected_register.take().unwrap_or(UNNAMED_REGISTER);
+        if let Some(values) = self.registers.read(register) {
+            let file_buffer = &mut self.file_buffers[self.current_file_buffer_idx];
+            file_buffer.paste(values, before);
+            file_buffer.commit();
+            self.shift_viewport();
+        }
+        self.leave_visual_mode();
+    }
+
+    /// Takes itself and a Visual `Mode`.
+    /// Enters the given Visual mode, or returns to Normal mode
+    /// if it is already active.
+    fn toggle_visual_mode(&mut self, mode: Mode) {
+        if self.mode == mode {
+            self.enter_normal_mode();
+        } else {
+            self.mode = mode;
+        }
+    }
+
+    /// Takes itself.
+    /// Returns a bool representing whether movement should extend
+    /// the selections rather than move them.
+    fn is_extending(&self) -> bool {
+        matches!(self.mode, Mode::Visual | Mode::VisualLine)
+    }
+
+    /// Takes itself and a `Motion`.
+    /// Moves the selections of the current `FileBuffer`,
+    /// extending them when in Visual mode.
+    fn move_selections(&mut self, motion: Motion) {
+        let extend = self.is_extending();
+        self.file_buffers[self.current_file_buffer_idx].apply_motion(motion, extend);
+        self.shift_viewport();
+    }
+
+    /// Takes itself, the `Frame` to draw into, the index of the buffer
+    /// a window shows, whether the window is the current one and its
+    /// width.
+    /// Draws the status bar underneath the text rows of the window.
+    fn draw_status_bar(
+        &self,
+        frame: &mut Frame,
+        file_buffer_idx: usize,
+        focused: bool,
+        width: usize,
+    ) {
+        let file_buffer = &self.file_buffers[file_buffer_idx];
+        let file_name: String = file_buffer.name().chars().take(20).collect();
+        let buffer_idx = format!(
+            "[{}/{}]",
+            file_buffer_idx.saturating_add(1),
+            self.file_buffers.len()
+        );
         let mut dirty_status = String::new();
-        if self.file_buffers[self.current_file_buffer_idx].file_is_dirty {
+        if file_buffer.file_is_dirty {
             dirty_status = String::from(" (Dirty)");
         }
-        status = format!(
-            "{file_name}{dirty_status} - {} lines",
-            self.file_buffers[self.current_file_buffer_idx].len()
+        let status = format!(
+            "{buffer_idx} {file_name}{dirty_status} - {} lines - {} {}",
+            file_buffer.len(),
+            file_buffer.options.encoding,
+            file_buffer.options.fileformat
         );
-        // status = format!("{status}"); // This line is kept in case formatting is needed later.
-        status.truncate(width);
-        Terminal::clear_current_line();
-        match self.mode {
-            Mode::Command => println!(
-                "{} {status}\r",
-                format!(" {} ", self.mode).black().on_white(),
-            ),
-            _ => print!("{} {status}", format!(" {} ", self.mode).black().on_white()),
+        if focused {
+            let key = self.mode.theme_key();
+            let mode_style = self.theme.get(&format!("ui.mode.{key}"));
+            let status_style = self.theme.get(&format!("ui.statusline.{key}"));
+            let mode = format!(" {} ", self.mode);
+            let pending: String = self.pending_keys.iter().map(ToString::to_string).collect();
+            let width = width.saturating_sub(mode.chars().count() + 1);
+            let pending: String = pending.chars().take(width).collect();
+            let width = width.saturating_sub(pending.chars().count());
+            let status: String = status.chars().take(width).collect();
+            frame.print(&mode, mode_style);
+            frame.print(&format!(" {status:<width$}{pending}"), status_style);
+        } else {
+            let style = self.theme.get("ui.statusline.inactive");
+            let status: String = format!(" {status}").chars().take(width).collect();
+            frame.print(&format!("{status:<width$}"), style);
         }
     }
 
-    /// Takes itself.
+    /// Takes itself and the `Frame` to draw into.
     /// Draws the commandline underneath the status bar.
-    fn draw_command_line(&self) {
-        Terminal::clear_current_line();
-        print!(":{}", self.command_line.command);
+    fn draw_command_line(&self, frame: &mut Frame) {
+        let width = usize::from(self.terminal.size().width);
+        let line = format!("{}{}", self.command_line.prompt, self.command_line.command);
+        frame.print(&format!("{line:<width$}"), self.theme.get("ui.commandline"));
     }
 
-    /// Takes itself.
+    /// Takes itself, the `Frame` to draw into and the area of the
+    /// current window.
     /// Draws all box cursors.
-    fn draw_cursors(&self) {
+    fn draw_cursors(&self, frame: &mut Frame, rect: Rect) {
         let file_buffer = &self.file_buffers[self.current_file_buffer_idx];
+        let size = Size {
+            width: rect.width,
+            height: rect.height.saturating_sub(1),
+        };
+        let gutter_width = self.gutter_width(file_buffer, usize::from(rect.width));
+        let wrap = self.wrap(
+            file_buffer,
+            usize::from(rect.width).saturating_sub(gutter_width),
+        );
+        let text_style = self.theme.get("ui.text");
         let file_buffer_selections = &file_buffer.selections;
-        for i in 0..file_buffer_selections.len() {
-            if i == file_buffer.primary_selection_idx {
+        for (i, selection) in file_buffer_selections.iter().enumerate() {
+            let primary = i == file_buffer.primary_selection_idx;
+            if primary {
                 if let Mode::Insert = self.mode {
                     continue;
                 }
             }
-            let cursor = file_buffer
-                .get_screen_cursor_pos(&file_buffer_selections[i].cursor, self.terminal.size());
+            let name = if primary {
+                "ui.cursor.primary"
+            } else {
+                "ui.cursor"
+            };
+            let style = theme::overlay(text_style, self.theme.get(name));
+            let cursor =
+                file_buffer.get_screen_cursor_pos(&selection.cursor, &size, gutter_width, wrap);
             if let Some(cursor) = cursor {
-                let char = file_buffer.get_char_under_cursor(&file_buffer_selections[i].cursor);
-                Terminal::cursor_position(&cursor);
-                print!("{}", char.black().on_white());
+                let grapheme = file_buffer.get_grapheme_under_cursor(&selection.cursor);
+                frame.move_to(
+                    cursor.x.saturating_add(usize::from(rect.x)),
+                    cursor.y.saturating_add(usize::from(rect.y)),
+                );
+                frame.print(&grapheme, style);
             }
         }
     }
@@ -371,22 +1136,1048 @@ impl Editor {
     /// Takes itself.
     /// Executes the command currently typed in the commandline.
     fn execute_command(&mut self) -> Result<(), std::io::Error> {
-        match &self.command_line.command[..] {
-            "q" => self.should_quit = true,
-            "w" => self.file_buffers[self.current_file_buffer_idx].save()?,
-            "wq" => {
-                self.file_buffers[self.current_file_buffer_idx].save()?;
-                self.should_quit = true;
+        let input = self.command_line.command.clone();
+        self.command_line.command_history.push(input.clone());
+        self.close_prompt();
+        self.run_command(&input)
+    }
+
+    /// Takes itself and a commandline command.
+    /// Parses the range of the command and runs it.
+    fn run_command(&mut self, input: &str) -> Result<(), std::io::Error> {
+        let file_buffer = &self.file_buffers[self.current_file_buffer_idx];
+        let context = LineContext {
+            current_line: file_buffer.selections[file_buffer.primary_selection_idx]
+                .cursor
+                .y,
+            last_line: file_buffer.len().saturating_sub(1),
+            visual_lines: file_buffer.visual_lines,
+        };
+        let (range, command) = match command::parse_range(input.trim(), &context) {
+            Ok(parsed) => parsed,
+            Err(message) => {
+                self.message = Some(message);
+                return Ok(());
+            }
+        };
+        let command = command.trim();
+        // `:confirm` asks what to do with unsaved changes instead of
+        // refusing to close the buffers.
+        let (confirm, command) = match command.split_once(' ') {
+            Some(("conf" | "confirm", command)) => (true, command.trim_start()),
+            _ => (false, command),
+        };
+        if let Some(substitution) = command
+            .strip_prefix("substitute")
+            .or_else(|| command.strip_prefix('s'))
+            .filter(|rest| rest.starts_with(|c: char| !c.is_alphanumeric()))
+        {
+            let lines = range.unwrap_or(context.current_line..=context.current_line);
+            return self.substitute(substitution, lines);
+        }
+        if let Some(write) = WriteCommand::parse(command) {
+            match self.write(&write, range) {
+                Ok(message) => {
+                    self.message = Some(message);
+                    if write.quit {
+                        self.quit_window(false, confirm)?;
+                    }
+                }
+                Err(message) => self.message = Some(message),
+            }
+            return Ok(());
+        }
+        let force = command.ends_with('!');
+        match command {
+            "q" | "quit" | "q!" | "quit!" => self.quit_window(force, confirm)?,
+            "qa" | "qall" | "quita" | "quitall" | "qa!" | "qall!" | "quita!" | "quitall!" => {
+                self.quit_all(force, confirm)?;
+            }
+            "wa" | "wall" => {
+                self.write_all();
+            }
+            "wqa" | "wqall" | "xa" | "xall" => {
+                if self.write_all() {
+                    self.should_quit = true;
+                }
+            }
+            "sav" | "saveas" | "sav!" | "saveas!" => {
+                self.message = Some(String::from("Argument required"));
+            }
+            "e!" | "edit!" | "reload" => self.reload_buffer(),
+            "sp" | "split" => self.split_window(SplitDirection::Horizontal),
+            "vs" | "vsplit" => self.split_window(SplitDirection::Vertical),
+            "clo" | "close" => self.close_window(),
+            "on" | "only" => self.only_window(),
+            "undo" => {
+                self.file_buffers[self.current_file_buffer_idx].undo();
+                self.shift_viewport();
+            }
+            "redo" => {
+                self.file_buffers[self.current_file_buffer_idx].redo();
+                self.shift_viewport();
             }
+            "bn" | "bnext" => self.switch_buffer(
+                self.current_file_buffer_idx.saturating_add(1) % self.file_buffers.len(),
+            ),
+            "bp" | "bprevious" => self.switch_buffer(
+                (self.current_file_buffer_idx + self.file_buffers.len() - 1)
+                    % self.file_buffers.len(),
+            ),
+            "bd" | "bdelete" | "bd!" | "bdelete!" => {
+                self.delete_buffer(self.current_file_buffer_idx, force, confirm)?;
+            }
+            "ls" | "buffers" => self.list_buffers(),
+            "theme" => self.message = Some(format!("Theme: {}", self.theme.name)),
+            "se" | "set" => self.list_options(),
+            "" => (),
+            _ => {
+                if let Some((name, argument)) = command.split_once(' ') {
+                    self.execute_command_with_argument(name, argument.trim(), confirm)?;
+                } else {
+                    self.message = Some(format!("Not an editor command: {command}"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes itself, the name of a command, its argument and whether
+    /// to ask what to do with unsaved changes.
+    /// Executes a command that takes an argument.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the screen cannot be redrawn or a key
+    /// cannot be read while confirming.
+    fn execute_command_with_argument(
+        &mut self,
+        name: &str,
+        argument: &str,
+        confirm: bool,
+    ) -> Result<(), std::io::Error> {
+        match name {
+            "e" | "edit" | "e!" | "edit!" => self.edit_file(argument, name.ends_with('!')),
+            "b" | "buffer" => match self.find_buffer(argument) {
+                Ok(idx) => self.switch_buffer(idx),
+                Err(message) => self.message = Some(message),
+            },
+            "bd" | "bdelete" | "bd!" | "bdelete!" => match self.find_buffer(argument) {
+                Ok(idx) => self.delete_buffer(idx, name.ends_with('!'), confirm)?,
+                Err(message) => self.message = Some(message),
+            },
+            "sp" | "split" => {
+                self.split_window(SplitDirection::Horizontal);
+                self.edit_file(argument, false);
+            }
+            "vs" | "vsplit" => {
+                self.split_window(SplitDirection::Vertical);
+                self.edit_file(argument, false);
+            }
+            "res" | "resize" => self.resize_window(SplitDirection::Horizontal, argument),
+            "theme" => {
+                if let Err(message) = self.set_option("theme", argument) {
+                    self.message = Some(message);
+                }
+            }
+            "se" | "set" => self.set_options(argument),
+            "sav" | "saveas" | "sav!" | "saveas!" => {
+                let force = name.ends_with('!');
+                self.message = Some(
+                    self.save_as(argument, force)
+                        .unwrap_or_else(|message| message),
+                );
+            }
+            "vert" | "vertical" => match argument.split_once(' ') {
+                Some(("res" | "resize", size)) => {
+                    self.resize_window(SplitDirection::Vertical, size.trim());
+                }
+                _ => self.message = Some(format!("Not an editor command: {name} {argument}")),
+            },
+            _ => self.message = Some(format!("Not an editor command: {name}")),
+        }
+        Ok(())
+    }
+
+    /// Takes itself, a file path and whether to discard unsaved
+    /// changes.
+    /// Switches to the buffer for the path, opening it if it is not
+    /// open yet. A path that does not exist gets a new, empty buffer.
+    /// When forced, a buffer that is already open reads its file
+    /// again.
+    fn edit_file(&mut self, file_path: &str, force: bool) {
+        if let Some(idx) = self
+            .file_buffers
+            .iter()
+            .position(|file_buffer| file_buffer.file_path.as_deref() == Some(Path::new(file_path)))
+        {
+            self.switch_buffer(idx);
+            if force {
+                self.reload_buffer();
+            }
+            return;
+        }
+        let mut file_buffer = match FileBuffer::open(Path::new(file_path)) {
+            Ok(file_buffer) => file_buffer,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                self.message = Some(format!("\"{file_path}\" [New File]"));
+                FileBuffer::with_path(Path::new(file_path))
+            }
+            Err(error) => {
+                self.message = Some(format!("Cannot open \"{file_path}\": {error}"));
+                return;
+            }
+        };
+        file_buffer.set_options(self.config.buffer_options_for(file_buffer.language()));
+        if let Some(warning) = file_buffer.open_warning() {
+            self.message = Some(warning);
+        }
+        let replace_current = {
+            let current = &self.file_buffers[self.current_file_buffer_idx];
+            current.file_path.is_none() && current.buffer_is_empty && !current.file_is_dirty
+        };
+        if replace_current {
+            self.file_buffers[self.current_file_buffer_idx] = file_buffer;
+        } else {
+            self.file_buffers.push(file_buffer);
+            self.switch_buffer(self.file_buffers.len() - 1);
+        }
+    }
+
+    /// Takes itself and a buffer number or part of a buffer name.
+    /// Returns the index of the matching buffer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if no buffer or more than one
+    /// buffer matches.
+    fn find_buffer(&self, argument: &str) -> Result<usize, String> {
+        if let Ok(number) = argument.parse::<usize>() {
+            return if (1..=self.file_buffers.len()).contains(&number) {
+                Ok(number - 1)
+            } else {
+                Err(format!("Buffer {number} does not exist"))
+            };
+        }
+        let matches: Vec<usize> = self
+            .file_buffers
+            .iter()
+            .enumerate()
+            .filter(|(_, file_buffer)| file_buffer.name().contains(argument))
+            .map(|(idx, _)| idx)
+            .collect();
+        match matches[..] {
+            [idx] => Ok(idx),
+            [] => Err(format!("No matching buffer for {argument}")),
+            _ => Err(format!("More than one match for {argument}")),
+        }
+    }
+
+    /// Takes itself and the index of a buffer.
+    /// Makes the buffer the current one. Each buffer keeps its
+    /// own selections and viewport.
+    fn switch_buffer(&mut self, idx: usize) {
+        if idx < self.file_buffers.len() {
+            self.current_file_buffer_idx = idx;
+            self.shift_viewport();
+        }
+    }
+
+    /// Takes itself and the index of a buffer.
+    /// Closes the buffer. Closing the last buffer leaves an
+    /// empty one in its place, and other windows showing the
+    /// buffer switch to the current one.
+    fn close_buffer(&mut self, idx: usize) {
+        self.file_buffers.remove(idx);
+        if self.file_buffers.is_empty() {
+            let mut file_buffer = FileBuffer::default();
+            file_buffer.set_options(self.config.buffer_options_for(None));
+            self.file_buffers.push(file_buffer);
+        }
+        if self.current_file_buffer_idx > idx
+            || self.current_file_buffer_idx >= self.file_buffers.len()
+        {
+            self.current_file_buffer_idx = self.current_file_buffer_idx.saturating_sub(1);
+        }
+        for window in &mut self.windows {
+            if window.file_buffer_idx == idx {
+                window.file_buffer_idx = self.current_file_buffer_idx;
+                window.view = self.file_buffers[self.current_file_buffer_idx].view();
+            } else if window.file_buffer_idx > idx {
+                window.file_buffer_idx -= 1;
+            }
+        }
+        self.shift_viewport();
+    }
+
+    /// Takes itself and a window `Command`.
+    /// Splits, moves between or resizes the windows.
+    fn window_command(&mut self, command: &Command) {
+        match command {
+            Command::WindowSplit => self.split_window(SplitDirection::Horizontal),
+            Command::WindowVsplit => self.split_window(SplitDirection::Vertical),
+            Command::WindowNext => {
+                self.focus_window((self.current_window_idx + 1) % self.windows.len());
+            }
+            Command::WindowPrev => self.focus_window(
+                (self.current_window_idx + self.windows.len() - 1) % self.windows.len(),
+            ),
+            Command::WindowLeft => self.focus_neighbour(Side::Left),
+            Command::WindowDown => self.focus_neighbour(Side::Down),
+            Command::WindowUp => self.focus_neighbour(Side::Up),
+            Command::WindowRight => self.focus_neighbour(Side::Right),
+            Command::WindowClose => self.close_window(),
+            Command::WindowOnly => self.only_window(),
+            Command::WindowTaller => self.grow_window(SplitDirection::Horizontal, 1),
+            Command::WindowShorter => self.grow_window(SplitDirection::Horizontal, -1),
+            Command::WindowWider => self.grow_window(SplitDirection::Vertical, 1),
+            Command::WindowNarrower => self.grow_window(SplitDirection::Vertical, -1),
+            Command::WindowEqualize => self.layout.equalize(),
+            _ => (),
+        }
+        self.shift_viewport();
+    }
+
+    /// Takes itself and the direction to split in.
+    /// Splits the current window in two, both showing the current
+    /// buffer, and moves to the new window.
+    fn split_window(&mut self, direction: SplitDirection) {
+        self.leave_visual_mode();
+        self.windows.push(Window {
+            file_buffer_idx: self.current_file_buffer_idx,
+            view: self.file_buffers[self.current_file_buffer_idx].view(),
+        });
+        let new_window_idx = self.windows.len() - 1;
+        self.layout
+            .split(self.current_window_idx, new_window_idx, direction);
+        self.focus_window(new_window_idx);
+    }
+
+    /// Takes itself and the index of a window.
+    /// Makes the window the current one. The view of the window being
+    /// left is stored, and the view of the new window is restored
+    /// into its buffer.
+    fn focus_window(&mut self, window_idx: usize) {
+        if window_idx == self.current_window_idx || window_idx >= self.windows.len() {
+            return;
+        }
+        self.leave_visual_mode();
+        self.map_views();
+        self.windows[self.current_window_idx] = Window {
+            file_buffer_idx: self.current_file_buffer_idx,
+            view: self.file_buffers[self.current_file_buffer_idx].view(),
+        };
+        self.load_window(window_idx);
+    }
+
+    /// Takes itself and the index of a window.
+    /// Makes the window current without storing the view of the
+    /// previous one.
+    fn load_window(&mut self, window_idx: usize) {
+        self.current_window_idx = window_idx;
+        self.current_file_buffer_idx = self.windows[window_idx].file_buffer_idx;
+        self.file_buffers[self.current_file_buffer_idx].set_view(&self.windows[window_idx].view);
+        self.shift_viewport();
+    }
+
+    /// Takes itself and a `Side`.
+    /// Moves to the nearest window on that side of the current one.
+    fn focus_neighbour(&mut self, side: Side) {
+        let (rects, _) = self.layout.rects(self.layout_area());
+        if let Some(window_idx) = window::neighbour(&rects, self.current_window_idx, side) {
+            self.focus_window(window_idx);
+        }
+    }
+
+    /// Takes itself.
+    /// Closes the current window and moves to the previous one.
+    fn close_window(&mut self) {
+        if self.windows.len() == 1 {
+            self.message = Some(String::from("Cannot close last window"));
+            return;
+        }
+        self.leave_visual_mode();
+        self.map_views();
+        let closed_idx = self.current_window_idx;
+        self.layout.remove(closed_idx);
+        self.windows.remove(closed_idx);
+        self.load_window(cmp::min(
+            closed_idx.saturating_sub(1),
+            self.windows.len() - 1,
+        ));
+    }
+
+    /// Takes itself.
+    /// Closes every window except the current one.
+    fn only_window(&mut self) {
+        self.windows = vec![Window {
+            file_buffer_idx: self.current_file_buffer_idx,
+            view: self.file_buffers[self.current_file_buffer_idx].view(),
+        }];
+        self.layout = Layout::Window(0);
+        self.current_window_idx = 0;
+        self.shift_viewport();
+    }
+
+    /// Takes itself.
+    /// Discards the changes to the current buffer and reads its file
+    /// again.
+    fn reload_buffer(&mut self) {
+        let file_buffer = &mut self.file_buffers[self.current_file_buffer_idx];
+        let name = file_buffer.name().to_string();
+        self.message = Some(match file_buffer.reload() {
+            Ok(bytes) => format!(
+                "\"{name}\" {} reloaded",
+                save::summary(file_buffer.text(None), bytes)
+            ),
+            Err(error) => format!("Cannot reload \"{name}\": {error}"),
+        });
+        self.shift_viewport();
+    }
+
+    /// Takes itself.
+    /// Looks for changes to the files of the buffers on disk.
+    /// Buffers without unsaved changes are reloaded, and a warning
+    /// is shown for the others and for large files, which are too
+    /// costly to read again whenever they change. Returns a bool
+    /// representing whether any change was found.
+    fn check_disk(&mut self) -> bool {
+        let mut messages = Vec::new();
+        for file_buffer in &mut self.file_buffers {
+            let Some(change) = file_buffer.poll_disk() else {
+                continue;
+            };
+            let name = file_buffer.name().to_string();
+            messages.push(match change {
+                DiskChange::Deleted => format!("\"{name}\" has been deleted on disk"),
+                DiskChange::Modified if file_buffer.file_is_dirty => {
+                    format!("\"{name}\" has changed on disk: :e! to reload or :w! to overwrite")
+                }
+                DiskChange::Modified if file_buffer.is_large() => {
+                    format!("\"{name}\" has changed on disk: :e! to reload")
+                }
+                DiskChange::Modified | DiskChange::Unchanged => match file_buffer.reload() {
+                    Ok(_) => format!("\"{name}\" has changed on disk and was reloaded"),
+                    Err(error) => format!("Cannot reload \"{name}\": {error}"),
+                },
+            });
+        }
+        let Some(message) = messages.first() else {
+            return false;
+        };
+        self.message = Some(if messages.len() == 1 {
+            message.clone()
+        } else {
+            format!("{message} (and {} more)", messages.len() - 1)
+        });
+        self.shift_viewport();
+        true
+    }
+
+    /// Takes itself, a write command and the lines it was given.
+    /// Writes the current buffer, or the lines, to its own file or
+    /// to the path of the command. Writing the whole buffer to its
+    /// own file, or to any file when it has none yet, saves it.
+    /// Returns a message saying how much was written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if there is no file to write to,
+    /// the file exists and the command is not forced, or the file
+    /// cannot be written.
+    fn write(
+        &mut self,
+        write: &WriteCommand,
+        lines: Option<RangeInclusive<usize>>,
+    ) -> Result<String, String> {
+        let backup = self.config.options.backup;
+        let file_buffer = &self.file_buffers[self.current_file_buffer_idx];
+        let own_path = file_buffer.file_path.clone();
+        let path = match (write.path, &own_path) {
+            (Some(path), _) => PathBuf::from(path),
+            (None, Some(path)) => path.clone(),
+            (None, None) => return Err(String::from("No file name")),
+        };
+        let name = path.display();
+        let is_own_file = own_path.as_ref() == Some(&path);
+        if write.append {
+            let contents = file_buffer.encode(lines.clone(), false)?;
+            save::append(&path, &contents)
+                .map_err(|error| format!("Cannot write \"{name}\": {error}"))?;
+            let summary = save::summary(file_buffer.text(lines), contents.len());
+            return Ok(format!("\"{name}\" {summary} appended"));
+        }
+        if is_own_file && lines.is_some() && !write.force {
+            return Err(String::from("Use ! to write partial buffer"));
+        }
+        if !is_own_file && !write.force && path.exists() {
+            return Err(String::from("File exists (add ! to override)"));
+        }
+        let (bytes, atomic) = if lines.is_none() && (is_own_file || own_path.is_none()) {
+            if own_path.is_none() {
+                self.rename_buffer(&path);
+            }
+            self.file_buffers[self.current_file_buffer_idx].save(backup, write.force)?
+        } else {
+            self.file_buffers[self.current_file_buffer_idx].write_lines(
+                lines.clone(),
+                &path,
+                backup,
+            )?
+        };
+        let text = self.file_buffers[self.current_file_buffer_idx].text(lines);
+        Ok(format!(
+            "\"{name}\" {} written{}",
+            save::summary(text, bytes),
+            save::in_place_note(atomic)
+        ))
+    }
+
+    /// Takes itself, a file path and whether to overwrite the file.
+    /// Makes the current buffer save to the path and saves it.
+    /// Returns a message saying how much was written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if the file exists and the command
+    /// is not forced, or the file cannot be written.
+    fn save_as(&mut self, file_path: &str, force: bool) -> Result<String, String> {
+        let file_buffer = &self.file_buffers[self.current_file_buffer_idx];
+        let path = Path::new(file_path);
+        if file_buffer.file_path.as_deref() != Some(path) && !force && path.exists() {
+            return Err(String::from("File exists (add ! to override)"));
+        }
+        self.rename_buffer(path);
+        let backup = self.config.options.backup;
+        let file_buffer = &mut self.file_buffers[self.current_file_buffer_idx];
+        let (bytes, atomic) = file_buffer.save(backup, force || !path.exists())?;
+        Ok(format!(
+            "\"{file_path}\" {} written{}",
+            save::summary(file_buffer.text(None), bytes),
+            save::in_place_note(atomic)
+        ))
+    }
+
+    /// Takes itself and a file path.
+    /// Makes the current buffer save to the path, with the options
+    /// for the language of the path.
+    fn rename_buffer(&mut self, file_path: &Path) {
+        let file_buffer = &mut self.file_buffers[self.current_file_buffer_idx];
+        file_buffer.set_file_path(file_path);
+        file_buffer.set_options(self.config.buffer_options_for(file_buffer.language()));
+        self.shift_viewport();
+    }
+
+    /// Takes itself.
+    /// Saves every buffer with unsaved changes. Returns a bool
+    /// representing whether they were all saved.
+    fn write_all(&mut self) -> bool {
+        let backup = self.config.options.backup;
+        let mut written = 0;
+        let mut in_place = 0;
+        let mut errors = Vec::new();
+        for file_buffer in &mut self.file_buffers {
+            if !file_buffer.file_is_dirty {
+                continue;
+            }
+            let name = file_buffer.name().to_string();
+            match file_buffer.save(backup, false) {
+                Ok((_, atomic)) => {
+                    written += 1;
+                    in_place += usize::from(!atomic);
+                }
+                Err(message) => errors.push(format!("{name}: {message}")),
+            }
+        }
+        self.message = Some(match errors.first() {
+            Some(error) if errors.len() == 1 => error.clone(),
+            Some(error) => format!("{error} (and {} more)", errors.len() - 1),
+            None if in_place > 0 => {
+                format!("{written} file(s) written, {in_place} in place (not atomically)")
+            }
+            None => format!("{written} file(s) written"),
+        });
+        errors.is_empty()
+    }
+
+    /// Takes itself, whether to discard unsaved changes and whether
+    /// to ask what to do with them.
+    /// Closes the current window, quitting when it is the last one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the screen cannot be redrawn or a key
+    /// cannot be read while confirming.
+    fn quit_window(&mut self, force: bool, confirm: bool) -> Result<(), std::io::Error> {
+        if self.windows.len() == 1 {
+            self.quit_all(force, confirm)
+        } else {
+            self.close_window();
+            Ok(())
+        }
+    }
+
+    /// Takes itself, whether to discard unsaved changes and whether
+    /// to ask what to do with them.
+    /// Quits the editor, unless a buffer has unsaved changes that
+    /// are kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the screen cannot be redrawn or a key
+    /// cannot be read while confirming.
+    fn quit_all(&mut self, force: bool, confirm: bool) -> Result<(), std::io::Error> {
+        let idxs: Vec<usize> = (0..self.file_buffers.len()).collect();
+        if self.check_unsaved(&idxs, force, confirm)? {
+            self.should_quit = true;
+        }
+        Ok(())
+    }
+
+    /// Takes itself, the index of a buffer, whether to discard its
+    /// unsaved changes and whether to ask what to do with them.
+    /// Closes the buffer, unless it has unsaved changes that are kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the screen cannot be redrawn or a key
+    /// cannot be read while confirming.
+    fn delete_buffer(
+        &mut self,
+        idx: usize,
+        force: bool,
+        confirm: bool,
+    ) -> Result<(), std::io::Error> {
+        if self.check_unsaved(&[idx], force, confirm)? {
+            self.close_buffer(idx);
+        }
+        Ok(())
+    }
+
+    /// Takes itself, the indices of the buffers about to be closed,
+    /// whether to discard their unsaved changes and whether to ask
+    /// what to do with them.
+    /// Returns a bool representing whether the buffers may be
+    /// closed. Unless forced or confirming, lists the buffers with
+    /// unsaved changes and refuses.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the screen cannot be redrawn or a key
+    /// cannot be read while confirming.
+    fn check_unsaved(
+        &mut self,
+        idxs: &[usize],
+        force: bool,
+        confirm: bool,
+    ) -> Result<bool, std::io::Error> {
+        let dirty: Vec<usize> = idxs
+            .iter()
+            .copied()
+            .filter(|&idx| self.file_buffers[idx].file_is_dirty)
+            .collect();
+        if force || dirty.is_empty() {
+            return Ok(true);
+        }
+        if confirm {
+            return self.confirm_unsaved(&dirty);
+        }
+        let list: Vec<String> = dirty
+            .iter()
+            .map(|&idx| {
+                format!(
+                    "{:>3} \"{}\"",
+                    idx.saturating_add(1),
+                    self.file_buffers[idx].name()
+                )
+            })
+            .collect();
+        self.message = Some(format!(
+            "No write since last change (add ! to override):\n{}",
+            list.join("\n")
+        ));
+        Ok(false)
+    }
+
+    /// Takes itself and the indices of buffers with unsaved changes.
+    /// Asks for each one whether to save or discard the changes, or
+    /// to cancel. Returns a bool representing whether every buffer
+    /// was saved or discarded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the screen cannot be redrawn or a key
+    /// cannot be read.
+    fn confirm_unsaved(&mut self, idxs: &[usize]) -> Result<bool, std::io::Error> {
+        let backup = self.config.options.backup;
+        for &idx in idxs {
+            self.message = Some(format!(
+                "Save changes to \"{}\"? (y)es, (n)o, (c)ancel",
+                self.file_buffers[idx].name()
+            ));
+            self.refresh_screen()?;
+            loop {
+                let key_event = match self.terminal.read_event()? {
+                    Input::Key(key_event) if key_event.kind != KeyEventKind::Release => key_event,
+                    Input::Key(_) => continue,
+                    Input::Resize => {
+                        self.refresh_screen()?;
+                        continue;
+                    }
+                };
+                match key_event.code {
+                    KeyCode::Char('y') => {
+                        if let Err(message) = self.file_buffers[idx].save(backup, false) {
+                            self.message = Some(message);
+                            return Ok(false);
+                        }
+                        break;
+                    }
+                    KeyCode::Char('n') => break,
+                    KeyCode::Char('c') | KeyCode::Esc => {
+                        self.message = None;
+                        return Ok(false);
+                    }
+                    _ => (),
+                }
+            }
+        }
+        self.message = None;
+        Ok(true)
+    }
+
+    /// Takes itself, the direction to resize in and the number of
+    /// cells to grow by.
+    /// Grows (or shrinks) the current window.
+    fn grow_window(&mut self, direction: SplitDirection, delta: i16) {
+        let area = self.layout_area();
+        self.layout
+            .resize(self.current_window_idx, direction, delta, area);
+        self.shift_viewport();
+    }
+
+    /// Takes itself, the direction to resize in and the argument of
+    /// a resize command.
+    /// Sets the height (or width) of the current window, or changes
+    /// it by the given amount when the argument starts with + or -.
+    fn resize_window(&mut self, direction: SplitDirection, argument: &str) {
+        let Ok(amount) = argument.parse::<i16>() else {
+            self.message = Some(format!("Invalid size: {argument}"));
+            return;
+        };
+        let delta = if argument.starts_with(['+', '-']) {
+            amount
+        } else {
+            let rect = self
+                .layout
+                .rect(self.current_window_idx, self.layout_area())
+                .unwrap_or_default();
+            let current = match direction {
+                SplitDirection::Horizontal => rect.height.saturating_sub(1),
+                SplitDirection::Vertical => rect.width,
+            };
+            amount.saturating_sub(i16::try_from(current).unwrap_or(i16::MAX))
+        };
+        self.grow_window(direction, delta);
+    }
+
+    /// Takes itself and the items of a `:set` command.
+    /// Sets or shows each option named by the items.
+    fn set_options(&mut self, argument: &str) {
+        let mut shown = Vec::new();
+        for item in argument.split_whitespace() {
+            let result = match Setting::parse(item) {
+                Setting::Show(name) => self
+                    .option_value(name)
+                    .map(|value| shown.push(format!("{name}={value}"))),
+                Setting::Assign(name, value) => self.set_option(name, value),
+            };
+            if let Err(message) = result {
+                self.message = Some(message);
+                return;
+            }
+        }
+        if !shown.is_empty() {
+            self.message = Some(shown.join("  "));
+        }
+    }
+
+    /// Takes itself, the name of an option and its new value.
+    /// Sets the option, applying it to the editor straight away.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if there is no such option, the
+    /// value does not suit it or it cannot be applied.
+    fn set_option(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if name == "theme" {
+            self.theme = Theme::load(value, ColorSupport::detect())?;
+        }
+        let file_buffer = &mut self.file_buffers[self.current_file_buffer_idx];
+        self.config.set(name, value, &mut file_buffer.options)?;
+        match name {
+            "alt_screen" => self
+                .terminal
+                .set_alt_screen(self.config.options.alt_screen)
+                .map_err(|error| format!("Cannot set alt_screen: {error}"))?,
+            "detect_indent" => {
+                self.file_buffers[self.current_file_buffer_idx].apply_detected_indent();
+            }
+            // The file no longer matches the buffer until it is saved.
+            "fileformat" | "encoding" => {
+                self.file_buffers[self.current_file_buffer_idx].file_is_dirty = true;
+            }
+            "scrolloff" | "line_numbers" | "marker_column" | "tab_width" | "soft_wrap"
+            | "wrap_at_words" | "wrap_indicator" | "large_file_size" => self.shift_viewport(),
             _ => (),
         }
-        self.command_line
-            .command_history
-            .push(self.command_line.command.clone());
+        Ok(())
+    }
+
+    /// Takes itself and the name of an option.
+    /// Returns the value of the option for the current buffer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if there is no such option.
+    fn option_value(&self, name: &str) -> Result<String, String> {
+        let file_buffer = &self.file_buffers[self.current_file_buffer_idx];
+        self.config
+            .get(name, &file_buffer.options)
+            .ok_or_else(|| format!("Unknown option: {name}"))
+    }
+
+    /// Takes itself.
+    /// Shows the value of every option for the current buffer.
+    fn list_options(&mut self) {
+        let options: Vec<String> = Config::names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = self.option_value(name).ok()?;
+                Some(format!("  {name}={value}"))
+            })
+            .collect();
+        self.message = Some(format!("--- Options ---\n{}", options.join("\n")));
+    }
+
+    /// Takes itself.
+    /// Shows a list of the open buffers.
+    fn list_buffers(&mut self) {
+        let list: Vec<String> = self
+            .file_buffers
+            .iter()
+            .enumerate()
+            .map(|(idx, file_buffer)| {
+                let current = if idx == self.current_file_buffer_idx {
+                    '%'
+                } else {
+                    ' '
+                };
+                let dirty = if file_buffer.file_is_dirty { '+' } else { ' ' };
+                let line = file_buffer.selections[file_buffer.primary_selection_idx]
+                    .cursor
+                    .y
+                    .saturating_add(1);
+                format!(
+                    "{:>3} {current}{dirty} \"{}\" line {line}",
+                    idx.saturating_add(1),
+                    file_buffer.name()
+                )
+            })
+            .collect();
+        self.message = Some(list.join("\n"));
+    }
+
+    /// Takes itself, the arguments of a substitute command and the
+    /// lines to run it on.
+    /// Replaces the matches of the pattern on those lines, asking
+    /// about each one first if the `c` flag was given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the screen cannot be redrawn or a key
+    /// cannot be read while confirming.
+    fn substitute(
+        &mut self,
+        arguments: &str,
+        lines: RangeInclusive<usize>,
+    ) -> Result<(), std::io::Error> {
+        let substitution = match Substitution::parse(arguments) {
+            Ok(substitution) => substitution,
+            Err(message) => {
+                self.message = Some(message);
+                return Ok(());
+            }
+        };
+        let mut substitutions = self.file_buffers[self.current_file_buffer_idx]
+            .substitution_matches(&substitution, lines);
+        if substitutions.is_empty() {
+            self.message = Some(format!("Pattern not found: {}", substitution.regex));
+            return Ok(());
+        }
+        if substitution.confirm {
+            substitutions = self.confirm_substitutions(substitutions)?;
+        }
+        let file_buffer = &mut self.file_buffers[self.current_file_buffer_idx];
+        let line_count = {
+            let mut lines: Vec<usize> = substitutions
+                .iter()
+                .map(|(range, _)| file_buffer.char_idx_to_pos(range.start).y)
+                .collect();
+            lines.dedup();
+            lines.len()
+        };
+        file_buffer.apply_substitutions(&substitutions);
+        self.shift_viewport();
+        self.message = Some(format!(
+            "{} substitutions on {line_count} lines",
+            substitutions.len()
+        ));
+        Ok(())
+    }
+
+    /// Takes itself and a list of pending substitutions.
+    /// Selects each match in turn and asks whether to replace it.
+    /// Returns the substitutions that were accepted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the screen cannot be redrawn or a key
+    /// cannot be read.
+    fn confirm_substitutions(
+        &mut self,
+        substitutions: Vec<(Range<usize>, String)>,
+    ) -> Result<Vec<(Range<usize>, String)>, std::io::Error> {
+        let idx = self.current_file_buffer_idx;
+        let saved_selections = self.file_buffers[idx].selections.clone();
+        let saved_primary_selection_idx = self.file_buffers[idx].primary_selection_idx;
+        let mut accepted = Vec::new();
+        let mut accept_all = false;
+        for (range, replacement) in substitutions {
+            if accept_all {
+                accepted.push((range, replacement));
+                continue;
+            }
+            let file_buffer = &mut self.file_buffers[idx];
+            file_buffer.selections = vec![Selection {
+                anchor: file_buffer.char_idx_to_pos(range.start),
+                cursor: file_buffer.char_idx_to_pos(range.end.saturating_sub(1)),
+            }];
+            file_buffer.primary_selection_idx = 0;
+            self.shift_viewport();
+            self.message = Some(format!(
+                "replace with {} (y/n/a/q/l)?",
+                replacement.escape_debug()
+            ));
+            self.refresh_screen()?;
+            let key_event = loop {
+                match self.terminal.read_event()? {
+                    Input::Key(key_event) if key_event.kind != KeyEventKind::Release => {
+                        break key_event;
+                    }
+                    Input::Key(_) => (),
+                    Input::Resize => {
+                        self.shift_viewport();
+                        self.refresh_screen()?;
+                    }
+                }
+            };
+            match key_event.code {
+                KeyCode::Char('y') => accepted.push((range, replacement)),
+                KeyCode::Char('a') => {
+                    accepted.push((range, replacement));
+                    accept_all = true;
+                }
+                KeyCode::Char('l') => {
+                    accepted.push((range, replacement));
+                    break;
+                }
+                KeyCode::Char('q') | KeyCode::Esc => break,
+                _ => (),
+            }
+        }
+        self.message = None;
+        self.file_buffers[idx].selections = saved_selections;
+        self.file_buffers[idx].primary_selection_idx = saved_primary_selection_idx;
+        Ok(accepted)
+    }
+
+    /// Takes itself and a `Prompt`.
+    /// Opens the commandline to read the given kind of input.
+    fn open_prompt(&mut self, prompt: Prompt) {
+        self.command_line.prompt = prompt;
+        self.command_line.return_mode = self.mode;
+        if self.is_extending() {
+            self.file_buffers[self.current_file_buffer_idx].mark_visual_lines();
+            if let Prompt::Command = prompt {
+                self.command_line.command = String::from("'<,'>");
+                self.command_line.cursor_pos = self.command_line.command.len();
+            }
+        }
+        self.mode = Mode::Command;
+    }
+
+    /// Takes itself.
+    /// Clears the commandline and returns to the mode it was opened from.
+    fn close_prompt(&mut self) {
         self.command_line.command = String::new();
         self.command_line.cursor_pos = 0;
-        self.mode = Mode::Normal;
-        Ok(())
+        self.mode = match self.command_line.prompt {
+            Prompt::Command => Mode::Normal,
+            _ => self.command_line.return_mode,
+        };
+    }
+
+    /// Takes itself.
+    /// Compiles the regex typed in the commandline and either jumps
+    /// to its next match or selects its matches inside the selections.
+    fn execute_search(&mut self) {
+        let prompt = self.command_line.prompt;
+        let pattern = self.command_line.command.clone();
+        self.close_prompt();
+        if pattern.is_empty() {
+            if let Prompt::SearchForward | Prompt::SearchBackward = prompt {
+                self.last_search_backward = matches!(prompt, Prompt::SearchBackward);
+                self.search_next(false);
+            }
+            return;
+        }
+        let regex = match Regex::new(&pattern) {
+            Ok(regex) => regex,
+            Err(error) => {
+                self.message = Some(format!("Invalid regex: {error}"));
+                return;
+            }
+        };
+        if let Prompt::Select = prompt {
+            let file_buffer = &mut self.file_buffers[self.current_file_buffer_idx];
+            if file_buffer.select_matches(&regex) {
+                self.shift_viewport();
+            } else {
+                self.message = Some(String::from("No matches"));
+            }
+            return;
+        }
+        self.last_search = Some(regex);
+        self.last_search_backward = matches!(prompt, Prompt::SearchBackward);
+        self.search_next(false);
+    }
+
+    /// Takes itself and whether to reverse the search direction.
+    /// Selects the next match of the last search.
+    fn search_next(&mut self, reverse: bool) {
+        let Some(regex) = &self.last_search else {
+            self.message = Some(String::from("No previous search"));
+            return;
+        };
+        let backward = self.last_search_backward != reverse;
+        let extend = self.is_extending();
+        let file_buffer = &mut self.file_buffers[self.current_file_buffer_idx];
+        if file_buffer.search(regex, backward, extend) {
+            self.shift_viewport();
+        } else {
+            self.message = Some(format!("Pattern not found: {regex}"));
+        }
     }
 }
 
@@ -0,0 +1,489 @@
+#![warn(clippy::all, clippy::pedantic)]
+use std::{
+    borrow::Cow,
+    fmt, mem,
+    str::{self, FromStr},
+};
+
+/// The byte order mark that starts UTF-8 files written by some
+/// editors.
+const UTF8_BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+
+/// The byte order mark of little-endian UTF-16 files.
+const UTF16LE_BOM: &[u8] = &[0xff, 0xfe];
+
+/// The byte order mark of big-endian UTF-16 files.
+const UTF16BE_BOM: &[u8] = &[0xfe, 0xff];
+
+/// How many bytes at the start of a file are looked at to tell
+/// whether it is binary.
+const BINARY_SAMPLE_LEN: usize = 8000;
+
+/// The line break a file ends its lines with. Buffers always hold
+/// `\n`, and convert to and from the file's line break when it is
+/// read and written.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`, as on Unix.
+    Lf,
+    /// `\r\n`, as on DOS and Windows.
+    Crlf,
+    /// `\r`, as on classic Mac OS.
+    Cr,
+}
+
+impl LineEnding {
+    /// Takes itself.
+    /// Returns the line break as a string slice.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    /// Takes itself and the text of a file.
+    /// Returns the text with its line breaks turned into `\n`. Line
+    /// breaks of other kinds are left alone.
+    #[must_use]
+    pub fn normalize(self, text: Cow<'_, str>) -> Cow<'_, str> {
+        match self {
+            LineEnding::Lf => text,
+            LineEnding::Crlf | LineEnding::Cr => Cow::Owned(text.replace(self.as_str(), "\n")),
+        }
+    }
+
+    /// Takes itself and the text of a buffer.
+    /// Returns the text with its line breaks turned into this kind.
+    #[must_use]
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        match self {
+            LineEnding::Lf => Cow::Borrowed(text),
+            LineEnding::Crlf | LineEnding::Cr => Cow::Owned(text.replace('\n', self.as_str())),
+        }
+    }
+}
+
+/// How many lines of a file end with each line break, counted a
+/// piece of text at a time.
+#[derive(Default)]
+pub struct LineEndingCounts {
+    lf: usize,
+    crlf: usize,
+    cr: usize,
+    /// Whether the text so far ends with a `\r`, which is only
+    /// counted once it is known whether a `\n` follows it.
+    after_cr: bool,
+}
+
+impl LineEndingCounts {
+    /// Takes itself and the next piece of the text of a file.
+    /// Counts the line breaks in the piece.
+    pub fn add(&mut self, text: &str) {
+        for byte in text.bytes() {
+            match byte {
+                b'\n' if self.after_cr => self.crlf += 1,
+                b'\n' => self.lf += 1,
+                _ if self.after_cr => self.cr += 1,
+                _ => (),
+            }
+            self.after_cr = byte == b'\r';
+        }
+    }
+
+    /// Takes itself.
+    /// Returns the line break most of the lines counted so far end
+    /// with, or `\n` if there are none.
+    #[must_use]
+    pub fn line_ending(&self) -> LineEnding {
+        let cr = self.cr + usize::from(self.after_cr);
+        if self.crlf > self.lf && self.crlf >= cr {
+            LineEnding::Crlf
+        } else if cr > self.lf && cr > self.crlf {
+            LineEnding::Cr
+        } else {
+            LineEnding::Lf
+        }
+    }
+}
+
+impl FromStr for LineEnding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unix" | "lf" => Ok(LineEnding::Lf),
+            "dos" | "crlf" => Ok(LineEnding::Crlf),
+            "mac" | "cr" => Ok(LineEnding::Cr),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LineEnding::Lf => write!(f, "unix"),
+            LineEnding::Crlf => write!(f, "dos"),
+            LineEnding::Cr => write!(f, "mac"),
+        }
+    }
+}
+
+/// The character encoding of a file.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    /// UTF-8 starting with a byte order mark.
+    Utf8Bom,
+    /// Little-endian UTF-16, starting with a byte order mark.
+    Utf16Le,
+    /// Big-endian UTF-16, starting with a byte order mark.
+    Utf16Be,
+    /// ISO-8859-1, which every byte sequence is valid in.
+    Latin1,
+}
+
+impl Encoding {
+    /// Takes the start of a file.
+    /// Returns the encoding its byte order mark stands for, or UTF-8
+    /// if it has none. Files that turn out not to be valid in it are
+    /// read as Latin-1 instead, which every byte sequence is valid
+    /// in.
+    #[must_use]
+    pub fn detect(start: &[u8]) -> Self {
+        [Encoding::Utf8Bom, Encoding::Utf16Le, Encoding::Utf16Be]
+            .into_iter()
+            .find(|encoding| start.starts_with(encoding.bom()))
+            .unwrap_or(Encoding::Utf8)
+    }
+
+    /// Takes itself and the contents of a file in the encoding.
+    /// Returns a bool representing whether the contents look like
+    /// binary data rather than text, which is when there is a NUL
+    /// byte near the start of a file that is not UTF-16.
+    #[must_use]
+    pub fn is_binary(self, bytes: &[u8]) -> bool {
+        match self {
+            Encoding::Utf16Le | Encoding::Utf16Be => false,
+            Encoding::Utf8 | Encoding::Utf8Bom | Encoding::Latin1 => {
+                bytes[..bytes.len().min(BINARY_SAMPLE_LEN)].contains(&0)
+            }
+        }
+    }
+
+    /// Takes itself.
+    /// Returns the byte order mark of the encoding, which is empty
+    /// if it has none.
+    #[must_use]
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8Bom => UTF8_BOM,
+            Encoding::Utf16Le => UTF16LE_BOM,
+            Encoding::Utf16Be => UTF16BE_BOM,
+            Encoding::Utf8 | Encoding::Latin1 => &[],
+        }
+    }
+
+    /// Takes itself, some text and the bytes to add it to.
+    /// Adds the text in the encoding to the end of the bytes, so that
+    /// a buffer can be encoded a piece at a time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if the text holds a char that the
+    /// encoding cannot represent.
+    pub fn encode_into(self, text: &str, bytes: &mut Vec<u8>) -> Result<(), String> {
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => bytes.extend_from_slice(text.as_bytes()),
+            Encoding::Utf16Le => bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+            Encoding::Utf16Be => bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+            Encoding::Latin1 => {
+                for c in text.chars() {
+                    let byte =
+                        u8::try_from(c).map_err(|_| format!("Cannot encode {c:?} as {self}"))?;
+                    bytes.push(byte);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "utf-8-bom" | "utf8-bom" => Ok(Encoding::Utf8Bom),
+            "utf-16le" | "utf16le" => Ok(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Ok(Encoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Encoding::Utf8 => write!(f, "utf-8"),
+            Encoding::Utf8Bom => write!(f, "utf-8-bom"),
+            Encoding::Utf16Le => write!(f, "utf-16le"),
+            Encoding::Utf16Be => write!(f, "utf-16be"),
+            Encoding::Latin1 => write!(f, "latin1"),
+        }
+    }
+}
+
+/// Turns the contents of a file into text a piece at a time, so
+/// that a file never has to be held in memory as a whole.
+pub struct Decoder {
+    encoding: Encoding,
+    /// The bytes at the end of the last piece that only make up
+    /// part of a char, or of the byte order mark.
+    partial: Vec<u8>,
+    /// Whether the byte order mark is still to be skipped.
+    at_start: bool,
+}
+
+impl Decoder {
+    /// Takes the encoding of a file.
+    /// Builds a `Decoder` for the start of the file.
+    #[must_use]
+    pub fn new(encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            partial: Vec::new(),
+            at_start: true,
+        }
+    }
+
+    /// Takes itself, the next piece of the contents of the file and
+    /// the text to add it to.
+    /// Adds the text of the piece to the end of the text. A char
+    /// that is split between two pieces is added with the second
+    /// one. Returns a bool representing whether the piece was valid
+    /// in the encoding.
+    pub fn decode(&mut self, bytes: &[u8], text: &mut String) -> bool {
+        let joined;
+        let mut bytes = if self.partial.is_empty() {
+            bytes
+        } else {
+            joined = [mem::take(&mut self.partial).as_slice(), bytes].concat();
+            joined.as_slice()
+        };
+        if self.at_start {
+            let bom = self.encoding.bom();
+            if bytes.len() < bom.len() && bom.starts_with(bytes) {
+                self.partial = bytes.to_vec();
+                return true;
+            }
+            self.at_start = false;
+            bytes = bytes.strip_prefix(bom).unwrap_or(bytes);
+        }
+        match self.encoding {
+            Encoding::Utf8 | Encoding::Utf8Bom => match str::from_utf8(bytes) {
+                Ok(valid) => text.push_str(valid),
+                Err(error) if error.error_len().is_none() => {
+                    let (valid, rest) = bytes.split_at(error.valid_up_to());
+                    text.push_str(str::from_utf8(valid).unwrap_or_default());
+                    self.partial = rest.to_vec();
+                }
+                Err(_) => return false,
+            },
+            Encoding::Utf16Le => return self.decode_utf16(bytes, u16::from_le_bytes, text),
+            Encoding::Utf16Be => return self.decode_utf16(bytes, u16::from_be_bytes, text),
+            Encoding::Latin1 => text.extend(bytes.iter().map(|&byte| char::from(byte))),
+        }
+        true
+    }
+
+    /// Takes itself, a piece of UTF-16 contents, a function that
+    /// reads a code unit from two bytes and the text to add it to.
+    /// Adds the text of the piece to the end of the text, keeping an
+    /// odd byte or a high surrogate at the end for the next piece.
+    /// Returns a bool representing whether the piece was valid.
+    fn decode_utf16(
+        &mut self,
+        bytes: &[u8],
+        from_bytes: fn([u8; 2]) -> u16,
+        text: &mut String,
+    ) -> bool {
+        let mut len = bytes.len() & !1;
+        if len >= 2 && (0xd800..0xdc00).contains(&from_bytes([bytes[len - 2], bytes[len - 1]])) {
+            len -= 2;
+        }
+        let (whole, rest) = bytes.split_at(len);
+        let units = whole
+            .chunks_exact(2)
+            .map(|pair| from_bytes([pair[0], pair[1]]));
+        for c in char::decode_utf16(units) {
+            let Ok(c) = c else {
+                return false;
+            };
+            text.push(c);
+        }
+        self.partial = rest.to_vec();
+        true
+    }
+
+    /// Takes itself.
+    /// Returns a bool representing whether the contents decoded so
+    /// far end with a whole char.
+    #[must_use]
+    pub fn finish(&self) -> bool {
+        self.partial.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ropey::Rope;
+
+    fn encode(encoding: Encoding, text: &str) -> Result<Vec<u8>, String> {
+        let mut bytes = encoding.bom().to_vec();
+        encoding.encode_into(text, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Decodes the bytes a piece of the given length at a time, as
+    /// a file is read, or returns `None` if they are not valid in
+    /// the encoding they start like.
+    fn decode_in_pieces(bytes: &[u8], piece_len: usize) -> Option<(String, Encoding)> {
+        let encoding = Encoding::detect(bytes);
+        let mut decoder = Decoder::new(encoding);
+        let mut text = String::new();
+        for piece in bytes.chunks(piece_len) {
+            if !decoder.decode(piece, &mut text) {
+                return None;
+            }
+        }
+        decoder.finish().then_some((text, encoding))
+    }
+
+    fn decode(bytes: &[u8]) -> (String, Encoding) {
+        decode_in_pieces(bytes, bytes.len().max(1)).unwrap_or_else(|| {
+            let mut text = String::new();
+            Decoder::new(Encoding::Latin1).decode(bytes, &mut text);
+            (text, Encoding::Latin1)
+        })
+    }
+
+    fn round_trip(bytes: &[u8]) -> (String, Encoding) {
+        let (text, encoding) = decode(bytes);
+        assert_eq!(encode(encoding, &text).as_deref(), Ok(bytes));
+        (text, encoding)
+    }
+
+    fn detect_line_ending(text: &str) -> LineEnding {
+        let mut counts = LineEndingCounts::default();
+        counts.add(text);
+        counts.line_ending()
+    }
+
+    #[test]
+    fn decode_and_encode_each_encoding() {
+        let text = String::from("aé\n");
+        assert!(round_trip("aé\n".as_bytes()) == (text.clone(), Encoding::Utf8));
+        assert!(round_trip(b"\xef\xbb\xbfa\xc3\xa9\n") == (text.clone(), Encoding::Utf8Bom));
+        assert!(round_trip(b"\xff\xfea\x00\xe9\x00\n\x00") == (text.clone(), Encoding::Utf16Le));
+        assert!(round_trip(b"\xfe\xff\x00a\x00\xe9\x00\n") == (text.clone(), Encoding::Utf16Be));
+        assert!(round_trip(b"a\xe9\n") == (text, Encoding::Latin1));
+    }
+
+    #[test]
+    fn invalid_utf16_falls_back_to_latin1() {
+        let (_, encoding) = decode(b"\xff\xfea");
+        assert!(encoding == Encoding::Latin1);
+        let (_, encoding) = decode(b"\xff\xfe\x00\xd8");
+        assert!(encoding == Encoding::Latin1);
+    }
+
+    #[test]
+    fn latin1_cannot_encode_other_chars() {
+        assert!(encode(Encoding::Latin1, "€").is_err());
+        assert_eq!(encode(Encoding::Latin1, "ÿ"), Ok(vec![0xff]));
+    }
+
+    #[test]
+    fn only_line_feeds_break_lines() {
+        let (text, encoding) = decode(b"a\x85b\x0bc\n");
+        assert!(encoding == Encoding::Latin1);
+        assert_eq!(Rope::from_str(&text).len_lines(), 2);
+        assert_eq!(Rope::from_str("a\rb\u{2028}c\n").len_lines(), 2);
+    }
+
+    #[test]
+    fn binary_files_have_a_nul_near_the_start() {
+        assert!(Encoding::Utf8.is_binary(b"ab\0c"));
+        assert!(!Encoding::Utf8.is_binary(b"abc"));
+        assert!(!Encoding::Utf16Le.is_binary(b"a\0b\0"));
+    }
+
+    #[test]
+    fn detect_the_most_common_line_ending() {
+        assert!(detect_line_ending("a\nb\r\nc\r\n") == LineEnding::Crlf);
+        assert!(detect_line_ending("a\rb\rc\n") == LineEnding::Cr);
+        assert!(detect_line_ending("a\nb\r\n") == LineEnding::Lf);
+        assert!(detect_line_ending("abc") == LineEnding::Lf);
+    }
+
+    #[test]
+    fn normalize_and_apply_line_endings() {
+        for (line_ending, text) in [
+            (LineEnding::Lf, "a\nb\n"),
+            (LineEnding::Crlf, "a\r\nb\r\n"),
+            (LineEnding::Cr, "a\rb\r"),
+        ] {
+            assert!(detect_line_ending(text) == line_ending);
+            let normalized = line_ending.normalize(Cow::Borrowed(text));
+            assert_eq!(normalized, "a\nb\n");
+            assert_eq!(line_ending.apply(&normalized), text);
+        }
+        assert_eq!(
+            LineEnding::Crlf.normalize(Cow::Borrowed("a\rb\r\n")),
+            "a\rb\n"
+        );
+    }
+
+    #[test]
+    fn chars_split_between_pieces_are_decoded_whole() {
+        let text = "aé€😀\n";
+        for encoding in [
+            Encoding::Utf8,
+            Encoding::Utf8Bom,
+            Encoding::Utf16Le,
+            Encoding::Utf16Be,
+        ] {
+            let bytes = encode(encoding, text).unwrap();
+            for piece_len in 1..bytes.len() {
+                let decoded = decode_in_pieces(&bytes, piece_len);
+                assert!(decoded == Some((String::from(text), encoding)));
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_bytes_in_a_later_piece_are_found() {
+        assert!(decode_in_pieces(b"abc\xc3\xa9\xff", 2).is_none());
+        assert!(decode_in_pieces(b"abc\xc3", 2).is_none());
+        assert!(decode_in_pieces(b"\xfe\xff\x00a\xdc\x00", 3).is_none());
+    }
+
+    #[test]
+    fn line_endings_split_between_pieces_are_counted_once() {
+        let mut counts = LineEndingCounts::default();
+        for piece in ["a\r", "\nb\r", "\nc\r", "\r", "\n"] {
+            counts.add(piece);
+        }
+        assert!(counts.line_ending() == LineEnding::Crlf);
+        assert_eq!((counts.lf, counts.crlf, counts.cr), (0, 3, 1));
+        counts.add("\r\r\r");
+        assert!(counts.line_ending() == LineEnding::Cr);
+    }
+}
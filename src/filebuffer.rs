@@ -1,34 +1,110 @@
 #![warn(clippy::all, clippy::pedantic)]
-use crate::Size;
+use crate::{
+    command::Substitution,
+    config::BufferOptions,
+    disk::{self, DiskChange, Stamp},
+    encoding::{Decoder, Encoding, LineEnding, LineEndingCounts},
+    grapheme::{self, line_width, Grapheme, Wrap},
+    highlight::{Highlighter, Language, Span},
+    history::{Edit, History},
+    movement::{self, Motion},
+    save, search, Size,
+};
 use crossterm::event::KeyCode;
-use ropey::{Rope, RopeSlice};
+use regex::Regex;
+use ropey::{Rope, RopeBuilder, RopeSlice};
 use std::{
+    borrow::Cow,
     cmp,
-    fs::File,
-    io::{BufReader, BufWriter},
+    io::ErrorKind,
+    iter, mem,
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
 };
+use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Default)]
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
 pub struct Position {
     pub x: usize,
     pub x_preferred: usize,
     pub y: usize,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Copy)]
 pub struct Selection {
     pub anchor: Position,
     pub cursor: Position,
 }
 
+/// The selections and scroll offset of a `FileBuffer`, kept by
+/// each window that shows it.
+#[derive(Clone)]
+pub struct View {
+    pub selections: Vec<Selection>,
+    pub primary_selection_idx: usize,
+    pub offset: Position,
+    pub wrap_offset: usize,
+}
+
+/// A row of a buffer, or the part of one, shown on a line of a
+/// window.
+pub struct ScreenLine {
+    pub row_idx: usize,
+    /// Whether the line continues a row wrapped onto several lines.
+    pub continuation: bool,
+    /// The screen columns of the row shown on the line.
+    pub columns: Range<usize>,
+}
+
+/// The indentation style of a file, as detected when it is opened.
+#[derive(Clone, Copy)]
+enum Indent {
+    Tabs,
+    Spaces(usize),
+}
+
+/// How many lines are looked at to detect the indentation style.
+const INDENT_SAMPLE_LINES: usize = 1000;
+
+/// The widest indentation in spaces that is detected.
+const MAX_INDENT_WIDTH: usize = 8;
+
+/// How many times a file is read at most while its encoding and
+/// line break are worked out.
+const MAX_READS: usize = 3;
+
 pub struct FileBuffer {
     file_contents: Rope,
-    pub file_path: Option<String>,
+    pub file_path: Option<PathBuf>,
     pub buffer_is_empty: bool,
     pub file_is_dirty: bool,
+    /// Whether the file looked like binary data when it was read.
+    pub binary: bool,
     pub selections: Vec<Selection>,
     pub primary_selection_idx: usize,
     pub offset: Position,
+    /// The number of screen lines of the top row that are scrolled
+    /// out of view when soft wrap is on.
+    pub wrap_offset: usize,
+    pub visual_lines: Option<(usize, usize)>,
+    history: History,
+    highlighter: Option<Highlighter>,
+    pub options: BufferOptions,
+    detected_indent: Option<Indent>,
+    /// The encoding and line break the file was read with.
+    file_format: Option<(Encoding, LineEnding)>,
+    /// The file as it was when the buffer last read or wrote it, or
+    /// `None` if it did not exist.
+    disk_stamp: Option<Stamp>,
+    /// A change to the file on disk that has been noticed but not
+    /// yet dealt with by reloading or saving.
+    disk_change: Option<DiskChange>,
+    /// The contents as they were before the edits in `changes`.
+    changes_base: Option<Rope>,
+    /// The edits made since the views that other windows keep of the
+    /// buffer were last mapped through them, as the char range that
+    /// was replaced and the number of chars that replaced it.
+    changes: Vec<(Range<usize>, usize)>,
 }
 
 impl Default for FileBuffer {
@@ -40,15 +116,27 @@ impl Default for FileBuffer {
             file_path: None,
             buffer_is_empty: true,
             file_is_dirty: false,
+            binary: false,
             selections: vec![Selection::default()],
             primary_selection_idx: 0,
             offset: Position::default(),
+            wrap_offset: 0,
+            visual_lines: None,
+            history: History::default(),
+            highlighter: None,
+            options: BufferOptions::default(),
+            detected_indent: None,
+            file_format: None,
+            disk_stamp: None,
+            disk_change: None,
+            changes_base: None,
+            changes: Vec::new(),
         }
     }
 }
 
 impl FileBuffer {
-    /// Takes a string slice represinting a file path.
+    /// Takes a file path.
     /// Builds a `FileBuffer` from the contents of the
     /// file at the given path, if one exists.
     ///
@@ -56,23 +144,63 @@ impl FileBuffer {
     ///
     /// Will return an error if the file cannot be read,
     /// or if a rope cannot be created from the file.
-    pub fn open(file_path: &str) -> Result<Self, std::io::Error> {
-        let file_contents = Rope::from_reader(BufReader::new(File::open(file_path)?))?;
+    pub fn open(file_path: &Path) -> Result<Self, std::io::Error> {
+        let file = read_file(file_path)?;
         Ok(Self {
+            file_format: Some(file.file_format),
+            disk_stamp: Some(file.stamp),
+            binary: file.binary,
             // buffer_has_content: true,
-            file_contents,
-            file_path: Some(file_path.to_string()),
+            detected_indent: detect_indent(&file.contents),
+            file_contents: file.contents,
+            file_path: Some(file_path.to_path_buf()),
             buffer_is_empty: false,
             selections: vec![Selection::default()],
+            highlighter: Language::from_path(file_path).map(Highlighter::new),
             ..Default::default()
         })
     }
 
+    /// Takes a file path.
+    /// Builds an empty `FileBuffer` that will be saved to the
+    /// given path, for files that do not exist yet.
+    #[must_use]
+    pub fn with_path(file_path: &Path) -> Self {
+        Self {
+            file_path: Some(file_path.to_path_buf()),
+            highlighter: Language::from_path(file_path).map(Highlighter::new),
+            ..Default::default()
+        }
+    }
+
+    /// Takes itself and a file path.
+    /// Makes the buffer save to the new path, highlighting it for
+    /// the language of the path. Nothing changes when the buffer
+    /// already saves to the path.
+    pub fn set_file_path(&mut self, file_path: &Path) {
+        if self.file_path.as_deref() == Some(file_path) {
+            return;
+        }
+        self.file_path = Some(file_path.to_path_buf());
+        self.highlighter = Language::from_path(file_path).map(Highlighter::new);
+        self.disk_stamp = None;
+        self.disk_change = None;
+    }
+
+    /// Takes itself.
+    /// Returns the name to show for the buffer.
+    #[must_use]
+    pub fn name(&self) -> Cow<'_, str> {
+        self.file_path
+            .as_deref()
+            .map_or(Cow::Borrowed("[No Name]"), Path::to_string_lossy)
+    }
+
     /// Takes itself and a usize representing the index of a row.
     /// Returns a `RopeSlice` wrapped in an Option if there is a
     /// row with the given index. Otherwise, returns None.
     #[must_use]
-    pub fn row(&self, index: usize) -> Option<RopeSlice> {
+    pub fn row(&self, index: usize) -> Option<RopeSlice<'_>> {
         self.file_contents.get_line(index)
     }
 
@@ -90,260 +218,1938 @@ impl FileBuffer {
         self.file_contents.len_lines() == 0
     }
 
+    /// Takes itself and the options for the buffer.
+    /// Sets the options, then applies the encoding and line break of
+    /// the file, and the indentation style found in it if they ask
+    /// for it to be detected.
+    pub fn set_options(&mut self, options: BufferOptions) {
+        self.options = options;
+        if let Some((encoding, fileformat)) = self.file_format {
+            self.options.encoding = encoding;
+            self.options.fileformat = fileformat;
+        }
+        if self.is_large() {
+            self.options.soft_wrap = false;
+        }
+        self.apply_detected_indent();
+    }
+
+    /// Takes itself.
+    /// Returns a bool representing whether the file is larger than
+    /// `large_file_size`, which turns off the features that slow
+    /// down with the size of the file.
+    #[must_use]
+    pub fn is_large(&self) -> bool {
+        let limit = u64::try_from(self.options.large_file_size)
+            .unwrap_or(u64::MAX)
+            .saturating_mul(1 << 20);
+        self.disk_stamp.is_some_and(|stamp| stamp.len() > limit)
+    }
+
+    /// Takes itself.
+    /// Returns a warning to show when the buffer is opened, if its
+    /// file is binary or large.
+    #[must_use]
+    pub fn open_warning(&self) -> Option<String> {
+        let name = self.name();
+        if self.binary {
+            Some(format!(
+                "\"{name}\" looks binary: highlighting is off and bytes are kept as they are"
+            ))
+        } else if self.is_large() {
+            Some(format!(
+                "\"{name}\" is large: highlighting, soft wrap and indent detection are off"
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Takes itself.
+    /// Sets the indentation options to the style found in the file
+    /// when it was opened, unless `detect_indent` is turned off.
+    pub fn apply_detected_indent(&mut self) {
+        if !self.options.detect_indent || self.is_large() {
+            return;
+        }
+        match self.detected_indent {
+            Some(Indent::Tabs) => self.options.expand_tabs = false,
+            Some(Indent::Spaces(width)) => {
+                self.options.expand_tabs = true;
+                self.options.indent_width = width;
+            }
+            None => (),
+        }
+    }
+
     /// Takes itself and a char.
-    /// Inserts the char into the file at the given position.
+    /// Inserts the char into the file at each cursor.
     pub fn insert(&mut self, c: char) {
-        for i in 0..self.selections.len() {
-            let char_pos = self.file_contents.line_to_char(self.selections[i].cursor.y)
-                + self.selections[i].cursor.x;
-            if self.selections[i].cursor.y == self.len() {
-                self.file_contents.insert(char_pos, &'\n'.to_string()[..]);
-            }
-            self.file_contents.insert(char_pos, &c.to_string()[..]);
-            if c == '\n' {
-                self.selections[i].cursor.x = 0;
-                self.selections[i].cursor.y = self.selections[i].cursor.y.saturating_add(1);
+        self.insert_at_cursors(|_, _| c.to_string());
+    }
+
+    /// Takes itself.
+    /// Inserts a tab at each cursor, or spaces up to the next
+    /// multiple of the indent width when tabs are expanded.
+    pub fn insert_indent(&mut self) {
+        self.insert_at_cursors(|file_buffer, char_idx| {
+            let BufferOptions {
+                indent_width,
+                expand_tabs,
+                tab_width,
+                ..
+            } = file_buffer.options;
+            if expand_tabs {
+                let line_idx = file_buffer.file_contents.char_to_line(char_idx);
+                let line_start = file_buffer.file_contents.line_to_char(line_idx);
+                let column = grapheme::column(
+                    file_buffer.file_contents.line(line_idx),
+                    char_idx.saturating_sub(line_start),
+                    tab_width,
+                );
+                " ".repeat(indent_width - column % indent_width)
             } else {
-                self.selections[i].cursor.x = self.selections[i].cursor.x.saturating_add(1);
+                String::from("\t")
             }
-            for j in i + 1..self.selections.len() {
-                if self.selections[j].cursor.y == self.selections[i].cursor.y
-                    && self.selections[j].cursor.x > self.selections[i].cursor.x
-                {
-                    self.selections[j].cursor.x = self.selections[j].cursor.x.saturating_add(1);
-                    self.selections[j].cursor.x_preferred =
-                        self.selections[j].cursor.x_preferred.saturating_add(1);
+        });
+    }
+
+    /// Takes itself and a function that returns the text to insert
+    /// at a char index.
+    /// Inserts the text at each cursor, moving the selections
+    /// after it along.
+    fn insert_at_cursors(&mut self, text_at: impl Fn(&Self, usize) -> String) {
+        let mut indices = self.selection_char_indices();
+        for i in 0..indices.len() {
+            let char_pos = indices[i].1;
+            let text = text_at(self, char_pos);
+            let len = text.chars().count();
+            self.insert_text(char_pos, &text);
+            for (anchor, cursor) in &mut indices {
+                if *anchor >= char_pos {
+                    *anchor = anchor.saturating_add(len);
                 }
-                if self.selections[j].anchor.y == self.selections[i].cursor.y
-                    && self.selections[j].anchor.x > self.selections[i].cursor.x
-                {
-                    self.selections[j].anchor.x = self.selections[j].anchor.x.saturating_add(1);
+                if *cursor >= char_pos {
+                    *cursor = cursor.saturating_add(len);
                 }
             }
         }
+        self.set_selection_char_indices(&indices);
         self.buffer_is_empty = false;
         self.file_is_dirty = true;
     }
 
-    /// Takes itself and the position of the cursor.
-    /// Deletes the character under the cursor.
+    /// Takes itself and whether the key pressed was backspace.
+    /// Deletes the grapheme cluster under each cursor, or the one
+    /// before it when `backspace` is true.
     pub fn delete(&mut self, backspace: bool) {
-        for i in 0..self.selections.len() {
-            if (self.selections[i].cursor.y >= self.len() && !backspace)
-                || (self.selections[i].cursor.x == 0
-                    && self.selections[i].cursor.y == 0
-                    && backspace)
-            {
-                return;
-            }
-            let mut char_pos = self.file_contents.line_to_char(self.selections[i].cursor.y)
-                + self.selections[i].cursor.x;
-            if backspace {
-                char_pos = char_pos.saturating_sub(1);
-            };
-            let newline_deleted = self.file_contents.slice(char_pos..=char_pos).eq("\n");
-            if newline_deleted && backspace {
-                self.selections[i].cursor.y = self.selections[i].cursor.y.saturating_sub(1);
-                self.selections[i].cursor.x =
-                    if let Some(row) = self.row(self.selections[i].cursor.y) {
-                        row.len_chars().saturating_sub(1)
-                    } else {
-                        0
-                    }
-            } else if backspace {
-                self.selections[i].cursor.x = self.selections[i].cursor.x.saturating_sub(1);
-            }
-            self.file_contents.remove(char_pos..=char_pos);
-            for j in i + 1..self.selections.len() {
-                if self.selections[j].cursor.y == self.selections[i].cursor.y
-                    && self.selections[j].cursor.x > self.selections[i].cursor.x
-                {
-                    self.selections[j].cursor.x -= 1;
+        let mut indices = self.selection_char_indices();
+        for i in 0..indices.len() {
+            let cursor = indices[i].1;
+            let range = if backspace {
+                if cursor == 0 {
+                    continue;
                 }
-                if self.selections[j].anchor.y == self.selections[i].cursor.y
-                    && self.selections[j].anchor.x > self.selections[i].cursor.x
-                {
-                    self.selections[j].anchor.x -= 1;
+                grapheme::prev_boundary(&self.file_contents, cursor)..cursor
+            } else {
+                if cursor >= self.file_contents.len_chars() {
+                    continue;
                 }
+                cursor..grapheme::next_boundary(&self.file_contents, cursor)
+            };
+            self.remove_text(range.clone());
+            let shift = |char_idx: &mut usize| {
+                if *char_idx >= range.end {
+                    *char_idx -= range.len();
+                } else if *char_idx > range.start {
+                    *char_idx = range.start;
+                }
+            };
+            for (anchor, cursor) in &mut indices {
+                shift(anchor);
+                shift(cursor);
             }
         }
+        self.set_selection_char_indices(&indices);
         self.file_is_dirty = true;
     }
 
     /// Takes itself.
-    /// Writes the contents to the file path, if it exists.
+    /// Returns the `(anchor, cursor)` char indices of every selection.
+    fn selection_char_indices(&self) -> Vec<(usize, usize)> {
+        self.selections
+            .iter()
+            .map(|selection| {
+                (
+                    self.pos_to_char_idx(&selection.anchor),
+                    self.pos_to_char_idx(&selection.cursor),
+                )
+            })
+            .collect()
+    }
+
+    /// Takes itself and the `(anchor, cursor)` char indices of every selection.
+    /// Moves the selections to the given char indices, merging any
+    /// that now overlap.
+    fn set_selection_char_indices(&mut self, indices: &[(usize, usize)]) {
+        for (i, (anchor, cursor)) in indices.iter().enumerate() {
+            self.selections[i] = Selection {
+                anchor: self.char_idx_to_pos(*anchor),
+                cursor: self.char_idx_to_pos(*cursor),
+            };
+        }
+        self.merge_selections();
+    }
+
+    /// Takes itself and an optional range of lines.
+    /// Returns the text of the lines, or of the whole buffer.
+    #[must_use]
+    pub fn text(&self, lines: Option<RangeInclusive<usize>>) -> RopeSlice<'_> {
+        match lines {
+            Some(lines) => {
+                let start = self.file_contents.line_to_char(*lines.start());
+                let end = self.file_contents.line_to_char(lines.end() + 1);
+                self.file_contents.slice(start..end)
+            }
+            None => self.file_contents.slice(..),
+        }
+    }
+
+    /// Takes itself, an optional range of lines and whether to start
+    /// with a byte order mark.
+    /// Returns the text of the lines, or of the whole buffer, with
+    /// the line break and in the encoding of the file. The text is
+    /// encoded straight from the rope, a chunk at a time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if the text cannot be represented in
+    /// the encoding.
+    pub fn encode(
+        &self,
+        lines: Option<RangeInclusive<usize>>,
+        bom: bool,
+    ) -> Result<Vec<u8>, String> {
+        let text = self.text(lines);
+        let encoding = self.options.encoding;
+        let mut bytes = Vec::with_capacity(text.len_bytes() + 3);
+        if bom {
+            bytes.extend_from_slice(encoding.bom());
+        }
+        for chunk in text.chunks() {
+            encoding.encode_into(&self.options.fileformat.apply(chunk), &mut bytes)?;
+        }
+        Ok(bytes)
+    }
+
+    /// Takes itself, whether to keep a backup of the file and
+    /// whether to overwrite changes made to it on disk.
+    /// Writes the contents to the file path, replacing the file
+    /// only once they have all been written. Returns the number of
+    /// bytes written and whether the file was replaced atomically.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if the buffer has no file path, the
+    /// file has changed on disk since it was read and the save is
+    /// not forced, or the contents cannot be encoded or written.
+    pub fn save(&mut self, backup: bool, force: bool) -> Result<(usize, bool), String> {
+        self.commit();
+        let Some(path) = self.file_path.clone() else {
+            return Err(String::from("No file name"));
+        };
+        let path = path.as_path();
+        if !force && disk::check(&mut self.disk_stamp, path) == DiskChange::Modified {
+            return Err(format!(
+                "\"{}\" has changed on disk since it was read (add ! to overwrite)",
+                path.display()
+            ));
+        }
+        let contents = self.encode(None, true)?;
+        let atomic = save::write_atomically(path, &contents, backup)
+            .map_err(|error| format!("Cannot write \"{}\": {error}", path.display()))?;
+        self.disk_stamp = Stamp::read(path).ok();
+        self.disk_change = None;
+        self.file_format = Some((self.options.encoding, self.options.fileformat));
+        self.file_is_dirty = false;
+        self.history.mark_saved();
+        Ok((contents.len(), atomic))
+    }
+
+    /// Takes itself, the lines to write, the path to write them to
+    /// and whether to keep a backup of the file.
+    /// Writes the lines to the path without saving the buffer. When
+    /// the path is the buffer's own file, the new contents on disk
+    /// are remembered, so they are not taken for a change made
+    /// elsewhere. Returns the number of bytes written and whether
+    /// the file was replaced atomically.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if the lines cannot be encoded or
+    /// written.
+    pub fn write_lines(
+        &mut self,
+        lines: Option<RangeInclusive<usize>>,
+        path: &Path,
+        backup: bool,
+    ) -> Result<(usize, bool), String> {
+        let contents = self.encode(lines, true)?;
+        let atomic = save::write_atomically(path, &contents, backup)
+            .map_err(|error| format!("Cannot write \"{}\": {error}", path.display()))?;
+        if self.file_path.as_deref() == Some(path) {
+            self.disk_stamp = Stamp::read(path).ok();
+            self.disk_change = None;
+        }
+        Ok((contents.len(), atomic))
+    }
+
+    /// Takes itself.
+    /// Returns how the file has changed on disk, the first time the
+    /// change is noticed. Changes that were already noticed are not
+    /// looked for again until the buffer is reloaded or saved.
+    pub fn poll_disk(&mut self) -> Option<DiskChange> {
+        if self.disk_change.is_some() {
+            return None;
+        }
+        let file_path = self.file_path.as_ref()?;
+        match disk::check(&mut self.disk_stamp, file_path) {
+            DiskChange::Unchanged => None,
+            change => {
+                self.disk_change = Some(change);
+                Some(change)
+            }
+        }
+    }
+
+    /// Takes itself.
+    /// Replaces the contents with those of the file on disk as a
+    /// single revision, so that the reload can be undone, and marks
+    /// the buffer as saved. Large files are not kept in the history,
+    /// so reloading one clears it instead. Returns the number of
+    /// bytes read.
     ///
     /// # Errors
     ///
-    /// Will return an error if the file cannot be opened
-    /// or created, or if the rope cannot be written to it.
-    pub fn save(&mut self) -> Result<(), std::io::Error> {
-        if let Some(file_name) = &self.file_path {
-            self.file_contents
-                .write_to(BufWriter::new(File::create(file_name)?))?;
-            self.file_is_dirty = false;
-            Ok(())
+    /// Returns an error if the buffer has no file path, or the file
+    /// cannot be read.
+    pub fn reload(&mut self) -> Result<usize, std::io::Error> {
+        let Some(file_path) = &self.file_path else {
+            return Err(std::io::Error::new(ErrorKind::NotFound, "No file name"));
+        };
+        let file = read_file(file_path)?;
+        self.commit();
+        let view = self.view();
+        let was_large = self.is_large();
+        self.disk_stamp = Some(file.stamp);
+        let len_chars = self.file_contents.len_chars();
+        if was_large || self.is_large() {
+            self.record_change(0..len_chars, file.contents.len_chars());
+            self.invalidate_highlights(0);
+            self.file_contents = file.contents;
+            self.history = History::default();
         } else {
-            // FIXME
-            self.file_contents
-                .write_to(BufWriter::new(File::create("")?))?;
-            Ok(())
+            let text = file.contents.to_string();
+            if len_chars > 0 {
+                self.remove_text(0..len_chars);
+            }
+            if !text.is_empty() {
+                self.insert_text(0, &text);
+            }
         }
+        self.set_view(&view);
+        self.commit();
+        self.disk_change = None;
+        self.detected_indent = detect_indent(&self.file_contents);
+        self.file_format = Some(file.file_format);
+        (self.options.encoding, self.options.fileformat) = file.file_format;
+        self.binary = file.binary;
+        self.buffer_is_empty = false;
+        self.file_is_dirty = false;
+        self.history.mark_saved();
+        Ok(usize::try_from(file.stamp.len()).unwrap_or(usize::MAX))
+    }
+
+    /// Takes itself, a char index and a string slice.
+    /// Inserts the text into the rope and records the edit
+    /// in the pending transaction.
+    fn insert_text(&mut self, char_idx: usize, text: &str) {
+        self.history.record(
+            Edit::Insert {
+                char_idx,
+                text: text.to_string(),
+            },
+            &self.selections,
+            self.primary_selection_idx,
+        );
+        self.invalidate_highlights(char_idx);
+        self.record_change(char_idx..char_idx, text.chars().count());
+        self.file_contents.insert(char_idx, text);
+    }
+
+    /// Takes itself and a range of char indices.
+    /// Removes the text in the range from the rope and records
+    /// the edit in the pending transaction.
+    fn remove_text(&mut self, range: Range<usize>) {
+        self.history.record(
+            Edit::Remove {
+                char_idx: range.start,
+                text: self.file_contents.slice(range.clone()).to_string(),
+            },
+            &self.selections,
+            self.primary_selection_idx,
+        );
+        self.invalidate_highlights(range.start);
+        self.record_change(range.clone(), 0);
+        self.file_contents.remove(range);
+    }
+
+    /// Takes itself, the range of chars an edit replaces and the
+    /// number of chars it replaces them with.
+    /// Remembers the edit, so that the views of other windows can
+    /// be mapped through it.
+    fn record_change(&mut self, range: Range<usize>, len: usize) {
+        if self.changes_base.is_none() {
+            self.changes_base = Some(self.file_contents.clone());
+        }
+        push_change(&mut self.changes, range, len);
+    }
+
+    /// Takes itself and the views that other windows keep of the
+    /// buffer.
+    /// Moves the selections and scroll offset of each view along
+    /// with the edits made since the views were last mapped, so that
+    /// they stay on the same text, and forgets those edits.
+    pub fn map_views<'a>(&mut self, views: impl IntoIterator<Item = &'a mut View>) {
+        let Some(base) = self.changes_base.take() else {
+            return;
+        };
+        let changes = mem::take(&mut self.changes);
+        let map = |pos: Position| {
+            let y = cmp::min(pos.y, base.len_lines().saturating_sub(1));
+            let x = cmp::min(pos.x, base.line(y).len_chars());
+            let mut char_idx = base.line_to_char(y) + x;
+            for (range, len) in &changes {
+                if char_idx >= range.end {
+                    char_idx = char_idx - range.len() + len;
+                } else if char_idx > range.start {
+                    // Text that was replaced keeps its place in the
+                    // text that replaced it, as far as it reaches.
+                    char_idx = range.start + cmp::min(char_idx - range.start, *len);
+                }
+            }
+            self.char_idx_to_pos(char_idx)
+        };
+        for view in views {
+            for selection in &mut view.selections {
+                selection.anchor = map(selection.anchor);
+                selection.cursor = map(selection.cursor);
+            }
+            view.offset.y = map(Position {
+                x: 0,
+                x_preferred: 0,
+                y: view.offset.y,
+            })
+            .y;
+        }
+    }
+
+    /// Takes itself and the char index of an edit.
+    /// Makes the highlighter forget the lines after the edit.
+    fn invalidate_highlights(&mut self, char_idx: usize) {
+        if let Some(highlighter) = &mut self.highlighter {
+            let char_idx = cmp::min(char_idx, self.file_contents.len_chars());
+            highlighter.invalidate(self.file_contents.char_to_line(char_idx));
+        }
+    }
+
+    /// Takes itself and the index of a row.
+    /// Brings the highlighting up to date for every row up to the
+    /// given one.
+    pub fn update_highlights(&mut self, row_idx: usize) {
+        if !self.is_highlighted() {
+            return;
+        }
+        if let Some(highlighter) = &mut self.highlighter {
+            highlighter.update(&self.file_contents, row_idx);
+        }
+    }
+
+    /// Takes itself and the index of a row.
+    /// Returns the highlighted spans of the row.
+    #[must_use]
+    pub fn highlight_spans(&self, row_idx: usize) -> Vec<Span> {
+        if !self.is_highlighted() {
+            return Vec::new();
+        }
+        self.highlighter
+            .as_ref()
+            .map_or_else(Vec::new, |highlighter| {
+                highlighter.spans(&self.file_contents, row_idx)
+            })
+    }
+
+    /// Takes itself.
+    /// Returns a bool representing whether the buffer is highlighted,
+    /// which binary and large files are not.
+    fn is_highlighted(&self) -> bool {
+        !self.binary && !self.is_large()
+    }
+
+    /// Takes itself.
+    /// Returns the language of the buffer, if it is highlighted.
+    #[must_use]
+    pub fn language(&self) -> Option<Language> {
+        self.highlighter.as_ref().map(Highlighter::language)
+    }
+
+    /// Takes itself.
+    /// Ends the pending transaction, so that the edits made
+    /// since the last commit are undone as a single revision.
+    pub fn commit(&mut self) {
+        self.history
+            .commit(&self.selections, self.primary_selection_idx);
+    }
+
+    /// Takes itself.
+    /// Reverts the most recent revision. Returns a bool
+    /// representing whether there was anything to undo.
+    pub fn undo(&mut self) -> bool {
+        self.commit();
+        if let Some(char_idx) = self.history.undo_start() {
+            self.invalidate_highlights(char_idx);
+        }
+        let base = self.file_contents.clone();
+        let changes = &mut self.changes;
+        if let Some((selections, primary_selection_idx)) =
+            self.history.undo(&mut self.file_contents, |range, len| {
+                push_change(changes, range, len);
+            })
+        {
+            self.changes_base.get_or_insert(base);
+            self.restore_selections(selections, primary_selection_idx);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Takes itself.
+    /// Reapplies the most recently undone revision. Returns a
+    /// bool representing whether there was anything to redo.
+    pub fn redo(&mut self) -> bool {
+        if self.history.has_pending() {
+            return false;
+        }
+        if let Some(char_idx) = self.history.redo_start() {
+            self.invalidate_highlights(char_idx);
+        }
+        let base = self.file_contents.clone();
+        let changes = &mut self.changes;
+        if let Some((selections, primary_selection_idx)) =
+            self.history.redo(&mut self.file_contents, |range, len| {
+                push_change(changes, range, len);
+            })
+        {
+            self.changes_base.get_or_insert(base);
+            self.restore_selections(selections, primary_selection_idx);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Takes itself and the selections recorded with a revision.
+    /// Replaces the current selections and updates the buffer state
+    /// to match the restored contents.
+    fn restore_selections(&mut self, selections: Vec<Selection>, primary_selection_idx: usize) {
+        self.selections = selections;
+        self.primary_selection_idx = primary_selection_idx;
+        self.file_is_dirty = !self.history.is_at_saved();
+        self.buffer_is_empty = self.file_contents.len_chars() == 0 && self.file_path.is_none();
     }
 
     /// Takes itself.
-    /// Sets cursor `x` pos based on cursor `x_preferred` pos
-    /// and row width.
+    /// Sets cursor `x` pos to the grapheme cluster drawn at the
+    /// screen column `x_preferred`, or the last one in the row.
     fn update_cursors_x_pos(&mut self) {
         for i in 0..self.selections.len() {
-            self.selections[i].cursor.x = if let Some(row) = self.row(self.selections[i].cursor.y) {
-                cmp::min(
+            self.selections[i].cursor.x = self.row(self.selections[i].cursor.y).map_or(0, |row| {
+                grapheme::char_idx_at_column(
+                    row,
                     self.selections[i].cursor.x_preferred,
-                    row.len_chars().saturating_sub(1),
+                    self.options.tab_width,
                 )
-            } else {
-                0
-            }
+            });
         }
     }
 
-    /// Takes itself and the key entered.
-    /// Moves each cursor if possible.
-    pub fn move_cursors(&mut self, key_code: KeyCode) {
-        match key_code {
-            KeyCode::Up => {
+    /// Takes itself, the key entered, whether to extend the
+    /// selections and how rows are wrapped.
+    /// Moves each cursor if possible, by screen lines when moving
+    /// vertically with soft wrap on. Unless extending, each anchor
+    /// follows its cursor.
+    pub fn move_cursors(&mut self, key_code: KeyCode, extend: bool, wrap: Option<Wrap>) {
+        match (key_code, wrap) {
+            (KeyCode::Up | KeyCode::Down, Some(wrap)) => {
+                self.move_cursors_by_screen_line(key_code == KeyCode::Up, wrap);
+            }
+            (KeyCode::Up, None) => {
                 for i in 0..self.selections.len() {
                     self.selections[i].cursor.y = self.selections[i].cursor.y.saturating_sub(1);
                 }
+                self.update_cursors_x_pos();
             }
-            KeyCode::Down => {
+            (KeyCode::Down, None) => {
                 for i in 0..self.selections.len() {
                     if self.selections[i].cursor.y < self.len().saturating_sub(1) {
                         self.selections[i].cursor.y = self.selections[i].cursor.y.saturating_add(1);
                     }
                 }
+                self.update_cursors_x_pos();
             }
-            KeyCode::Left => {
+            (KeyCode::Left | KeyCode::Right, _) => {
                 for i in 0..self.selections.len() {
+                    let Some(row) = self.row(self.selections[i].cursor.y) else {
+                        continue;
+                    };
+                    let x = self.selections[i].cursor.x;
+                    let tab_width = self.options.tab_width;
+                    let last = grapheme::start_of(row, row.len_chars().saturating_sub(1));
+                    let current = cmp::min(grapheme::start_of(row, x), last);
+                    let target = if key_code == KeyCode::Left {
+                        grapheme::start_of(row, current.saturating_sub(1))
+                    } else {
+                        grapheme::grapheme_at(row, current, tab_width)
+                            .map_or(current, |grapheme| cmp::min(grapheme.end, last))
+                    };
                     self.selections[i].cursor.x_preferred =
-                        self.selections[i].cursor.x.saturating_sub(1);
+                        grapheme::column(row, target, tab_width);
                 }
+                self.update_cursors_x_pos();
             }
-            KeyCode::Right => {
-                for i in 0..self.selections.len() {
-                    if let Some(row) = self.row(self.selections[i].cursor.y) {
-                        if self.selections[i].cursor.x < row.len_chars().saturating_sub(1) {
-                            self.selections[i].cursor.x_preferred =
-                                self.selections[i].cursor.x.saturating_add(1);
-                        }
-                    }
+            _ => (),
+        }
+        if !extend {
+            self.collapse_selections();
+        }
+        self.merge_selections();
+    }
+
+    /// Takes itself, whether to move up and how rows are wrapped.
+    /// Moves each cursor onto the screen line above (or below) it,
+    /// keeping it in the same screen column where it can.
+    fn move_cursors_by_screen_line(&mut self, up: bool, wrap: Wrap) {
+        let indicator_width = |line: usize| if line > 0 { wrap.indicator_width } else { 0 };
+        for i in 0..self.selections.len() {
+            let cursor = self.selections[i].cursor;
+            let (graphemes, lines) = self.wrapped_row(cursor.y, Some(wrap));
+            let (line, _, _) = self.cursor_columns(&cursor, Some(wrap));
+            let line_start = line_width(&graphemes[..lines[line].start]);
+            let goal = cursor.x_preferred.saturating_sub(line_start) + indicator_width(line);
+            let (row_idx, line) = if up {
+                if line > 0 {
+                    (cursor.y, line - 1)
+                } else if cursor.y > 0 {
+                    (cursor.y - 1, usize::MAX)
+                } else {
+                    continue;
+                }
+            } else if line + 1 < lines.len() {
+                (cursor.y, line + 1)
+            } else if cursor.y + 1 < self.len() {
+                (cursor.y + 1, 0)
+            } else {
+                continue;
+            };
+            let (graphemes, lines) = if row_idx == cursor.y {
+                (graphemes, lines)
+            } else {
+                self.wrapped_row(row_idx, Some(wrap))
+            };
+            let line = cmp::min(line, lines.len() - 1);
+            let line_start = line_width(&graphemes[..lines[line].start]);
+            let target = goal.saturating_sub(indicator_width(line));
+            let mut column = 0;
+            let mut x = graphemes
+                .get(lines[line].start)
+                .map_or(0, |grapheme| grapheme.start);
+            for grapheme in &graphemes[lines[line].clone()] {
+                x = grapheme.start;
+                column += grapheme.width;
+                if column > target {
+                    break;
                 }
             }
-            _ => (),
+            self.selections[i].cursor = Position {
+                x,
+                x_preferred: line_start + target,
+                y: row_idx,
+            };
         }
-        self.update_cursors_x_pos();
+    }
+
+    /// Takes itself.
+    /// Moves each anchor onto its cursor.
+    pub fn collapse_selections(&mut self) {
+        for selection in &mut self.selections {
+            selection.anchor.x = selection.cursor.x;
+            selection.anchor.y = selection.cursor.y;
+        }
+    }
+
+    /// Takes itself, a `Selection` and whether to select whole lines.
+    /// Returns the range of char indices covered by the selection,
+    /// including the grapheme cluster under the cursor.
+    #[must_use]
+    pub fn selection_char_range(&self, selection: &Selection, linewise: bool) -> Range<usize> {
+        let anchor = self.pos_to_char_idx(&selection.anchor);
+        let cursor = self.pos_to_char_idx(&selection.cursor);
+        let len = self.file_contents.len_chars();
+        let start = cmp::min(anchor, cursor);
+        let end = grapheme::next_boundary(&self.file_contents, cmp::max(anchor, cursor));
+        if linewise {
+            let first_line = self.file_contents.char_to_line(start);
+            let last_line = self.file_contents.char_to_line(end.saturating_sub(1));
+            let end = if last_line.saturating_add(1) < self.len() {
+                self.file_contents.line_to_char(last_line.saturating_add(1))
+            } else {
+                len
+            };
+            self.file_contents.line_to_char(first_line)..end
+        } else {
+            cmp::min(start, end)..end
+        }
+    }
+
+    /// Takes itself, the selections of a window, the index of a row
+    /// and whether selections cover whole lines.
+    /// Returns the column ranges of the row that are selected.
+    #[must_use]
+    pub fn selected_columns(
+        &self,
+        selections: &[Selection],
+        row_idx: usize,
+        linewise: bool,
+    ) -> Vec<Range<usize>> {
+        if row_idx >= self.len() {
+            return Vec::new();
+        }
+        let row_start = self.file_contents.line_to_char(row_idx);
+        let row_end = row_start.saturating_add(self.file_contents.line(row_idx).len_chars());
+        selections
+            .iter()
+            .filter(|selection| linewise || selection.anchor != selection.cursor)
+            .map(|selection| self.selection_char_range(selection, linewise))
+            .filter(|range| range.start < row_end && range.end > row_start)
+            .map(|range| {
+                range.start.saturating_sub(row_start)
+                    ..cmp::min(range.end, row_end).saturating_sub(row_start)
+            })
+            .collect()
+    }
+
+    /// Takes itself and a `Position`.
+    /// Returns the char index in the rope that the position refers to.
+    #[must_use]
+    pub fn pos_to_char_idx(&self, pos: &Position) -> usize {
+        if pos.y >= self.len() {
+            return self.file_contents.len_chars();
+        }
+        cmp::min(
+            self.file_contents.line_to_char(pos.y) + pos.x,
+            self.file_contents.len_chars(),
+        )
+    }
+
+    /// Takes itself and a char index.
+    /// Returns the `Position` of the grapheme cluster containing
+    /// the char index in the rope.
+    #[must_use]
+    pub fn char_idx_to_pos(&self, char_idx: usize) -> Position {
+        let char_idx = cmp::min(char_idx, self.file_contents.len_chars());
+        let y = self.file_contents.char_to_line(char_idx);
+        let row = self.file_contents.line(y);
+        let x = char_idx - self.file_contents.line_to_char(y);
+        let (x, x_preferred) = match grapheme::grapheme_at(row, x, self.options.tab_width) {
+            Some(grapheme) => (grapheme.start, grapheme.column),
+            None => (x, grapheme::column(row, x, self.options.tab_width)),
+        };
+        Position { x, x_preferred, y }
+    }
+
+    /// Takes itself, a `Motion` and whether to extend the selections.
+    /// Moves each selection according to the motion. When extending,
+    /// each anchor stays in place and only the cursor moves.
+    pub fn apply_motion(&mut self, motion: Motion, extend: bool) {
         for i in 0..self.selections.len() {
-            self.selections[i].anchor.x = self.selections[i].cursor.x;
-            self.selections[i].anchor.y = self.selections[i].cursor.y;
+            let cursor = self.pos_to_char_idx(&self.selections[i].cursor);
+            let (anchor, cursor) = movement::apply(motion, &self.file_contents, cursor);
+            if !extend {
+                self.selections[i].anchor = self.char_idx_to_pos(anchor);
+            }
+            self.selections[i].cursor = self.char_idx_to_pos(cursor);
         }
+        self.merge_selections();
     }
 
     /// Takes itself.
-    /// Returns the position of the primary cursor on the screen.
+    /// Sorts the selections by position and merges any that overlap,
+    /// keeping track of which one is primary.
+    pub fn merge_selections(&mut self) {
+        let mut ranges: Vec<(Range<usize>, Selection, bool)> = self
+            .selections
+            .iter()
+            .enumerate()
+            .map(|(i, selection)| {
+                (
+                    self.selection_char_range(selection, false),
+                    *selection,
+                    i == self.primary_selection_idx,
+                )
+            })
+            .collect();
+        ranges.sort_by_key(|(range, _, _)| range.start);
+        let mut merged: Vec<(Range<usize>, Selection, bool)> = Vec::with_capacity(ranges.len());
+        for (range, selection, is_primary) in ranges {
+            if let Some(last) = merged.last_mut() {
+                if last.1.anchor == selection.anchor && last.1.cursor == selection.cursor {
+                    last.2 |= is_primary;
+                    continue;
+                }
+                if range.start < last.0.end {
+                    last.0.end = cmp::max(range.end, last.0.end);
+                    last.1 = Selection {
+                        anchor: self.char_idx_to_pos(last.0.start),
+                        cursor: self.char_idx_to_pos(last.0.end.saturating_sub(1)),
+                    };
+                    last.2 |= is_primary;
+                    continue;
+                }
+            }
+            merged.push((range, selection, is_primary));
+        }
+        self.primary_selection_idx = merged
+            .iter()
+            .position(|(_, _, is_primary)| *is_primary)
+            .unwrap_or(0);
+        self.selections = merged
+            .into_iter()
+            .map(|(_, selection, _)| selection)
+            .collect();
+    }
+
+    /// Takes itself and whether to search upwards.
+    /// Adds a copy of each selection on the nearest line below
+    /// (or above) that is long enough to hold it. The first new
+    /// selection becomes the primary one.
+    pub fn copy_selections_to_adjacent_line(&mut self, above: bool) {
+        let mut new_selections = Vec::new();
+        for selection in &self.selections {
+            let height = selection
+                .anchor
+                .y
+                .abs_diff(selection.cursor.y)
+                .saturating_add(1);
+            let mut delta = height;
+            loop {
+                let (anchor_y, cursor_y) = if above {
+                    match (
+                        selection.anchor.y.checked_sub(delta),
+                        selection.cursor.y.checked_sub(delta),
+                    ) {
+                        (Some(anchor_y), Some(cursor_y)) => (anchor_y, cursor_y),
+                        _ => break,
+                    }
+                } else {
+                    (selection.anchor.y + delta, selection.cursor.y + delta)
+                };
+                if anchor_y >= self.len() || cursor_y >= self.len() {
+                    break;
+                }
+                if self.column_fits(selection.anchor.x, anchor_y)
+                    && self.column_fits(selection.cursor.x, cursor_y)
+                {
+                    new_selections.push(Selection {
+                        anchor: Position {
+                            y: anchor_y,
+                            ..selection.anchor
+                        },
+                        cursor: Position {
+                            y: cursor_y,
+                            ..selection.cursor
+                        },
+                    });
+                    break;
+                }
+                delta = delta.saturating_add(height);
+            }
+        }
+        if new_selections.is_empty() {
+            return;
+        }
+        let primary = new_selections[0];
+        self.selections.extend(new_selections);
+        self.primary_selection_idx = self.selections.len().saturating_sub(1);
+        self.merge_selections();
+        if let Some(i) = self
+            .selections
+            .iter()
+            .position(|selection| selection.cursor == primary.cursor)
+        {
+            self.primary_selection_idx = i;
+        }
+    }
+
+    /// Takes itself, a column and the index of a row.
+    /// Returns a bool representing whether a cursor can be
+    /// placed at that column of the row.
+    fn column_fits(&self, x: usize, y: usize) -> bool {
+        self.row(y).is_some_and(|row| {
+            let len = row.len_chars();
+            x < len || (x == 0 && len == 0)
+        })
+    }
+
+    /// Takes itself.
+    /// Splits each selection into one selection per line it covers,
+    /// leaving out the line breaks.
+    pub fn split_selections_on_lines(&mut self) {
+        let primary_line = self.selections[self.primary_selection_idx].cursor.y;
+        let mut new_selections = Vec::new();
+        for selection in &self.selections {
+            let range = self.selection_char_range(selection, false);
+            let first_line = self.file_contents.char_to_line(range.start);
+            let last_line = self
+                .file_contents
+                .char_to_line(range.end.saturating_sub(1).max(range.start));
+            for line_idx in first_line..=last_line {
+                let line_start = self.file_contents.line_to_char(line_idx);
+                let start = cmp::max(range.start, line_start);
+                let end = cmp::min(
+                    range.end,
+                    movement::line_end(&self.file_contents, line_idx) + 1,
+                );
+                let end = cmp::max(end, start.saturating_add(1));
+                new_selections.push(Selection {
+                    anchor: self.char_idx_to_pos(start),
+                    cursor: self.char_idx_to_pos(end.saturating_sub(1)),
+                });
+            }
+        }
+        self.selections = new_selections;
+        self.primary_selection_idx = 0;
+        self.merge_selections();
+        if let Some(i) = self
+            .selections
+            .iter()
+            .position(|selection| selection.cursor.y == primary_line)
+        {
+            self.primary_selection_idx = i;
+        }
+    }
+
+    /// Takes itself.
+    /// Removes every selection except the primary one.
+    pub fn keep_primary_selection(&mut self) {
+        let primary = self.selections[self.primary_selection_idx];
+        self.selections = vec![primary];
+        self.primary_selection_idx = 0;
+    }
+
+    /// Takes itself and whether to cycle backwards.
+    /// Makes the next (or previous) selection the primary one.
+    pub fn cycle_primary_selection(&mut self, backwards: bool) {
+        let len = self.selections.len();
+        self.primary_selection_idx = if backwards {
+            (self.primary_selection_idx + len - 1) % len
+        } else {
+            (self.primary_selection_idx + 1) % len
+        };
+    }
+
+    /// Takes itself and whether selections cover whole lines.
+    /// Returns the text covered by each selection.
     #[must_use]
-    pub fn get_primary_selection_cursor_pos(&self) -> Position {
-        let primary_selection = &self.selections[self.primary_selection_idx];
-        let Position {
-            x,
-            x_preferred: _,
-            y,
-        } = primary_selection.cursor;
-        let x = x.saturating_sub(self.offset.x);
-        let y = y.saturating_sub(self.offset.y);
+    pub fn selection_texts(&self, linewise: bool) -> Vec<String> {
+        self.selections
+            .iter()
+            .map(|selection| {
+                self.file_contents
+                    .slice(self.selection_char_range(selection, linewise))
+                    .to_string()
+            })
+            .collect()
+    }
+
+    /// Takes itself and whether selections cover whole lines.
+    /// Returns the sorted ranges covered by the selections, with
+    /// overlapping ranges merged together.
+    fn selection_char_ranges(&self, linewise: bool) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::with_capacity(self.selections.len());
+        for selection in &self.selections {
+            let range = self.selection_char_range(selection, linewise);
+            if let Some(last) = ranges.last_mut() {
+                if range.start < last.end {
+                    last.end = cmp::max(last.end, range.end);
+                    continue;
+                }
+            }
+            ranges.push(range);
+        }
+        ranges
+    }
+
+    /// Takes itself and a list of sorted, non-overlapping changes.
+    /// Replaces the text in each range with the matching string and
+    /// returns the ranges that the new strings occupy.
+    fn apply_changes(&mut self, changes: &[(Range<usize>, String)]) -> Vec<Range<usize>> {
+        for (range, text) in changes.iter().rev() {
+            if !range.is_empty() {
+                self.remove_text(range.clone());
+            }
+            if !text.is_empty() {
+                self.insert_text(range.start, text);
+            }
+        }
+        let mut shift: isize = 0;
+        let mut new_ranges = Vec::with_capacity(changes.len());
+        for (range, text) in changes {
+            let start = range.start.saturating_add_signed(shift);
+            let len = text.chars().count();
+            new_ranges.push(start..start.saturating_add(len));
+            shift += len.cast_signed() - range.len().cast_signed();
+        }
+        if !changes.is_empty() {
+            self.buffer_is_empty = false;
+            self.file_is_dirty = true;
+        }
+        new_ranges
+    }
+
+    /// Takes itself and a list of char ranges.
+    /// Replaces the selections with one selection per range.
+    fn set_selection_ranges(&mut self, ranges: &[Range<usize>]) {
+        if ranges.is_empty() {
+            return;
+        }
+        self.selections = ranges
+            .iter()
+            .map(|range| Selection {
+                anchor: self.char_idx_to_pos(range.start),
+                cursor: self.char_idx_to_pos(cmp::max(range.end.saturating_sub(1), range.start)),
+            })
+            .collect();
+        self.primary_selection_idx = cmp::min(self.primary_selection_idx, ranges.len() - 1);
+        self.merge_selections();
+    }
+
+    /// Takes itself, whether selections cover whole lines and whether
+    /// the final line break of a line-wise selection should be kept.
+    /// Deletes the text covered by every selection, leaving a cursor
+    /// where each selection started.
+    pub fn delete_selections(&mut self, linewise: bool, keep_line_break: bool) {
+        let changes: Vec<(Range<usize>, String)> = self
+            .selection_char_ranges(linewise)
+            .into_iter()
+            .map(|range| {
+                let keep = keep_line_break
+                    && linewise
+                    && range.end > range.start
+                    && self.file_contents.char(range.end - 1) == '\n';
+                let end = if keep { range.end - 1 } else { range.end };
+                (range.start..end, String::new())
+            })
+            .collect();
+        let new_ranges = self.apply_changes(&changes);
+        self.set_selection_ranges(&new_ranges);
+    }
+
+    /// Takes itself, the values to paste and whether to paste
+    /// before the selections.
+    /// Pastes one value at each selection, repeating the last value
+    /// if there are more selections than values. Values that end in
+    /// a line break are pasted on their own line. The pasted text
+    /// becomes selected.
+    pub fn paste(&mut self, values: &[String], before: bool) {
+        let Some(last_value) = values.last() else {
+            return;
+        };
+        let mut changes: Vec<(Range<usize>, String)> = self
+            .selections
+            .iter()
+            .enumerate()
+            .map(|(i, selection)| {
+                let value = values.get(i).unwrap_or(last_value);
+                let range = self.selection_char_range(selection, false);
+                let char_idx = if value.ends_with('\n') {
+                    let line_idx = if before {
+                        self.file_contents.char_to_line(range.start)
+                    } else {
+                        self.file_contents
+                            .char_to_line(range.end.saturating_sub(1).max(range.start))
+                            .saturating_add(1)
+                    };
+                    if line_idx < self.len() {
+                        self.file_contents.line_to_char(line_idx)
+                    } else {
+                        self.file_contents.len_chars()
+                    }
+                } else if before {
+                    range.start
+                } else {
+                    range.end
+                };
+                (char_idx..char_idx, value.clone())
+            })
+            .collect();
+        changes.sort_by_key(|(range, _)| range.start);
+        let new_ranges = self.apply_changes(&changes);
+        self.set_selection_ranges(&new_ranges);
+    }
+
+    /// Takes itself and a char.
+    /// Replaces every grapheme cluster covered by the selections
+    /// with the given char, leaving line breaks in place.
+    pub fn replace_selections(&mut self, c: char) {
+        let replacement = c.to_string();
+        let changes: Vec<(Range<usize>, String)> = self
+            .selection_char_ranges(false)
+            .into_iter()
+            .map(|range| {
+                let text: String = self
+                    .file_contents
+                    .slice(range.clone())
+                    .to_string()
+                    .graphemes(true)
+                    .map(|old| {
+                        if grapheme::is_line_break(old) {
+                            old
+                        } else {
+                            &replacement
+                        }
+                    })
+                    .collect();
+                (range, text)
+            })
+            .collect();
+        let new_ranges = self.apply_changes(&changes);
+        self.set_selection_ranges(&new_ranges);
+    }
+
+    /// Takes itself, a regex and the index of a row.
+    /// Returns the column ranges of the row that match the regex.
+    #[must_use]
+    pub fn search_columns(&self, regex: &Regex, row_idx: usize) -> Vec<Range<usize>> {
+        if row_idx >= self.len() {
+            return Vec::new();
+        }
+        let row_start = self.file_contents.line_to_char(row_idx);
+        search::line_matches(&self.file_contents, regex, row_idx)
+            .into_iter()
+            .map(|found| found.start - row_start..found.end - row_start)
+            .collect()
+    }
+
+    /// Takes itself, a regex, whether to search backwards and
+    /// whether to extend the primary selection.
+    /// Selects the next match after (or before) the primary cursor.
+    /// Returns a bool representing whether a match was found.
+    pub fn search(&mut self, regex: &Regex, backward: bool, extend: bool) -> bool {
+        let primary = self.selections[self.primary_selection_idx];
+        let from = self.pos_to_char_idx(&primary.cursor);
+        let Some(found) = search::find_next(&self.file_contents, regex, from, backward) else {
+            return false;
+        };
+        let anchor = if extend {
+            primary.anchor
+        } else {
+            self.char_idx_to_pos(found.start)
+        };
+        let cursor = self.char_idx_to_pos(found.end.saturating_sub(1));
+        self.selections = vec![Selection { anchor, cursor }];
+        self.primary_selection_idx = 0;
+        true
+    }
+
+    /// Takes itself and a regex.
+    /// Replaces the selections with one selection for every match
+    /// of the regex inside them. Returns a bool representing
+    /// whether any matches were found.
+    pub fn select_matches(&mut self, regex: &Regex) -> bool {
+        let matches: Vec<Range<usize>> = self
+            .selection_char_ranges(false)
+            .iter()
+            .flat_map(|range| search::find_in_range(&self.file_contents, regex, range))
+            .collect();
+        if matches.is_empty() {
+            return false;
+        }
+        self.primary_selection_idx = matches.len() - 1;
+        self.set_selection_ranges(&matches);
+        true
+    }
+
+    /// Takes itself, the index of a line and a column.
+    /// Collapses the selections into a single cursor at the given
+    /// position, clamped to the contents of the buffer.
+    pub fn goto(&mut self, line: usize, column: usize) {
+        let cursor = self.clamp_position(Position {
+            x: column,
+            x_preferred: column,
+            y: line,
+        });
+        let cursor = self.char_idx_to_pos(self.pos_to_char_idx(&cursor));
+        self.selections = vec![Selection {
+            anchor: cursor,
+            cursor,
+        }];
+        self.primary_selection_idx = 0;
+    }
+
+    /// Takes itself and a `Position`.
+    /// Returns the position moved onto the nearest line and column
+    /// that exist in the buffer.
+    fn clamp_position(&self, pos: Position) -> Position {
+        let y = cmp::min(pos.y, self.len().saturating_sub(1));
+        let x = self.row(y).map_or(0, |row| {
+            let len = row.len_chars();
+            let last = if row.chars().last() == Some('\n') {
+                len.saturating_sub(1)
+            } else {
+                len
+            };
+            cmp::min(pos.x, last)
+        });
         Position {
             x,
-            x_preferred: 0,
+            x_preferred: pos.x_preferred,
             y,
         }
     }
 
-    /// Takes itself and a position.
+    /// Takes itself.
+    /// Returns a copy of the selections and scroll offset.
+    #[must_use]
+    pub fn view(&self) -> View {
+        View {
+            selections: self.selections.clone(),
+            primary_selection_idx: self.primary_selection_idx,
+            offset: self.offset,
+            wrap_offset: self.wrap_offset,
+        }
+    }
+
+    /// Takes itself and a `View`.
+    /// Replaces the selections and scroll offset with those of the
+    /// view, moving any selection that no longer fits back inside
+    /// the buffer.
+    pub fn set_view(&mut self, view: &View) {
+        self.selections = view
+            .selections
+            .iter()
+            .map(|selection| Selection {
+                anchor: self.clamp_position(selection.anchor),
+                cursor: self.clamp_position(selection.cursor),
+            })
+            .collect();
+        if self.selections.is_empty() {
+            self.selections.push(Selection::default());
+        }
+        self.primary_selection_idx =
+            cmp::min(view.primary_selection_idx, self.selections.len() - 1);
+        self.offset = view.offset;
+        self.wrap_offset = view.wrap_offset;
+        self.merge_selections();
+    }
+
+    /// Takes itself.
+    /// Remembers the lines covered by the selections, so that
+    /// commands can refer to them with `'<,'>`.
+    pub fn mark_visual_lines(&mut self) {
+        let first = self
+            .selections
+            .iter()
+            .map(|selection| cmp::min(selection.anchor.y, selection.cursor.y))
+            .min();
+        let last = self
+            .selections
+            .iter()
+            .map(|selection| cmp::max(selection.anchor.y, selection.cursor.y))
+            .max();
+        if let (Some(first), Some(last)) = (first, last) {
+            self.visual_lines = Some((first, last));
+        }
+    }
+
+    /// Takes itself, a `Substitution` and a range of lines.
+    /// Returns the char range and replacement text of every match
+    /// of the substitution on those lines.
+    #[must_use]
+    pub fn substitution_matches(
+        &self,
+        substitution: &Substitution,
+        lines: RangeInclusive<usize>,
+    ) -> Vec<(Range<usize>, String)> {
+        lines
+            .flat_map(|line_idx| {
+                search::line_substitutions(
+                    &self.file_contents,
+                    &substitution.regex,
+                    line_idx,
+                    &substitution.replacement,
+                    substitution.global,
+                )
+            })
+            .collect()
+    }
+
+    /// Takes itself and a list of sorted, non-overlapping replacements.
+    /// Applies every replacement as a single undoable revision and
+    /// moves the cursor to the start of the last one.
+    pub fn apply_substitutions(&mut self, substitutions: &[(Range<usize>, String)]) {
+        let new_ranges = self.apply_changes(substitutions);
+        if let Some(last) = new_ranges.last() {
+            let cursor = self.char_idx_to_pos(last.start);
+            self.selections = vec![Selection {
+                anchor: cursor,
+                cursor,
+            }];
+            self.primary_selection_idx = 0;
+        }
+        self.commit();
+    }
+
+    /// Takes itself, the index of a row and how rows are wrapped.
+    /// Returns the grapheme clusters of the row and the range of
+    /// them on each screen line the row takes up.
+    fn wrapped_row(
+        &self,
+        row_idx: usize,
+        wrap: Option<Wrap>,
+    ) -> (Vec<Grapheme>, Vec<Range<usize>>) {
+        let graphemes = self.row(row_idx).map_or_else(Vec::new, |row| {
+            grapheme::graphemes(row, self.options.tab_width)
+        });
+        let lines = match wrap {
+            Some(wrap) => wrap.split(&graphemes),
+            None => iter::once(0..graphemes.len()).collect(),
+        };
+        (graphemes, lines)
+    }
+
+    /// Takes itself, the index of a row and how rows are wrapped.
+    /// Returns the number of screen lines the row takes up.
+    fn screen_line_count(&self, row_idx: usize, wrap: Wrap) -> usize {
+        self.wrapped_row(row_idx, Some(wrap)).1.len()
+    }
+
+    /// Takes itself, a `View`, how rows are wrapped and the width
+    /// and height of the text area of a window.
+    /// Returns what the window shows on each of its lines.
+    #[must_use]
+    pub fn screen_lines(
+        &self,
+        view: &View,
+        wrap: Option<Wrap>,
+        width: usize,
+        height: usize,
+    ) -> Vec<ScreenLine> {
+        let mut screen_lines = Vec::with_capacity(height);
+        let mut row_idx = view.offset.y;
+        while screen_lines.len() < height && row_idx < self.len() {
+            let Some(wrap) = wrap else {
+                screen_lines.push(ScreenLine {
+                    row_idx,
+                    continuation: false,
+                    columns: view.offset.x..view.offset.x.saturating_add(width),
+                });
+                row_idx += 1;
+                continue;
+            };
+            let (graphemes, lines) = self.wrapped_row(row_idx, Some(wrap));
+            let skipped = if row_idx == view.offset.y {
+                cmp::min(view.wrap_offset, lines.len() - 1)
+            } else {
+                0
+            };
+            let mut start = 0;
+            for (line_idx, line) in lines.into_iter().enumerate() {
+                let end = start + line_width(&graphemes[line]);
+                if line_idx >= skipped && screen_lines.len() < height {
+                    screen_lines.push(ScreenLine {
+                        row_idx,
+                        continuation: line_idx > 0,
+                        columns: start..end,
+                    });
+                }
+                start = end;
+            }
+            row_idx += 1;
+        }
+        screen_lines
+    }
+
+    /// Takes itself, a `Position` and how rows are wrapped.
+    /// Returns the screen line of its row the cursor is on, the
+    /// column it starts at on that line and the number of columns
+    /// its grapheme cluster takes up.
+    fn cursor_columns(&self, cursor: &Position, wrap: Option<Wrap>) -> (usize, usize, usize) {
+        let Some(wrap) = wrap else {
+            // Without wrapping, the rest of the row after the cursor
+            // does not matter.
+            let row = self.row(cursor.y);
+            let tab_width = self.options.tab_width;
+            return match row.and_then(|row| grapheme::grapheme_at(row, cursor.x, tab_width)) {
+                Some(grapheme) => (0, grapheme.column, grapheme.width),
+                None => (
+                    0,
+                    row.map_or(0, |row| grapheme::column(row, cursor.x, tab_width)),
+                    1,
+                ),
+            };
+        };
+        let (graphemes, lines) = self.wrapped_row(cursor.y, Some(wrap));
+        let last_line = lines.len() - 1;
+        let Some(idx) = graphemes
+            .iter()
+            .position(|grapheme| grapheme.end > cursor.x)
+        else {
+            return (
+                last_line,
+                line_width(&graphemes[lines[last_line].clone()]),
+                1,
+            );
+        };
+        let line = lines
+            .iter()
+            .position(|line| line.contains(&idx))
+            .unwrap_or(last_line);
+        let column = line_width(&graphemes[lines[line].start..idx]);
+        (line, column, graphemes[idx].width)
+    }
+
+    /// Takes itself, a position, the size of the window, the width
+    /// of its gutter and how rows are wrapped.
     /// Returns the position of the cursor on the screen.
     ///
     /// # Errors
     ///
     /// Returns `None` if the cursor is off-screen.
     #[must_use]
-    pub fn get_screen_cursor_pos(&self, cursor: &Position, size: &Size) -> Option<Position> {
-        let Position {
-            x,
-            x_preferred: _,
-            y,
-        } = cursor;
-        if x < &self.offset.x
-            || x >= &self.offset.x.saturating_add(size.width as usize)
-            || y < &self.offset.y
-            || y >= &self.offset.y.saturating_add(size.height as usize)
-        {
-            None
+    pub fn get_screen_cursor_pos(
+        &self,
+        cursor: &Position,
+        size: &Size,
+        gutter_width: usize,
+        wrap: Option<Wrap>,
+    ) -> Option<Position> {
+        let (line, column, width) = self.cursor_columns(cursor, wrap);
+        let height = size.height as usize;
+        let (x, y) = if let Some(wrap) = wrap {
+            if (cursor.y, line) < (self.offset.y, self.wrap_offset) {
+                return None;
+            }
+            let mut y = line;
+            for row_idx in self.offset.y..cursor.y {
+                y += self.screen_line_count(row_idx, wrap);
+                if y >= height.saturating_add(self.wrap_offset) {
+                    return None;
+                }
+            }
+            let indicator_width = if line > 0 { wrap.indicator_width } else { 0 };
+            (indicator_width + column, y.saturating_sub(self.wrap_offset))
         } else {
-            let x = x.saturating_sub(self.offset.x);
-            let y = y.saturating_sub(self.offset.y);
-            Some(Position {
-                x,
-                x_preferred: 0,
-                y,
-            })
-        }
+            let text_width = (size.width as usize).saturating_sub(gutter_width);
+            if column < self.offset.x
+                || column.saturating_add(width) > self.offset.x.saturating_add(text_width)
+                || cursor.y < self.offset.y
+            {
+                return None;
+            }
+            (column - self.offset.x, cursor.y - self.offset.y)
+        };
+        (y < height).then_some(Position {
+            x: x + gutter_width,
+            x_preferred: 0,
+            y,
+        })
     }
 
     /// Takes itself and a `Position`.
-    /// Returns the char under the cursor.
+    /// Returns the text drawn for the grapheme cluster under the
+    /// cursor.
     #[must_use]
-    pub fn get_char_under_cursor(&self, cursor: &Position) -> char {
-        let line = self.row(cursor.y);
-        if let Some(line) = line {
-            let char = line.get_char(cursor.x);
-            if let Some(char) = char {
-                if char == '\n' {
-                    ' '
-                } else {
-                    char
-                }
+    pub fn get_grapheme_under_cursor(&self, cursor: &Position) -> String {
+        self.row(cursor.y)
+            .and_then(|row| grapheme::grapheme_at(row, cursor.x, self.options.tab_width))
+            .map_or_else(|| String::from(" "), |grapheme| grapheme.text)
+    }
+
+    /// Takes itself, the size of the window, the width of its
+    /// gutter, how many rows to keep visible above and below the
+    /// cursor and how rows are wrapped.
+    /// Scrolls the viewport so that the primary selection
+    /// is in view.
+    pub fn shift_viewport(
+        &mut self,
+        size: &Size,
+        gutter_width: usize,
+        scrolloff: usize,
+        wrap: Option<Wrap>,
+    ) {
+        let height = size.height as usize;
+        let scrolloff = cmp::min(scrolloff, height.saturating_sub(1) / 2);
+        if let Some(wrap) = wrap {
+            self.offset.x = 0;
+            self.shift_wrapped_viewport(height, scrolloff, wrap);
+            return;
+        }
+        self.wrap_offset = 0;
+        let text_width = cmp::max((size.width as usize).saturating_sub(gutter_width), 1);
+        let (_, column, width) =
+            self.cursor_columns(&self.selections[self.primary_selection_idx].cursor, None);
+        if column.saturating_add(width) > self.offset.x.saturating_add(text_width) {
+            self.offset.x = column.saturating_add(width).saturating_sub(text_width);
+        }
+        if self.offset.x > column {
+            self.offset.x = column;
+        }
+        let cursor_y = self.selections[self.primary_selection_idx].cursor.y;
+        let top = cursor_y.saturating_sub(scrolloff);
+        let bottom = cmp::min(
+            cursor_y.saturating_add(scrolloff),
+            self.len().saturating_sub(1),
+        );
+        if self.offset.y > top {
+            self.offset.y = top;
+        } else if bottom >= self.offset.y.saturating_add(height) {
+            self.offset.y = bottom.saturating_add(1).saturating_sub(height);
+        }
+    }
+
+    /// Takes itself, the height of the window, how many screen lines
+    /// to keep visible above and below the cursor and how rows are
+    /// wrapped.
+    /// Scrolls the viewport by screen lines so that the primary
+    /// selection is in view.
+    fn shift_wrapped_viewport(&mut self, height: usize, scrolloff: usize, wrap: Wrap) {
+        let cursor = self.selections[self.primary_selection_idx].cursor;
+        let (line, _, _) = self.cursor_columns(&cursor, Some(wrap));
+        let cursor_line = (cursor.y, line);
+        let top = self.screen_lines_back(cursor_line, scrolloff, wrap);
+        let below = self.screen_lines_ahead(cursor_line, scrolloff, wrap);
+        let bottom_top =
+            self.screen_lines_back(cursor_line, height.saturating_sub(below + 1), wrap);
+        let offset = (self.offset.y, self.wrap_offset);
+        (self.offset.y, self.wrap_offset) = if offset > top {
+            top
+        } else if offset < bottom_top {
+            bottom_top
+        } else {
+            offset
+        };
+    }
+
+    /// Takes itself, a row and one of its screen lines, a number of
+    /// screen lines and how rows are wrapped.
+    /// Returns the row and screen line that many screen lines up,
+    /// stopping at the start of the buffer.
+    fn screen_lines_back(
+        &self,
+        (mut row_idx, mut line): (usize, usize),
+        count: usize,
+        wrap: Wrap,
+    ) -> (usize, usize) {
+        for _ in 0..count {
+            if line > 0 {
+                line -= 1;
+            } else if row_idx > 0 {
+                row_idx -= 1;
+                line = self.screen_line_count(row_idx, wrap) - 1;
             } else {
-                ' '
+                break;
             }
+        }
+        (row_idx, line)
+    }
+
+    /// Takes itself, a row and one of its screen lines, a number of
+    /// screen lines and how rows are wrapped.
+    /// Returns how many screen lines, up to that number, follow the
+    /// given one in the buffer.
+    fn screen_lines_ahead(
+        &self,
+        (row_idx, line): (usize, usize),
+        count: usize,
+        wrap: Wrap,
+    ) -> usize {
+        let mut ahead = self.screen_line_count(row_idx, wrap) - 1 - line;
+        let mut row_idx = row_idx + 1;
+        while ahead < count && row_idx < self.len() {
+            ahead += self.screen_line_count(row_idx, wrap);
+            row_idx += 1;
+        }
+        cmp::min(ahead, count)
+    }
+}
+
+/// Takes the contents of a file.
+/// Returns the indentation style used by most of its indented
+/// lines, judging by how far each line is indented past the one
+/// before it.
+fn detect_indent(rope: &Rope) -> Option<Indent> {
+    let mut tab_lines = 0;
+    let mut space_lines = 0;
+    let mut widths = [0; MAX_INDENT_WIDTH + 1];
+    let mut previous_indent = 0;
+    for line in rope.lines().take(INDENT_SAMPLE_LINES) {
+        if line.chars().all(char::is_whitespace) {
+            continue;
+        }
+        if line.char(0) == '\t' {
+            tab_lines += 1;
+            previous_indent = 0;
+            continue;
+        }
+        let indent = line.chars().take_while(|c| *c == ' ').count();
+        if indent > 0 {
+            space_lines += 1;
+        }
+        if indent > previous_indent && indent - previous_indent <= MAX_INDENT_WIDTH {
+            widths[indent - previous_indent] += 1;
+        }
+        previous_indent = indent;
+    }
+    if tab_lines > space_lines {
+        return Some(Indent::Tabs);
+    }
+    let (width, count) = widths
+        .iter()
+        .enumerate()
+        .rev()
+        .max_by_key(|(_, count)| **count)?;
+    (*count > 0).then_some(Indent::Spaces(width))
+}
+
+/// A file as it was read from disk.
+struct LoadedFile {
+    /// The contents, with their line breaks turned into `\n`.
+    contents: Rope,
+    /// The encoding and line break the contents were found to use.
+    file_format: (Encoding, LineEnding),
+    binary: bool,
+    stamp: Stamp,
+}
+
+/// Takes a list of edits, the char range an edit replaces and the
+/// number of chars it replaces it with.
+/// Adds the edit to the list, joining an insertion with a removal
+/// just before it at the same place into a single replacement.
+fn push_change(changes: &mut Vec<(Range<usize>, usize)>, range: Range<usize>, len: usize) {
+    if let Some(last) = changes.last_mut() {
+        if range.is_empty() && last.1 == 0 && last.0.start == range.start {
+            last.1 = len;
+            return;
+        }
+    }
+    changes.push((range, len));
+}
+
+/// Takes the path of a file.
+/// Returns the file as it was read. The encoding and line break
+/// are guessed from the first piece of the file, and the file is
+/// only read again when the rest of it proves the guess wrong, so
+/// that loading it never takes more memory than its rope.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read.
+fn read_file(path: &Path) -> Result<LoadedFile, std::io::Error> {
+    let mut file_format = None;
+    let mut reads = 0;
+    loop {
+        reads += 1;
+        let (file, line_ending) = read_file_as(path, file_format)?;
+        match file {
+            Some(file)
+                if file.binary || file.file_format.1 == line_ending || reads >= MAX_READS =>
+            {
+                return Ok(file);
+            }
+            Some(file) => file_format = Some((file.file_format.0, line_ending)),
+            None => file_format = Some((Encoding::Latin1, line_ending)),
+        }
+    }
+}
+
+/// Takes the path of a file and the encoding and line break to
+/// read it with, or `None` to guess them from its first piece.
+/// Reads the file into a rope a piece at a time, decoding each
+/// piece and turning its line breaks into `\n`. The line breaks of
+/// binary files are kept as they are, so that their bytes are
+/// saved unchanged. Returns the file, or `None` if it is not valid
+/// in the encoding, and the line break most of the lines read end
+/// with.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read.
+fn read_file_as(
+    path: &Path,
+    file_format: Option<(Encoding, LineEnding)>,
+) -> Result<(Option<LoadedFile>, LineEnding), std::io::Error> {
+    let mut encoding = file_format.map(|(encoding, _)| encoding);
+    let mut line_ending = file_format.map(|(_, line_ending)| line_ending);
+    let mut decoder = None;
+    let mut binary = false;
+    let mut valid = true;
+    let mut counts = LineEndingCounts::default();
+    let mut text = String::new();
+    let mut builder = RopeBuilder::new();
+    let stamp = disk::read_chunks(path, |bytes| {
+        let decoder = decoder.get_or_insert_with(|| {
+            let encoding = *encoding.get_or_insert_with(|| Encoding::detect(bytes));
+            binary = encoding.is_binary(bytes);
+            Decoder::new(encoding)
+        });
+        let start = text.len();
+        valid = decoder.decode(bytes, &mut text);
+        if !valid {
+            return false;
+        }
+        counts.add(&text[start..]);
+        let line_ending = if binary {
+            LineEnding::Lf
         } else {
-            ' '
+            *line_ending.get_or_insert_with(|| counts.line_ending())
+        };
+        // A `\r` at the end may be followed by a `\n` in the next piece.
+        let split_crlf = line_ending == LineEnding::Crlf && text.ends_with('\r');
+        if split_crlf {
+            text.pop();
         }
+        builder.append(&line_ending.normalize(Cow::Borrowed(&text)));
+        text.clear();
+        if split_crlf {
+            text.push('\r');
+        }
+        true
+    })?;
+    if !valid || decoder.is_some_and(|decoder| !decoder.finish()) {
+        return Ok((None, counts.line_ending()));
     }
+    builder.append(&text);
+    let line_ending = if binary {
+        LineEnding::Lf
+    } else {
+        line_ending.unwrap_or(LineEnding::Lf)
+    };
+    let file = LoadedFile {
+        contents: builder.finish(),
+        file_format: (encoding.unwrap_or(Encoding::Utf8), line_ending),
+        binary,
+        stamp,
+    };
+    Ok((Some(file), counts.line_ending()))
+}
 
-    /// Takes itself and the terminal size.
-    /// Scrolls the viewport so that the primary selection
-    /// is in view.
-    pub fn shift_viewport(&mut self, size: &Size) {
-        let Position {
-            x,
-            x_preferred: _,
-            y,
-        } = self.get_primary_selection_cursor_pos();
-        if x >= size.width as usize {
-            self.offset.x = self.selections[self.primary_selection_idx]
-                .cursor
-                .x
-                .saturating_sub(size.width as usize)
-                .saturating_add(1);
-        } else if self.offset.x > self.selections[self.primary_selection_idx].cursor.x {
-            self.offset.x = self.selections[self.primary_selection_idx].cursor.x;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempFile;
+
+    fn cursor_at(file_buffer: &FileBuffer, char_idx: usize) -> View {
+        let pos = file_buffer.char_idx_to_pos(char_idx);
+        View {
+            selections: vec![Selection {
+                anchor: pos,
+                cursor: pos,
+            }],
+            primary_selection_idx: 0,
+            offset: Position::default(),
+            wrap_offset: 0,
         }
-        if y >= size.height as usize {
-            self.offset.y = self.selections[self.primary_selection_idx]
-                .cursor
-                .y
-                .saturating_sub(size.height as usize)
-                .saturating_add(1);
-        } else if self.offset.y > self.selections[self.primary_selection_idx].cursor.y {
-            self.offset.y = self.selections[self.primary_selection_idx].cursor.y;
+    }
+
+    fn cursor(view: &View) -> (usize, usize) {
+        (view.selections[0].cursor.y, view.selections[0].cursor.x)
+    }
+
+    #[test]
+    fn views_of_other_windows_follow_edits() {
+        let mut file_buffer = FileBuffer {
+            file_contents: Rope::from_str("a\nb\ncd\n"),
+            ..FileBuffer::default()
+        };
+        let mut view = cursor_at(&file_buffer, 5);
+        file_buffer.insert('\n');
+        file_buffer.insert('x');
+        file_buffer.map_views([&mut view]);
+        assert_eq!(cursor(&view), (3, 1));
+        file_buffer.undo();
+        file_buffer.map_views([&mut view]);
+        assert_eq!(cursor(&view), (2, 1));
+        file_buffer.redo();
+        file_buffer.map_views([&mut view]);
+        assert_eq!(cursor(&view), (3, 1));
+    }
+
+    #[test]
+    fn views_in_removed_text_move_to_its_start() {
+        let mut file_buffer = FileBuffer {
+            file_contents: Rope::from_str("abc\ndef\n"),
+            ..FileBuffer::default()
+        };
+        let mut view = cursor_at(&file_buffer, 5);
+        file_buffer.apply_changes(&[(2..6, String::new())]);
+        file_buffer.map_views([&mut view]);
+        assert_eq!(cursor(&view), (0, 2));
+    }
+
+    #[test]
+    fn reloading_a_large_file_clears_the_history() {
+        let file = TempFile::new("reload", "a\n");
+        let mut file_buffer = FileBuffer::open(&file.0).unwrap();
+        std::fs::write(&file.0, "ab\n").unwrap();
+        file_buffer.reload().unwrap();
+        assert!(file_buffer.undo());
+        assert_eq!(file_buffer.file_contents, "a\n");
+        file_buffer.options.large_file_size = 0;
+        std::fs::write(&file.0, "abc\n").unwrap();
+        file_buffer.reload().unwrap();
+        assert_eq!(file_buffer.file_contents, "abc\n");
+        assert!(!file_buffer.undo());
+        assert!(!file_buffer.file_is_dirty);
+    }
+
+    #[test]
+    fn writing_lines_to_the_own_file_is_not_a_change_on_disk() {
+        let file = TempFile::new("write-lines", "a\nb\nc\n");
+        let mut file_buffer = FileBuffer::open(&file.0).unwrap();
+        let path = file.0.clone();
+        assert_eq!(
+            file_buffer.write_lines(Some(0..=1), &path, false),
+            Ok((4, true))
+        );
+        assert_eq!(std::fs::read_to_string(&file.0).unwrap(), "a\nb\n");
+        assert!(file_buffer.poll_disk().is_none());
+        assert_eq!(file_buffer.file_contents, "a\nb\nc\n");
+    }
+
+    #[test]
+    fn setting_the_same_file_path_keeps_the_disk_stamp() {
+        let file = TempFile::new("same-path", "a\n");
+        let mut file_buffer = FileBuffer::open(&file.0).unwrap();
+        file_buffer.set_file_path(&file.0);
+        assert!(file_buffer.disk_stamp.is_some());
+        assert_eq!(file_buffer.save(false, false), Ok((2, true)));
+    }
+
+    fn read_bytes(name: &str, bytes: &[u8]) -> LoadedFile {
+        let file = TempFile::new(name, bytes);
+        read_file(&file.0).unwrap()
+    }
+
+    #[test]
+    fn read_line_breaks_and_chars_split_between_pieces() {
+        let mut bytes = "x".repeat(disk::CHUNK_LEN - 1).into_bytes();
+        bytes.extend_from_slice("\r\ny\r\n".as_bytes());
+        let file = read_bytes("split-crlf", &bytes);
+        assert_eq!(file.contents.len_lines(), 3);
+        assert_eq!(file.contents.line(1), "y\n");
+        assert!(file.file_format == (Encoding::Utf8, LineEnding::Crlf));
+        let mut bytes = "x".repeat(disk::CHUNK_LEN - 1).into_bytes();
+        bytes.extend_from_slice("é\n".as_bytes());
+        let file = read_bytes("split-char", &bytes);
+        assert_eq!(file.contents.line(0).chars().last(), Some('\n'));
+        assert_eq!(file.contents.char(disk::CHUNK_LEN - 1), 'é');
+        assert!(file.file_format == (Encoding::Utf8, LineEnding::Lf));
+    }
+
+    #[test]
+    fn read_again_when_the_first_piece_guessed_wrong() {
+        let text = "a\n".repeat(disk::CHUNK_LEN / 2) + &"b\r\n".repeat(disk::CHUNK_LEN);
+        let file = read_bytes("guess-crlf", text.as_bytes());
+        assert!(file.file_format == (Encoding::Utf8, LineEnding::Crlf));
+        assert_eq!(file.contents, text.replace("\r\n", "\n"));
+        let mut bytes = "é\n".repeat(disk::CHUNK_LEN).into_bytes();
+        bytes.extend_from_slice(b"\xff\n");
+        let file = read_bytes("guess-latin1", &bytes);
+        assert!(file.file_format == (Encoding::Latin1, LineEnding::Lf));
+        let text: String = bytes.iter().map(|&byte| char::from(byte)).collect();
+        assert_eq!(file.contents, text);
+    }
+
+    #[test]
+    fn binary_files_keep_their_line_breaks() {
+        let file = read_bytes("binary", b"a\0\r\nb\r\n");
+        assert!(file.binary);
+        assert_eq!(file.contents, "a\0\r\nb\r\n");
+        assert!(file.file_format == (Encoding::Utf8, LineEnding::Lf));
+    }
+
+    #[test]
+    fn encode_converts_each_chunk_of_the_rope() {
+        let text = "é\n".repeat(5000);
+        let mut file_buffer = FileBuffer {
+            file_contents: Rope::from_str(&text),
+            ..FileBuffer::default()
+        };
+        file_buffer.options.fileformat = LineEnding::Crlf;
+        file_buffer.options.encoding = Encoding::Utf16Be;
+        let bytes = file_buffer.encode(None, true).unwrap();
+        let expected: Vec<u8> = [0xfe, 0xff]
+            .into_iter()
+            .chain(
+                "é\r\n"
+                    .repeat(5000)
+                    .encode_utf16()
+                    .flat_map(u16::to_be_bytes),
+            )
+            .collect();
+        assert!(bytes == expected);
+        let bytes = file_buffer.encode(Some(1..=1), false).unwrap();
+        assert_eq!(bytes, [0x00, 0xe9, 0x00, 0x0d, 0x00, 0x0a]);
+    }
+
+    fn with_cursors(text: &str, cursors: &[(usize, usize)]) -> FileBuffer {
+        FileBuffer {
+            file_contents: Rope::from_str(text),
+            selections: cursors
+                .iter()
+                .map(|&(y, x)| {
+                    let pos = Position {
+                        x,
+                        x_preferred: x,
+                        y,
+                    };
+                    Selection {
+                        anchor: pos,
+                        cursor: pos,
+                    }
+                })
+                .collect(),
+            ..FileBuffer::default()
         }
     }
+
+    #[test]
+    fn paste_one_value_per_selection() {
+        let mut file_buffer = with_cursors("ab\ncd\n", &[(0, 0), (1, 0)]);
+        assert_eq!(file_buffer.selection_texts(false), ["a", "c"]);
+        file_buffer.paste(&[String::from("X"), String::from("Y")], false);
+        assert_eq!(file_buffer.file_contents, "aXb\ncYd\n");
+        assert_eq!(file_buffer.selection_texts(false), ["X", "Y"]);
+    }
+
+    #[test]
+    fn paste_repeats_the_last_value_for_extra_selections() {
+        let mut file_buffer = with_cursors("ab\ncd\nef\n", &[(0, 0), (1, 0), (2, 0)]);
+        file_buffer.paste(&[String::from("X"), String::from("Y")], true);
+        assert_eq!(file_buffer.file_contents, "Xab\nYcd\nYef\n");
+        let mut file_buffer = with_cursors("ab\ncd\n", &[(0, 1), (1, 1)]);
+        file_buffer.paste(&[String::from("new\n")], false);
+        assert_eq!(file_buffer.file_contents, "ab\nnew\ncd\nnew\n");
+    }
 }
@@ -0,0 +1,306 @@
+#![warn(clippy::all, clippy::pedantic)]
+use crate::Position;
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    queue,
+    style::{ContentStyle, PrintStyledContent},
+    terminal::{Clear, ClearType},
+};
+use std::{
+    fmt,
+    io::{self, Write},
+};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// A single cell of the screen.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Cell {
+    /// The grapheme cluster drawn in the cell, or nothing if the cell
+    /// is covered by a wide cluster in the cell before it.
+    pub symbol: String,
+    pub style: ContentStyle,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            symbol: String::from(" "),
+            style: ContentStyle::default(),
+        }
+    }
+}
+
+/// The contents of the whole screen. The editor draws each frame
+/// into one of these, and only the cells that differ from the last
+/// frame are written to the terminal.
+pub struct Frame {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    /// Where text is printed next, as with the terminal cursor.
+    pen: (usize, usize),
+    /// Where the terminal cursor is shown, if it is shown at all.
+    pub cursor: Option<Position>,
+}
+
+impl Frame {
+    /// Takes the width and height of the screen.
+    /// Creates a blank `Frame` of that size.
+    #[must_use]
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![Cell::default(); width.saturating_mul(height)],
+            pen: (0, 0),
+            cursor: None,
+        }
+    }
+
+    /// Takes itself and a position on the screen.
+    /// Moves the point that text is printed at to the position.
+    pub fn move_to(&mut self, x: usize, y: usize) {
+        self.pen = (x, y);
+    }
+
+    /// Takes itself, some text and the style to draw it in.
+    /// Draws the text from the point that text is printed at, and
+    /// moves the point past it. Text that runs past the right edge
+    /// of the screen is cut off, and wide grapheme clusters that do
+    /// not fit are drawn as spaces.
+    pub fn print(&mut self, text: &str, style: ContentStyle) {
+        let (mut x, y) = self.pen;
+        if y >= self.height {
+            return;
+        }
+        for grapheme in text.graphemes(true) {
+            if x >= self.width {
+                break;
+            }
+            let width = grapheme.width();
+            if width == 0 {
+                // Marks with no width of their own join the cluster
+                // before them.
+                let row = &mut self.cells[y * self.width..y * self.width + x];
+                if let Some(cell) = row.iter_mut().rev().find(|cell| !cell.symbol.is_empty()) {
+                    cell.symbol.push_str(grapheme);
+                }
+                continue;
+            }
+            if x + width > self.width {
+                for x in x..self.width {
+                    self.set(x, y, " ", 1, style);
+                }
+                x = self.width;
+                break;
+            }
+            self.set(x, y, grapheme, width, style);
+            x += width;
+        }
+        self.pen = (x, y);
+    }
+
+    /// Takes itself, a position on the screen, a grapheme cluster,
+    /// the number of columns it takes up and its style.
+    /// Puts the cluster in the cell at the position and covers the
+    /// cells after it that it spills into. Wide clusters that are
+    /// partly overwritten are replaced with spaces.
+    fn set(&mut self, x: usize, y: usize, symbol: &str, width: usize, style: ContentStyle) {
+        let row = y * self.width;
+        let mut start = x;
+        while start > 0 && self.cells[row + start].symbol.is_empty() {
+            start -= 1;
+            self.cells[row + start].symbol = String::from(" ");
+        }
+        let mut end = x + width;
+        while end < self.width && self.cells[row + end].symbol.is_empty() {
+            self.cells[row + end].symbol = String::from(" ");
+            end += 1;
+        }
+        self.cells[row + x] = Cell {
+            symbol: symbol.to_string(),
+            style,
+        };
+        for x in x + 1..x + width {
+            self.cells[row + x] = Cell {
+                symbol: String::new(),
+                style,
+            };
+        }
+    }
+
+    /// Takes itself, the frame that is on the screen, if it is
+    /// known, and where to write to.
+    /// Writes the commands that turn the screen into this frame,
+    /// redrawing only the cells that differ from the frame on the
+    /// screen, or every cell if there is none or it is a different
+    /// size.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the commands cannot be written.
+    pub fn render(&self, previous: Option<&Frame>, out: &mut impl Write) -> io::Result<()> {
+        let previous = previous
+            .filter(|previous| (previous.width, previous.height) == (self.width, self.height));
+        queue!(out, Hide)?;
+        if previous.is_none() {
+            queue!(out, Clear(ClearType::All))?;
+        }
+        let changed = |i: usize| previous.is_none_or(|previous| previous.cells[i] != self.cells[i]);
+        for y in 0..self.height {
+            let row = y * self.width;
+            let mut x = 0;
+            while x < self.width {
+                if !changed(row + x) {
+                    x += 1;
+                    continue;
+                }
+                // A run never starts halfway through a wide cluster.
+                let mut start = x;
+                while start > 0 && self.cells[row + start].symbol.is_empty() {
+                    start -= 1;
+                }
+                let style = self.cells[row + start].style;
+                let mut text = self.cells[row + start].symbol.clone();
+                x = start + 1;
+                // Cells covered by a wide cluster always go with it, so
+                // that the next run starts past the cluster.
+                while x < self.width
+                    && (self.cells[row + x].symbol.is_empty()
+                        || self.cells[row + x].style == style && changed(row + x))
+                {
+                    text.push_str(&self.cells[row + x].symbol);
+                    x += 1;
+                }
+                queue!(
+                    out,
+                    MoveTo(to_u16(start), to_u16(y)),
+                    PrintStyledContent(style.apply(text))
+                )?;
+            }
+        }
+        if let Some(cursor) = self.cursor {
+            queue!(out, MoveTo(to_u16(cursor.x), to_u16(cursor.y)), Show)?;
+        }
+        out.flush()
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (y, row) in self.cells.chunks(self.width.max(1)).enumerate() {
+            if y > 0 {
+                writeln!(f)?;
+            }
+            for cell in row {
+                write!(f, "{}", cell.symbol)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Takes a screen coordinate.
+/// Returns it as a terminal coordinate.
+fn to_u16(coordinate: usize) -> u16 {
+    u16::try_from(coordinate).unwrap_or(u16::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::style::Stylize;
+
+    fn frame(width: usize, height: usize, rows: &[&str]) -> Frame {
+        let mut frame = Frame::new(width, height);
+        for (y, row) in rows.iter().enumerate() {
+            frame.move_to(0, y);
+            frame.print(row, ContentStyle::new());
+        }
+        frame
+    }
+
+    fn render(frame: &Frame, previous: Option<&Frame>) -> String {
+        let mut out = Vec::new();
+        frame.render(previous, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn print_cuts_text_off_at_the_edge() {
+        assert_eq!(frame(4, 2, &["abcdef", "a中b"]).to_string(), "abcd\na中b");
+        assert_eq!(frame(4, 1, &["abc中"]).to_string(), "abc ");
+    }
+
+    #[test]
+    fn marks_join_the_cluster_before_them() {
+        let mut frame = frame(3, 1, &["中"]);
+        frame.print("\u{301}", ContentStyle::new());
+        assert_eq!(frame.cells[0].symbol, "中\u{301}");
+        assert_eq!(frame.to_string(), "中\u{301} ");
+    }
+
+    #[test]
+    fn overwriting_part_of_a_wide_cluster_blanks_the_rest() {
+        let mut frame = frame(4, 1, &["中中"]);
+        frame.move_to(1, 0);
+        frame.print("x", ContentStyle::new());
+        assert_eq!(frame.to_string(), " x中");
+        frame.move_to(2, 0);
+        frame.print("y", ContentStyle::new());
+        assert_eq!(frame.to_string(), " xy ");
+        frame.move_to(1, 0);
+        frame.print("中", ContentStyle::new());
+        assert_eq!(frame.to_string(), " 中 ");
+    }
+
+    #[test]
+    fn render_draws_every_cell_without_a_previous_frame() {
+        let frame = frame(3, 2, &["ab", "c"]);
+        assert_eq!(
+            render(&frame, None),
+            "\u{1b}[?25l\u{1b}[2J\u{1b}[1;1Hab \u{1b}[2;1Hc  "
+        );
+    }
+
+    #[test]
+    fn render_draws_only_changed_cells() {
+        let previous = frame(5, 2, &["abcde", "fghij"]);
+        let mut next = frame(5, 2, &["abXYe", "fghij"]);
+        next.cursor = Some(Position {
+            x: 1,
+            x_preferred: 1,
+            y: 1,
+        });
+        assert_eq!(
+            render(&next, Some(&previous)),
+            "\u{1b}[?25l\u{1b}[1;3HXY\u{1b}[2;2H\u{1b}[?25h"
+        );
+        assert_eq!(render(&previous, Some(&previous)), "\u{1b}[?25l");
+    }
+
+    #[test]
+    fn render_draws_wide_clusters_whole() {
+        let previous = frame(4, 1, &["中ab"]);
+        let mut next = frame(4, 1, &["中ab"]);
+        next.move_to(0, 0);
+        let red = ContentStyle::new().red();
+        next.print("中", red);
+        let output = render(&next, Some(&previous));
+        let expected = format!("\u{1b}[?25l\u{1b}[1;1H{}", red.apply("中"));
+        assert_eq!(output, expected);
+        let next = frame(4, 1, &["中xb"]);
+        assert_eq!(render(&next, Some(&previous)), "\u{1b}[?25l\u{1b}[1;3Hx");
+    }
+
+    #[test]
+    fn render_redraws_everything_after_a_resize() {
+        let previous = frame(3, 1, &["abc"]);
+        let next = frame(2, 1, &["ab"]);
+        assert_eq!(
+            render(&next, Some(&previous)),
+            "\u{1b}[?25l\u{1b}[2J\u{1b}[1;1Hab"
+        );
+    }
+}
@@ -0,0 +1,493 @@
+#![warn(clippy::all, clippy::pedantic)]
+use ropey::{str_utils::byte_to_char_idx, Rope, RopeSlice};
+use std::{cmp, iter, ops::Range};
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete, UnicodeSegmentation};
+use unicode_width::UnicodeWidthStr;
+
+/// How many chars of a row are laid out at first. Each further
+/// piece of the row is twice as long as the one before, so that
+/// only as much of a long row is laid out as is needed.
+const CHUNK_LEN: usize = 256;
+
+/// A grapheme cluster of a row, as it is drawn on the screen.
+pub struct Grapheme {
+    /// The char index of the start of the grapheme in its row.
+    pub start: usize,
+    /// The char index just past the end of the grapheme.
+    pub end: usize,
+    /// The screen column the grapheme starts at, counting from the
+    /// start of the row.
+    pub column: usize,
+    /// The text drawn for the grapheme.
+    pub text: String,
+    /// The number of columns the grapheme takes up.
+    pub width: usize,
+    /// Whether the grapheme is the line break that ends its row.
+    pub line_break: bool,
+}
+
+/// A grapheme cluster of a row as it is laid out, before the text
+/// drawn for it is built.
+struct Cluster<'a> {
+    text: &'a str,
+    start: usize,
+    end: usize,
+    column: usize,
+    width: usize,
+}
+
+impl Cluster<'_> {
+    /// Takes itself.
+    /// Returns the `Grapheme` drawn for it.
+    fn to_grapheme(&self) -> Grapheme {
+        Grapheme {
+            start: self.start,
+            end: self.end,
+            column: self.column,
+            text: display(self.text, self.width),
+            width: self.width,
+            line_break: is_line_break(self.text),
+        }
+    }
+}
+
+/// Takes a grapheme cluster.
+/// Returns a bool representing whether it is a line break. Rows
+/// only end at `\n`, so a `\r` on its own is a control char.
+#[must_use]
+pub fn is_line_break(grapheme: &str) -> bool {
+    grapheme.ends_with('\n')
+}
+
+/// Takes a grapheme cluster, the screen column it starts at and
+/// the width of a tab stop.
+/// Returns the number of columns it takes up. Tabs reach up to the
+/// next tab stop, line breaks take up one column, C0 control chars
+/// two, C1 control chars four, and clusters with no width of their
+/// own one.
+fn width(grapheme: &str, column: usize, tab_width: usize) -> usize {
+    let first = grapheme.chars().next().unwrap_or(' ');
+    if first == '\t' {
+        tab_width - column % tab_width
+    } else if is_line_break(grapheme) {
+        1
+    } else if is_c1_control(first) {
+        4
+    } else if first.is_control() {
+        2
+    } else if grapheme.len() == 1 {
+        // Printable ASCII, which most text is, skips the width tables.
+        1
+    } else {
+        cmp::max(grapheme.width(), 1)
+    }
+}
+
+/// Takes a grapheme cluster and the number of columns it takes up.
+/// Returns the text to draw for it. Tabs are drawn as spaces up to
+/// the next tab stop, line breaks as a single space, C1 control
+/// chars as their hex code, as in `<85>`, other control chars in
+/// caret notation, and clusters with no width of their own on top
+/// of a space.
+fn display(grapheme: &str, width: usize) -> String {
+    let first = grapheme.chars().next().unwrap_or(' ');
+    if first == '\t' {
+        return " ".repeat(width);
+    }
+    if is_line_break(grapheme) {
+        return String::from(" ");
+    }
+    if is_c1_control(first) {
+        return format!("<{:02X}>", u32::from(first));
+    }
+    if first.is_control() {
+        let caret = match first {
+            '\u{7f}' => '?',
+            c => char::from_u32(u32::from(c) ^ 0x40).unwrap_or('?'),
+        };
+        return format!("^{caret}");
+    }
+    if grapheme.width() == 0 {
+        format!(" {grapheme}")
+    } else {
+        grapheme.to_string()
+    }
+}
+
+/// Takes a char.
+/// Returns a bool representing whether it is a C1 control char,
+/// which have no caret notation.
+fn is_c1_control(c: char) -> bool {
+    ('\u{80}'..='\u{9f}').contains(&c)
+}
+
+/// Takes a row of a rope, the width of a tab stop and a function
+/// that is given each grapheme cluster of the row in turn and
+/// returns whether it has seen all the clusters it needs.
+/// Lays out the row piece by piece until the function is done, so
+/// that the cost grows with how far into the row it looks rather
+/// than with the length of the row.
+fn scan(row: RopeSlice, tab_width: usize, mut done: impl FnMut(&Cluster) -> bool) {
+    let len_chars = row.len_chars();
+    let mut start = 0;
+    let mut column = 0;
+    let mut chunk_len = CHUNK_LEN;
+    while start < len_chars {
+        let chunk_end = cmp::min(len_chars, start.saturating_add(chunk_len));
+        let text = row.slice(start..chunk_end).to_string();
+        let mut clusters = split_clusters(&text).peekable();
+        while let Some(text) = clusters.next() {
+            // The last cluster of a piece may have been cut short, so
+            // it is laid out again as part of the next piece.
+            if chunk_end < len_chars && clusters.peek().is_none() {
+                break;
+            }
+            let cluster = Cluster {
+                text,
+                start,
+                end: start + text.chars().count(),
+                column,
+                width: width(text, column, tab_width),
+            };
+            if done(&cluster) {
+                return;
+            }
+            start = cluster.end;
+            column += cluster.width;
+        }
+        chunk_len = chunk_len.saturating_mul(2);
+    }
+}
+
+/// Takes some text.
+/// Returns its grapheme clusters. ASCII text is split without the
+/// full segmentation rules, as each of its chars is a cluster of its
+/// own apart from `\r\n`.
+fn split_clusters(text: &str) -> impl Iterator<Item = &str> {
+    let ascii = text.is_ascii();
+    let mut graphemes = text.graphemes(true);
+    let mut rest = text;
+    iter::from_fn(move || {
+        if !ascii {
+            return graphemes.next();
+        }
+        if rest.is_empty() {
+            return None;
+        }
+        let (cluster, tail) = rest.split_at(if rest.starts_with("\r\n") { 2 } else { 1 });
+        rest = tail;
+        Some(cluster)
+    })
+}
+
+/// Takes a row of a rope and the width of a tab stop.
+/// Returns the grapheme clusters of the row, including its line
+/// break if it has one.
+#[must_use]
+pub fn graphemes(row: RopeSlice, tab_width: usize) -> Vec<Grapheme> {
+    let mut graphemes = Vec::new();
+    scan(row, tab_width, |cluster| {
+        graphemes.push(cluster.to_grapheme());
+        false
+    });
+    graphemes
+}
+
+/// Takes a row of a rope, the width of a tab stop and a range of
+/// screen columns.
+/// Returns the grapheme clusters of the row that are drawn at least
+/// partly within the columns.
+#[must_use]
+pub fn graphemes_in(row: RopeSlice, tab_width: usize, columns: Range<usize>) -> Vec<Grapheme> {
+    let mut graphemes = Vec::new();
+    scan(row, tab_width, |cluster| {
+        if cluster.column >= columns.end {
+            return true;
+        }
+        if cluster.column + cluster.width > columns.start {
+            graphemes.push(cluster.to_grapheme());
+        }
+        false
+    });
+    graphemes
+}
+
+/// Takes a row of a rope, a char index in the row and the width of
+/// a tab stop.
+/// Returns the grapheme cluster that contains the char index, or
+/// `None` if it is past the end of the row.
+#[must_use]
+pub fn grapheme_at(row: RopeSlice, char_idx: usize, tab_width: usize) -> Option<Grapheme> {
+    let mut grapheme = None;
+    scan(row, tab_width, |cluster| {
+        if cluster.end > char_idx {
+            grapheme = Some(cluster.to_grapheme());
+        }
+        grapheme.is_some()
+    });
+    grapheme
+}
+
+/// Takes a list of grapheme clusters.
+/// Returns the number of columns they take up.
+#[must_use]
+pub fn line_width(graphemes: &[Grapheme]) -> usize {
+    graphemes.iter().map(|grapheme| grapheme.width).sum()
+}
+
+/// Takes a row of a rope and a char index in the row.
+/// Returns the char index of the start of the grapheme cluster
+/// that contains it, or the char index itself if it is past the
+/// end of the row.
+#[must_use]
+pub fn start_of(row: RopeSlice, char_idx: usize) -> usize {
+    if char_idx >= row.len_chars() {
+        return char_idx;
+    }
+    boundary_before(row, char_idx + 1)
+}
+
+/// Takes a row of a rope, a char index in the row and the width
+/// of a tab stop.
+/// Returns the screen column the grapheme cluster containing the
+/// char index starts at, counting from the start of the row.
+#[must_use]
+pub fn column(row: RopeSlice, char_idx: usize, tab_width: usize) -> usize {
+    let mut column = 0;
+    scan(row, tab_width, |cluster| {
+        column = cluster.column;
+        if cluster.end > char_idx {
+            return true;
+        }
+        column += cluster.width;
+        false
+    });
+    column
+}
+
+/// Takes a row of a rope, a screen column and the width of a
+/// tab stop.
+/// Returns the char index of the grapheme cluster drawn at the
+/// column, or of the last one if the row is shorter.
+#[must_use]
+pub fn char_idx_at_column(row: RopeSlice, column: usize, tab_width: usize) -> usize {
+    let mut char_idx = 0;
+    scan(row, tab_width, |cluster| {
+        char_idx = cluster.start;
+        cluster.column + cluster.width > column
+    });
+    char_idx
+}
+
+/// Takes a rope and a char index.
+/// Returns the char index of the start of the grapheme cluster
+/// before the one containing the char index.
+#[must_use]
+pub fn prev_boundary(rope: &Rope, char_idx: usize) -> usize {
+    if char_idx == 0 {
+        return 0;
+    }
+    let line_idx = rope.char_to_line(char_idx - 1);
+    let line_start = rope.line_to_char(line_idx);
+    line_start + start_of(rope.line(line_idx), char_idx - 1 - line_start)
+}
+
+/// Takes a rope and a char index.
+/// Returns the char index just past the end of the grapheme
+/// cluster containing the char index.
+#[must_use]
+pub fn next_boundary(rope: &Rope, char_idx: usize) -> usize {
+    if char_idx >= rope.len_chars() {
+        return rope.len_chars();
+    }
+    let line_idx = rope.char_to_line(char_idx);
+    let line_start = rope.line_to_char(line_idx);
+    line_start + boundary_after(rope.line(line_idx), char_idx - line_start)
+}
+
+/// Takes a row of a rope and a char index in the row, which must
+/// not be 0.
+/// Returns the char index of the last grapheme cluster boundary
+/// before it. Only the text around the char index is looked at.
+fn boundary_before(row: RopeSlice, char_idx: usize) -> usize {
+    let byte_idx = row.char_to_byte(char_idx);
+    let (mut chunk, mut chunk_byte_idx, mut chunk_char_idx, _) = row.chunk_at_byte(byte_idx);
+    let mut cursor = GraphemeCursor::new(byte_idx, row.len_bytes(), true);
+    loop {
+        match cursor.prev_boundary(chunk, chunk_byte_idx) {
+            Ok(None) => return 0,
+            Ok(Some(boundary)) => {
+                return chunk_char_idx + byte_to_char_idx(chunk, boundary - chunk_byte_idx);
+            }
+            Err(GraphemeIncomplete::PrevChunk) => {
+                (chunk, chunk_byte_idx, chunk_char_idx, _) = row.chunk_at_byte(chunk_byte_idx - 1);
+            }
+            Err(GraphemeIncomplete::PreContext(end)) => {
+                let context = row.chunk_at_byte(end - 1).0;
+                cursor.provide_context(context, end - context.len());
+            }
+            Err(_) => unreachable!("only earlier text is asked for"),
+        }
+    }
+}
+
+/// Takes a row of a rope and a char index in the row, which must
+/// be before its end.
+/// Returns the char index of the first grapheme cluster boundary
+/// after it. Only the text around the char index is looked at.
+fn boundary_after(row: RopeSlice, char_idx: usize) -> usize {
+    let byte_idx = row.char_to_byte(char_idx);
+    let (mut chunk, mut chunk_byte_idx, mut chunk_char_idx, _) = row.chunk_at_byte(byte_idx);
+    let mut cursor = GraphemeCursor::new(byte_idx, row.len_bytes(), true);
+    loop {
+        match cursor.next_boundary(chunk, chunk_byte_idx) {
+            Ok(None) => return row.len_chars(),
+            Ok(Some(boundary)) => {
+                return chunk_char_idx + byte_to_char_idx(chunk, boundary - chunk_byte_idx);
+            }
+            Err(GraphemeIncomplete::NextChunk) => {
+                chunk_byte_idx += chunk.len();
+                (chunk, _, chunk_char_idx, _) = row.chunk_at_byte(chunk_byte_idx);
+            }
+            Err(GraphemeIncomplete::PreContext(end)) => {
+                let context = row.chunk_at_byte(end - 1).0;
+                cursor.provide_context(context, end - context.len());
+            }
+            Err(_) => unreachable!("only earlier text is asked for"),
+        }
+    }
+}
+
+/// How the rows of a buffer are split into screen lines when soft
+/// wrap is on.
+#[derive(Clone, Copy)]
+pub struct Wrap {
+    /// The number of columns of text a screen line holds.
+    pub width: usize,
+    /// The width of the indicator drawn at the start of each
+    /// continuation line.
+    pub indicator_width: usize,
+    /// Whether rows are broken after whitespace rather than at any
+    /// grapheme cluster.
+    pub at_words: bool,
+}
+
+impl Wrap {
+    /// Takes itself and whether a screen line continues a row.
+    /// Returns the number of columns of text the screen line holds.
+    #[must_use]
+    pub fn line_width(self, continuation: bool) -> usize {
+        if continuation {
+            cmp::max(self.width.saturating_sub(self.indicator_width), 1)
+        } else {
+            cmp::max(self.width, 1)
+        }
+    }
+
+    /// Takes itself and the grapheme clusters of a row.
+    /// Returns the range of grapheme clusters on each screen line
+    /// the row is split into. The line break stays on the last one.
+    #[must_use]
+    pub fn split(self, graphemes: &[Grapheme]) -> Vec<Range<usize>> {
+        let mut lines = Vec::new();
+        let mut start = 0;
+        let mut column = 0;
+        for (i, grapheme) in graphemes.iter().enumerate() {
+            let width = self.line_width(!lines.is_empty());
+            if i > start && !grapheme.line_break && column + grapheme.width > width {
+                let carried_width = |end: usize| -> usize {
+                    graphemes[end..=i]
+                        .iter()
+                        .map(|grapheme| grapheme.width)
+                        .sum()
+                };
+                let end = graphemes[start..i]
+                    .iter()
+                    .rposition(|grapheme| grapheme.text.trim().is_empty())
+                    .map(|last_space| start + last_space + 1)
+                    .filter(|&end| {
+                        self.at_words && end > start && carried_width(end) <= self.line_width(true)
+                    })
+                    .unwrap_or(i);
+                lines.push(start..end);
+                start = end;
+                column = carried_width(end) - grapheme.width;
+            }
+            column += grapheme.width;
+        }
+        lines.push(start..graphemes.len());
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(
+        text: &str,
+        width: usize,
+        indicator_width: usize,
+        at_words: bool,
+    ) -> Vec<(usize, usize)> {
+        let wrap = Wrap {
+            width,
+            indicator_width,
+            at_words,
+        };
+        let rope = Rope::from_str(text);
+        wrap.split(&graphemes(rope.line(0), 4))
+            .into_iter()
+            .map(|line| (line.start, line.end))
+            .collect()
+    }
+
+    fn drawn(text: &str) -> Vec<(String, usize)> {
+        let rope = Rope::from_str(text);
+        graphemes(rope.line(0), 4)
+            .into_iter()
+            .map(|grapheme| (grapheme.text, grapheme.width))
+            .collect()
+    }
+
+    #[test]
+    fn control_chars_are_drawn_visibly() {
+        assert_eq!(
+            drawn("\u{1}\u{7f}\u{85}\n"),
+            [
+                (String::from("^A"), 2),
+                (String::from("^?"), 2),
+                (String::from("<85>"), 4),
+                (String::from(" "), 1),
+            ]
+        );
+        assert_eq!(drawn("a\rb")[1], (String::from("^M"), 2));
+        assert_eq!(drawn("\tx")[0], (String::from("    "), 4));
+    }
+
+    #[test]
+    fn split_at_any_grapheme() {
+        assert_eq!(split("abcdef\n", 4, 0, false), [(0, 4), (4, 7)]);
+        assert_eq!(split("ab cd ef\n", 5, 0, false), [(0, 5), (5, 9)]);
+        assert_eq!(split("abcd\n", 4, 0, false), [(0, 5)]);
+        assert_eq!(split("", 4, 0, false), [(0, 0)]);
+    }
+
+    #[test]
+    fn split_after_whitespace() {
+        assert_eq!(split("ab cd ef\n", 5, 0, true), [(0, 3), (3, 9)]);
+        assert_eq!(split("abcdefgh", 3, 0, true), [(0, 3), (3, 6), (6, 8)]);
+    }
+
+    #[test]
+    fn continuation_lines_leave_room_for_the_indicator() {
+        assert_eq!(split("abcdefgh", 4, 2, false), [(0, 4), (4, 6), (6, 8)]);
+        assert_eq!(split("abcdef", 4, 8, false), [(0, 4), (4, 5), (5, 6)]);
+    }
+
+    #[test]
+    fn wide_graphemes_move_to_the_next_line_whole() {
+        assert_eq!(split("a中b", 2, 0, false), [(0, 1), (1, 2), (2, 3)]);
+        assert_eq!(split("\tabc", 5, 0, false), [(0, 2), (2, 4)]);
+    }
+}
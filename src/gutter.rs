@@ -0,0 +1,91 @@
+#![warn(clippy::all, clippy::pedantic)]
+use std::{cmp, fmt, str::FromStr};
+
+/// The fewest digits the line numbers take up, so that the gutter
+/// does not change width for short files.
+const MIN_NUMBER_DIGITS: usize = 3;
+
+/// How the gutter numbers the rows of a window.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LineNumbers {
+    None,
+    /// The number of each line.
+    Absolute,
+    /// The distance from each line to the cursor.
+    Relative,
+    /// The number of the cursor line, and the distance to it on
+    /// every other line.
+    Hybrid,
+}
+
+impl FromStr for LineNumbers {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(LineNumbers::None),
+            "absolute" => Ok(LineNumbers::Absolute),
+            "relative" => Ok(LineNumbers::Relative),
+            "hybrid" => Ok(LineNumbers::Hybrid),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for LineNumbers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LineNumbers::None => write!(f, "none"),
+            LineNumbers::Absolute => write!(f, "absolute"),
+            LineNumbers::Relative => write!(f, "relative"),
+            LineNumbers::Hybrid => write!(f, "hybrid"),
+        }
+    }
+}
+
+/// The columns drawn to the left of the text of a window: a column
+/// for diagnostic and diff markers, and the line numbers.
+#[derive(Clone, Copy)]
+pub struct Gutter {
+    pub line_numbers: LineNumbers,
+    pub marker_column: bool,
+}
+
+impl Gutter {
+    /// Takes itself.
+    /// Returns the width of the marker column, if there is one.
+    #[must_use]
+    pub fn marker_width(self) -> usize {
+        usize::from(self.marker_column)
+    }
+
+    /// Takes itself and the number of lines in a buffer.
+    /// Returns the width of the gutter, including the space that
+    /// separates the numbers from the text.
+    #[must_use]
+    pub fn width(self, line_count: usize) -> usize {
+        if self.line_numbers == LineNumbers::None {
+            return self.marker_width();
+        }
+        let digits = line_count.max(1).ilog10() as usize + 1;
+        self.marker_width() + cmp::max(digits, MIN_NUMBER_DIGITS) + 1
+    }
+
+    /// Takes itself, the index of a row, the row of the cursor and
+    /// the width of the gutter.
+    /// Returns the line number of the row, padded to fill the gutter
+    /// after the marker column.
+    #[must_use]
+    pub fn number(self, row_idx: usize, cursor_row: usize, width: usize) -> String {
+        let width = width.saturating_sub(self.marker_width());
+        let number_width = width.saturating_sub(1);
+        let distance = row_idx.abs_diff(cursor_row);
+        let number = match self.line_numbers {
+            LineNumbers::None => String::new(),
+            LineNumbers::Absolute => format!("{:>number_width$} ", row_idx + 1),
+            LineNumbers::Hybrid if distance == 0 => format!("{:<number_width$} ", row_idx + 1),
+            LineNumbers::Relative | LineNumbers::Hybrid => format!("{distance:>number_width$} "),
+        };
+        number.chars().take(width).collect()
+    }
+}
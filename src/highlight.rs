@@ -0,0 +1,1102 @@
+#![warn(clippy::all, clippy::pedantic)]
+use ropey::{Rope, RopeSlice};
+use std::{ops::Range, path::Path};
+
+/// The kind of text a highlighted span holds.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Comment,
+    Keyword,
+    Type,
+    Function,
+    Macro,
+    String,
+    Escape,
+    Number,
+    Constant,
+    Operator,
+    Attribute,
+    Lifetime,
+    Property,
+    Table,
+    Heading,
+    Emphasis,
+    Strong,
+    Code,
+    Link,
+    Quote,
+    ListMarker,
+    Punctuation,
+}
+
+impl Scope {
+    /// Takes itself.
+    /// Returns the name a theme styles the scope by. Names are
+    /// dotted, so a theme that lacks `constant.numeric` falls back
+    /// to `constant`.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::Comment => "comment",
+            Scope::Keyword => "keyword",
+            Scope::Type => "type",
+            Scope::Function => "function",
+            Scope::Macro => "function.macro",
+            Scope::String => "string",
+            Scope::Escape => "string.escape",
+            Scope::Number => "constant.numeric",
+            Scope::Constant => "constant",
+            Scope::Operator => "operator",
+            Scope::Attribute => "attribute",
+            Scope::Lifetime => "label",
+            Scope::Property => "property",
+            Scope::Table => "namespace",
+            Scope::Heading => "markup.heading",
+            Scope::Emphasis => "markup.italic",
+            Scope::Strong => "markup.bold",
+            Scope::Code => "markup.raw",
+            Scope::Link => "markup.link",
+            Scope::Quote => "markup.quote",
+            Scope::ListMarker => "markup.list",
+            Scope::Punctuation => "punctuation",
+        }
+    }
+}
+
+/// A range of columns in a line and the scope they belong to.
+#[derive(Clone)]
+pub struct Span {
+    pub range: Range<usize>,
+    pub scope: Scope,
+}
+
+/// A language that Reflex can highlight.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    Rust,
+    Toml,
+    Markdown,
+    Nix,
+}
+
+impl Language {
+    /// Takes a file path.
+    /// Returns the language of the file, judging by its extension.
+    #[must_use]
+    pub fn from_path(path: &Path) -> Option<Self> {
+        if path.file_name().is_some_and(|name| name == "Cargo.lock") {
+            return Some(Language::Toml);
+        }
+        match path.extension()?.to_str()? {
+            "rs" => Some(Language::Rust),
+            "toml" => Some(Language::Toml),
+            "md" | "markdown" => Some(Language::Markdown),
+            "nix" => Some(Language::Nix),
+            _ => None,
+        }
+    }
+
+    /// Takes itself.
+    /// Returns the name of the language.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::Rust => "rust",
+            Language::Toml => "toml",
+            Language::Markdown => "markdown",
+            Language::Nix => "nix",
+        }
+    }
+}
+
+/// The constructs that can carry over from one line to the next.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum LineState {
+    #[default]
+    Normal,
+    BlockComment(u8),
+    String,
+    RawString(u8),
+    MultiLineString {
+        literal: bool,
+    },
+    IndentedString,
+    CodeFence {
+        marker: char,
+        len: u8,
+    },
+}
+
+/// Highlights the lines of a rope, remembering the state at the
+/// start of every line it has seen so that only the lines after
+/// an edit need to be tokenized again.
+pub struct Highlighter {
+    language: Language,
+    states: Vec<LineState>,
+}
+
+impl Highlighter {
+    /// Takes a `Language`.
+    /// Builds a `Highlighter` for the language.
+    #[must_use]
+    pub fn new(language: Language) -> Self {
+        Self {
+            language,
+            states: vec![LineState::Normal],
+        }
+    }
+
+    /// Takes itself.
+    /// Returns the language being highlighted.
+    #[must_use]
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// Takes itself and the index of the first line that changed.
+    /// Forgets the states of the lines after it.
+    pub fn invalidate(&mut self, line_idx: usize) {
+        self.states.truncate(line_idx.saturating_add(1));
+    }
+
+    /// Takes itself, a rope and the index of a line.
+    /// Tokenizes the lines up to and including the given one,
+    /// starting after the last line whose state is known.
+    pub fn update(&mut self, rope: &Rope, last_line: usize) {
+        let last_line = last_line.min(rope.len_lines().saturating_sub(1));
+        while self.states.len() <= last_line {
+            let line_idx = self.states.len() - 1;
+            let (_, state) = tokenize(self.language, rope.line(line_idx), self.states[line_idx]);
+            self.states.push(state);
+        }
+    }
+
+    /// Takes itself, a rope and the index of a line.
+    /// Returns the highlighted spans of the line, or nothing if the
+    /// line has not been reached by `update` yet.
+    #[must_use]
+    pub fn spans(&self, rope: &Rope, line_idx: usize) -> Vec<Span> {
+        match (self.states.get(line_idx), rope.get_line(line_idx)) {
+            (Some(state), Some(line)) => tokenize(self.language, line, *state).0,
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Takes a language, a line and the state at its start.
+/// Returns the spans of the line and the state at its end.
+fn tokenize(language: Language, line: RopeSlice, state: LineState) -> (Vec<Span>, LineState) {
+    let mut chars: Vec<char> = line.chars().collect();
+    while matches!(chars.last(), Some('\n' | '\r')) {
+        chars.pop();
+    }
+    let mut lexer = Lexer {
+        chars: &chars,
+        pos: 0,
+        spans: Vec::new(),
+    };
+    let state = match language {
+        Language::Rust => rust_line(&mut lexer, state),
+        Language::Toml => toml_line(&mut lexer, state),
+        Language::Markdown => markdown_line(&mut lexer, state),
+        Language::Nix => nix_line(&mut lexer, state),
+    };
+    (lexer.spans, state)
+}
+
+/// Walks through the chars of a single line, collecting spans.
+struct Lexer<'a> {
+    chars: &'a [char],
+    pos: usize,
+    spans: Vec<Span>,
+}
+
+impl Lexer<'_> {
+    /// Takes itself and a number of chars to look ahead.
+    /// Returns the char that far past the current one, if any.
+    fn peek(&self, ahead: usize) -> Option<char> {
+        self.chars.get(self.pos.saturating_add(ahead)).copied()
+    }
+
+    /// Takes itself and a string slice.
+    /// Returns a bool representing whether the text at the current
+    /// position starts with the string.
+    fn at(&self, text: &str) -> bool {
+        text.chars()
+            .enumerate()
+            .all(|(i, c)| self.peek(i) == Some(c))
+    }
+
+    /// Takes itself.
+    /// Returns the char before the current one, if any.
+    fn prev(&self) -> Option<char> {
+        self.pos.checked_sub(1).map(|idx| self.chars[idx])
+    }
+
+    /// Takes itself and a predicate.
+    /// Advances past every char that matches the predicate.
+    fn skip_while(&mut self, predicate: impl Fn(char) -> bool) {
+        while self.peek(0).is_some_and(&predicate) {
+            self.pos += 1;
+        }
+    }
+
+    /// Takes itself.
+    /// Returns the next char that is not whitespace, without
+    /// advancing.
+    fn next_non_space(&self) -> Option<char> {
+        self.chars[self.pos.min(self.chars.len())..]
+            .iter()
+            .copied()
+            .find(|c| !c.is_whitespace())
+    }
+
+    /// Takes itself, the column a token started at and its scope.
+    /// Adds a span from the start to the current position, joining
+    /// it to the previous span when they touch and share a scope.
+    fn span(&mut self, start: usize, scope: Scope) {
+        if self.pos <= start {
+            return;
+        }
+        if let Some(last) = self.spans.last_mut() {
+            if last.range.end == start && last.scope == scope {
+                last.range.end = self.pos;
+                return;
+            }
+        }
+        self.spans.push(Span {
+            range: start..self.pos,
+            scope,
+        });
+    }
+
+    /// Takes itself and a scope.
+    /// Gives the rest of the line the scope.
+    fn rest(&mut self, scope: Scope) {
+        let start = self.pos;
+        self.pos = self.chars.len();
+        self.span(start, scope);
+    }
+
+    /// Takes itself.
+    /// Returns a bool representing whether the whole line has been read.
+    fn is_done(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+
+    /// Takes itself, the closing quote and whether backslash escapes
+    /// are allowed.
+    /// Reads the body of a string up to and including the closing
+    /// quote. Returns a bool representing whether it was closed on
+    /// this line.
+    fn string_body(&mut self, quote: &str, escapes: bool) -> bool {
+        let start = self.pos;
+        while !self.is_done() {
+            if escapes && self.peek(0) == Some('\\') {
+                self.pos = self.pos.saturating_add(2).min(self.chars.len());
+            } else if self.at(quote) {
+                self.pos += quote.chars().count();
+                self.span(start, Scope::String);
+                return true;
+            } else {
+                self.pos += 1;
+            }
+        }
+        self.span(start, Scope::String);
+        false
+    }
+
+    /// Takes itself, the nesting depth and whether comments nest.
+    /// Reads the body of a block comment. Returns the state at the
+    /// end of the comment, or at the end of the line.
+    fn block_comment(&mut self, mut depth: u8, nested: bool) -> LineState {
+        let start = self.pos;
+        while !self.is_done() {
+            if self.at("*/") {
+                self.pos += 2;
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    self.span(start, Scope::Comment);
+                    return LineState::Normal;
+                }
+            } else if nested && self.at("/*") {
+                self.pos += 2;
+                depth = depth.saturating_add(1);
+            } else {
+                self.pos += 1;
+            }
+        }
+        self.span(start, Scope::Comment);
+        LineState::BlockComment(depth)
+    }
+
+    /// Takes itself.
+    /// Reads a number, including any suffix or fractional part.
+    fn number(&mut self) {
+        let start = self.pos;
+        self.skip_while(|c| c.is_alphanumeric() || c == '_');
+        if self.peek(0) == Some('.') && self.peek(1).is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+            self.skip_while(|c| c.is_alphanumeric() || c == '_');
+        }
+        self.span(start, Scope::Number);
+    }
+
+    /// Takes itself and the closing bracket of a group.
+    /// Reads up to and including the bracket that closes the group
+    /// opened just before the current position.
+    fn skip_group(&mut self, open: char, close: char) {
+        let mut depth = 1;
+        while let Some(c) = self.peek(0) {
+            self.pos += 1;
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Takes itself and a string slice.
+    /// Returns the position of the next occurrence of the string
+    /// at or after the current position.
+    fn find(&self, text: &str) -> Option<usize> {
+        let needle: Vec<char> = text.chars().collect();
+        (self.pos..self.chars.len()).find(|&idx| self.chars[idx..].starts_with(&needle))
+    }
+}
+
+/// Takes a char.
+/// Returns a bool representing whether it can start an identifier.
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+/// Takes a char.
+/// Returns a bool representing whether it can continue an identifier.
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "type", "unsafe", "use",
+    "where", "while", "yield",
+];
+
+const RUST_PRIMITIVES: &[&str] = &[
+    "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64",
+    "i128", "isize", "f32", "f64",
+];
+
+/// Takes a lexer and the state at the start of the line.
+/// Highlights a line of Rust.
+fn rust_line(lexer: &mut Lexer, mut state: LineState) -> LineState {
+    loop {
+        state = match state {
+            LineState::BlockComment(depth) => lexer.block_comment(depth, true),
+            LineState::String => {
+                if lexer.string_body("\"", true) {
+                    LineState::Normal
+                } else {
+                    return LineState::String;
+                }
+            }
+            LineState::RawString(hashes) => {
+                let quote = format!("\"{}", "#".repeat(usize::from(hashes)));
+                if lexer.string_body(&quote, false) {
+                    LineState::Normal
+                } else {
+                    return LineState::RawString(hashes);
+                }
+            }
+            _ => LineState::Normal,
+        };
+        if state != LineState::Normal {
+            return state;
+        }
+        lexer.skip_while(char::is_whitespace);
+        let Some(c) = lexer.peek(0) else {
+            return LineState::Normal;
+        };
+        let start = lexer.pos;
+        if lexer.at("//") {
+            lexer.rest(Scope::Comment);
+        } else if lexer.at("/*") {
+            lexer.pos += 2;
+            lexer.span(start, Scope::Comment);
+            state = LineState::BlockComment(1);
+        } else if let Some(hashes) = raw_string_start(lexer) {
+            lexer.span(start, Scope::String);
+            state = LineState::RawString(hashes);
+        } else if c == '"' || lexer.at("b\"") {
+            lexer.pos += if c == '"' { 1 } else { 2 };
+            lexer.span(start, Scope::String);
+            state = LineState::String;
+        } else if c == '\'' || lexer.at("b'") {
+            rust_quote(lexer);
+        } else if c.is_ascii_digit() {
+            lexer.number();
+        } else if is_ident_start(c) {
+            rust_ident(lexer);
+        } else if c == '#' && (lexer.peek(1) == Some('[') || lexer.at("#![")) {
+            lexer.pos += if lexer.peek(1) == Some('[') { 2 } else { 3 };
+            lexer.skip_group('[', ']');
+            lexer.span(start, Scope::Attribute);
+        } else if "=+-*/%<>!&|^?:~@".contains(c) {
+            lexer.pos += 1;
+            lexer.span(start, Scope::Operator);
+        } else {
+            lexer.pos += 1;
+        }
+    }
+}
+
+/// Takes a lexer.
+/// Reads the opening of a raw string such as `r#"`, returning the
+/// number of hashes, or returns `None` without moving if there
+/// is none.
+fn raw_string_start(lexer: &mut Lexer) -> Option<u8> {
+    let prefix = if lexer.at("br") {
+        2
+    } else {
+        usize::from(lexer.at("r"))
+    };
+    if prefix == 0 || lexer.prev().is_some_and(is_ident_char) {
+        return None;
+    }
+    let hashes = lexer.chars[lexer.pos + prefix..]
+        .iter()
+        .take_while(|c| **c == '#')
+        .count();
+    if lexer.peek(prefix + hashes) != Some('"') {
+        return None;
+    }
+    lexer.pos += prefix + hashes + 1;
+    Some(u8::try_from(hashes).unwrap_or(u8::MAX))
+}
+
+/// Takes a lexer positioned on a quote.
+/// Reads either a char literal or a lifetime.
+fn rust_quote(lexer: &mut Lexer) {
+    let start = lexer.pos;
+    if lexer.peek(0) == Some('b') {
+        lexer.pos += 1;
+    }
+    let is_char = lexer.peek(1) == Some('\\') || lexer.peek(2) == Some('\'');
+    lexer.pos += 1;
+    if is_char {
+        lexer.span(start, Scope::String);
+        lexer.string_body("'", true);
+    } else {
+        lexer.skip_while(is_ident_char);
+        lexer.span(start, Scope::Lifetime);
+    }
+}
+
+/// Takes a lexer positioned on an identifier.
+/// Reads the identifier and highlights it by what it names.
+fn rust_ident(lexer: &mut Lexer) {
+    let start = lexer.pos;
+    lexer.skip_while(is_ident_char);
+    let ident: String = lexer.chars[start..lexer.pos].iter().collect();
+    let scope = if RUST_KEYWORDS.contains(&ident.as_str()) {
+        Some(Scope::Keyword)
+    } else if ident == "true" || ident == "false" {
+        Some(Scope::Constant)
+    } else if lexer.peek(0) == Some('!') && lexer.peek(1) != Some('=') {
+        lexer.pos += 1;
+        Some(Scope::Macro)
+    } else if RUST_PRIMITIVES.contains(&ident.as_str()) {
+        Some(Scope::Type)
+    } else if ident.starts_with(char::is_uppercase) {
+        if ident.len() > 1 && !ident.contains(char::is_lowercase) {
+            Some(Scope::Constant)
+        } else {
+            Some(Scope::Type)
+        }
+    } else if lexer.peek(0) == Some('(') || lexer.at("::<") {
+        Some(Scope::Function)
+    } else {
+        None
+    };
+    if let Some(scope) = scope {
+        lexer.span(start, scope);
+    }
+}
+
+/// Takes a lexer and the state at the start of the line.
+/// Highlights a line of TOML.
+fn toml_line(lexer: &mut Lexer, state: LineState) -> LineState {
+    if let LineState::MultiLineString { literal } = state {
+        let quote = if literal { "'''" } else { "\"\"\"" };
+        if !lexer.string_body(quote, !literal) {
+            return state;
+        }
+    }
+    if state == LineState::Normal {
+        lexer.skip_while(char::is_whitespace);
+        if lexer.peek(0) == Some('[') {
+            let start = lexer.pos;
+            lexer.pos += 1;
+            lexer.skip_group('[', ']');
+            lexer.span(start, Scope::Table);
+        }
+    }
+    loop {
+        lexer.skip_while(char::is_whitespace);
+        let Some(c) = lexer.peek(0) else {
+            return LineState::Normal;
+        };
+        let start = lexer.pos;
+        if c == '#' {
+            lexer.rest(Scope::Comment);
+        } else if lexer.at("\"\"\"") || lexer.at("'''") {
+            let literal = c == '\'';
+            lexer.pos += 3;
+            lexer.span(start, Scope::String);
+            let quote = if literal { "'''" } else { "\"\"\"" };
+            if !lexer.string_body(quote, !literal) {
+                return LineState::MultiLineString { literal };
+            }
+        } else if c == '"' || c == '\'' {
+            lexer.pos += 1;
+            lexer.span(start, Scope::String);
+            lexer.string_body(if c == '"' { "\"" } else { "'" }, c == '"');
+            if is_toml_key(lexer) {
+                lexer.spans.pop();
+                lexer.span(start, Scope::Property);
+            }
+        } else if is_ident_char(c) || "+-.:".contains(c) {
+            lexer.skip_while(|c| is_ident_char(c) || "+-.:".contains(c));
+            let word: String = lexer.chars[start..lexer.pos].iter().collect();
+            let scope = if is_toml_key(lexer) {
+                Scope::Property
+            } else if word == "true" || word == "false" {
+                Scope::Constant
+            } else if word.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-')
+                || word.ends_with("inf")
+                || word.ends_with("nan")
+            {
+                Scope::Number
+            } else {
+                continue;
+            };
+            lexer.span(start, scope);
+        } else if c == '=' {
+            lexer.pos += 1;
+            lexer.span(start, Scope::Operator);
+        } else {
+            lexer.pos += 1;
+        }
+    }
+}
+
+/// Takes a lexer positioned just after a word or string.
+/// Returns a bool representing whether the word is a key, being
+/// followed by an equals sign.
+fn is_toml_key(lexer: &Lexer) -> bool {
+    lexer.next_non_space() == Some('=')
+}
+
+/// Takes a lexer and the state at the start of the line.
+/// Highlights a line of Markdown.
+fn markdown_line(lexer: &mut Lexer, state: LineState) -> LineState {
+    let indent = lexer.chars.iter().take_while(|c| **c == ' ').count();
+    let fence = |marker: char| {
+        lexer.chars[indent..]
+            .iter()
+            .take_while(|c| **c == marker)
+            .count()
+    };
+    let fences = [fence('`'), fence('~')];
+    if let LineState::CodeFence { marker, len } = state {
+        let closing = indent < 4 && fences[usize::from(marker == '~')] >= usize::from(len);
+        lexer.rest(Scope::Code);
+        return if closing { LineState::Normal } else { state };
+    }
+    if indent < 4 {
+        for (marker, len) in ['`', '~'].into_iter().zip(fences) {
+            if len >= 3 {
+                lexer.rest(Scope::Code);
+                return LineState::CodeFence {
+                    marker,
+                    len: u8::try_from(len).unwrap_or(u8::MAX),
+                };
+            }
+        }
+    }
+    lexer.pos = indent;
+    let start = lexer.pos;
+    let hashes = lexer.chars[start..]
+        .iter()
+        .take_while(|c| **c == '#')
+        .count();
+    if (1..=6).contains(&hashes) && lexer.peek(hashes).is_none_or(|c| c == ' ') {
+        lexer.rest(Scope::Heading);
+        return LineState::Normal;
+    }
+    if lexer.peek(0) == Some('>') {
+        lexer.rest(Scope::Quote);
+        return LineState::Normal;
+    }
+    let rest: String = lexer.chars[start..].iter().filter(|c| **c != ' ').collect();
+    if rest.len() >= 3
+        && ['-', '*', '_']
+            .iter()
+            .any(|marker| rest.chars().all(|c| c == *marker))
+    {
+        lexer.rest(Scope::Punctuation);
+        return LineState::Normal;
+    }
+    if lexer.peek(0).is_some_and(|c| "-*+".contains(c)) && lexer.peek(1) == Some(' ') {
+        lexer.pos += 1;
+        lexer.span(start, Scope::ListMarker);
+    } else {
+        let digits = lexer.chars[start..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if digits > 0
+            && lexer.peek(digits).is_some_and(|c| c == '.' || c == ')')
+            && lexer.peek(digits + 1) == Some(' ')
+        {
+            lexer.pos += digits + 1;
+            lexer.span(start, Scope::ListMarker);
+        }
+    }
+    markdown_inline(lexer);
+    LineState::Normal
+}
+
+/// Takes a lexer.
+/// Highlights the inline code, emphasis and links in the rest
+/// of a Markdown line.
+fn markdown_inline(lexer: &mut Lexer) {
+    while let Some(c) = lexer.peek(0) {
+        let start = lexer.pos;
+        if c == '\\' {
+            lexer.pos = lexer.pos.saturating_add(2).min(lexer.chars.len());
+        } else if c == '`' {
+            let len = lexer.chars[start..]
+                .iter()
+                .take_while(|c| **c == '`')
+                .count();
+            let ticks = "`".repeat(len);
+            lexer.pos += len;
+            if let Some(end) = lexer.find(&ticks) {
+                lexer.pos = end + len;
+                lexer.span(start, Scope::Code);
+            }
+        } else if lexer.at("**") || lexer.at("__") {
+            let marker = if c == '*' { "**" } else { "__" };
+            lexer.pos += 2;
+            match lexer.find(marker) {
+                Some(end) if end > lexer.pos => {
+                    lexer.pos = end + 2;
+                    lexer.span(start, Scope::Strong);
+                }
+                _ => (),
+            }
+        } else if (c == '*' || c == '_')
+            && !(c == '_' && lexer.prev().is_some_and(char::is_alphanumeric))
+            && lexer.peek(1).is_some_and(|next| !next.is_whitespace())
+        {
+            lexer.pos += 1;
+            if let Some(end) = lexer.find(&c.to_string()) {
+                lexer.pos = end + 1;
+                lexer.span(start, Scope::Emphasis);
+            }
+        } else if c == '[' || lexer.at("![") {
+            lexer.pos += if c == '[' { 1 } else { 2 };
+            lexer.skip_group('[', ']');
+            if lexer.peek(0) == Some('(') {
+                lexer.pos += 1;
+                lexer.skip_group('(', ')');
+                lexer.span(start, Scope::Link);
+            } else {
+                lexer.pos = start + 1;
+            }
+        } else if lexer.at("<http") {
+            if let Some(end) = lexer.find(">") {
+                lexer.pos = end + 1;
+                lexer.span(start, Scope::Link);
+            } else {
+                lexer.pos += 1;
+            }
+        } else {
+            lexer.pos += 1;
+        }
+    }
+}
+
+const NIX_KEYWORDS: &[&str] = &[
+    "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+];
+
+const NIX_BUILTINS: &[&str] = &[
+    "abort",
+    "baseNameOf",
+    "builtins",
+    "derivation",
+    "dirOf",
+    "fetchTarball",
+    "import",
+    "isNull",
+    "map",
+    "removeAttrs",
+    "throw",
+    "toString",
+];
+
+/// Takes a lexer and the state at the start of the line.
+/// Highlights a line of Nix.
+fn nix_line(lexer: &mut Lexer, mut state: LineState) -> LineState {
+    loop {
+        state = match state {
+            LineState::BlockComment(depth) => lexer.block_comment(depth, false),
+            LineState::String => nix_string(lexer, false),
+            LineState::IndentedString => nix_string(lexer, true),
+            _ => LineState::Normal,
+        };
+        if state != LineState::Normal {
+            return state;
+        }
+        lexer.skip_while(char::is_whitespace);
+        let Some(c) = lexer.peek(0) else {
+            return LineState::Normal;
+        };
+        let start = lexer.pos;
+        if c == '#' {
+            lexer.rest(Scope::Comment);
+        } else if lexer.at("/*") {
+            lexer.pos += 2;
+            lexer.span(start, Scope::Comment);
+            state = LineState::BlockComment(1);
+        } else if c == '"' {
+            lexer.pos += 1;
+            lexer.span(start, Scope::String);
+            state = LineState::String;
+        } else if lexer.at("''") {
+            lexer.pos += 2;
+            lexer.span(start, Scope::String);
+            state = LineState::IndentedString;
+        } else if lexer.at("./") || lexer.at("../") || lexer.at("~/") {
+            lexer.skip_while(is_nix_path_char);
+            lexer.span(start, Scope::String);
+        } else if c == '<' && lexer.peek(1).is_some_and(char::is_alphabetic) {
+            lexer.pos += 1;
+            lexer.skip_while(is_nix_path_char);
+            if lexer.peek(0) == Some('>') {
+                lexer.pos += 1;
+                lexer.span(start, Scope::String);
+            } else {
+                lexer.pos = start + 1;
+                lexer.span(start, Scope::Operator);
+            }
+        } else if c.is_ascii_digit() {
+            lexer.number();
+        } else if is_ident_start(c) {
+            nix_ident(lexer);
+        } else if "=+-*/<>!&|?:@".contains(c) {
+            lexer.pos += 1;
+            lexer.span(start, Scope::Operator);
+        } else {
+            lexer.pos += 1;
+        }
+    }
+}
+
+/// Takes a char.
+/// Returns a bool representing whether it can be part of a path.
+fn is_nix_path_char(c: char) -> bool {
+    c.is_alphanumeric() || "._-+/~".contains(c)
+}
+
+/// Takes a lexer positioned on an identifier.
+/// Reads the identifier, or a URL starting with it, and highlights
+/// it by what it names.
+fn nix_ident(lexer: &mut Lexer) {
+    let start = lexer.pos;
+    lexer.skip_while(|c| is_ident_char(c) || c == '-' || c == '\'');
+    if lexer.at("://") {
+        lexer.skip_while(|c| !c.is_whitespace() && c != ';' && c != '"');
+        lexer.span(start, Scope::String);
+        return;
+    }
+    let ident: String = lexer.chars[start..lexer.pos].iter().collect();
+    let after = lexer.next_non_space();
+    let is_binding = after == Some('=')
+        && lexer.chars[lexer.pos..]
+            .iter()
+            .skip_while(|c| c.is_whitespace())
+            .nth(1)
+            != Some(&'=');
+    let scope = if NIX_KEYWORDS.contains(&ident.as_str()) {
+        Some(Scope::Keyword)
+    } else if ["true", "false", "null"].contains(&ident.as_str()) {
+        Some(Scope::Constant)
+    } else if is_binding {
+        Some(Scope::Property)
+    } else if NIX_BUILTINS.contains(&ident.as_str()) {
+        Some(Scope::Function)
+    } else {
+        None
+    };
+    if let Some(scope) = scope {
+        lexer.span(start, scope);
+    }
+}
+
+/// Takes a lexer inside a string and whether it is an indented
+/// `''` string.
+/// Reads the rest of the string, highlighting interpolations.
+/// Returns the state at the end of the string or line.
+fn nix_string(lexer: &mut Lexer, indented: bool) -> LineState {
+    let mut start = lexer.pos;
+    while !lexer.is_done() {
+        if lexer.at("${") {
+            lexer.span(start, Scope::String);
+            let interpolation = lexer.pos;
+            lexer.pos += 2;
+            lexer.skip_group('{', '}');
+            lexer.span(interpolation, Scope::Escape);
+            start = lexer.pos;
+        } else if indented && (lexer.at("'''") || lexer.at("''$") || lexer.at("''\\")) {
+            lexer.pos = lexer.pos.saturating_add(3).min(lexer.chars.len());
+        } else if indented && lexer.at("''") {
+            lexer.pos += 2;
+            lexer.span(start, Scope::String);
+            return LineState::Normal;
+        } else if !indented && lexer.peek(0) == Some('\\') {
+            lexer.pos = lexer.pos.saturating_add(2).min(lexer.chars.len());
+        } else if !indented && lexer.peek(0) == Some('"') {
+            lexer.pos += 1;
+            lexer.span(start, Scope::String);
+            return LineState::Normal;
+        } else {
+            lexer.pos += 1;
+        }
+    }
+    lexer.span(start, Scope::String);
+    if indented {
+        LineState::IndentedString
+    } else {
+        LineState::String
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Spans = Vec<(usize, usize, &'static str)>;
+
+    /// Takes a language and some text.
+    /// Returns the spans of each line, with their scope names, and
+    /// the state at the end of each line.
+    fn highlight(language: Language, text: &str) -> (Vec<Spans>, Vec<LineState>) {
+        let mut state = LineState::Normal;
+        let mut spans = Vec::new();
+        let mut states = Vec::new();
+        for line in Rope::from_str(text).lines() {
+            let (line_spans, next) = tokenize(language, line, state);
+            spans.push(
+                line_spans
+                    .iter()
+                    .map(|span| (span.range.start, span.range.end, span.scope.name()))
+                    .collect(),
+            );
+            states.push(next);
+            state = next;
+        }
+        (spans, states)
+    }
+
+    #[test]
+    fn rust_raw_strings_and_nested_comments_span_lines() {
+        let (spans, states) = highlight(
+            Language::Rust,
+            "let x = r#\"a\"b\n\"# /* a /* b */\nc */ 1.5f32\n",
+        );
+        assert_eq!(
+            spans[0],
+            [(0, 3, "keyword"), (6, 7, "operator"), (8, 14, "string")]
+        );
+        assert_eq!(spans[1], [(0, 2, "string"), (3, 15, "comment")]);
+        assert_eq!(spans[2], [(0, 4, "comment"), (5, 11, "constant.numeric")]);
+        assert!(states[0] == LineState::RawString(1));
+        assert!(states[1] == LineState::BlockComment(1));
+        assert!(states[2] == LineState::Normal);
+    }
+
+    #[test]
+    fn rust_tokens() {
+        let (spans, states) = highlight(
+            Language::Rust,
+            "foo(\"s\\\"\")\n#[derive(Debug)]\n'a' 'b b'\\n' Vec::<u8>::new() MAX println!(x) != \"open\n",
+        );
+        assert_eq!(spans[0], [(0, 3, "function"), (4, 9, "string")]);
+        assert_eq!(spans[1], [(0, 16, "attribute")]);
+        assert_eq!(
+            spans[2],
+            [
+                (0, 3, "string"),
+                (4, 6, "label"),
+                (7, 12, "string"),
+                (13, 16, "type"),
+                (16, 19, "operator"),
+                (19, 21, "type"),
+                (21, 24, "operator"),
+                (24, 27, "function"),
+                (30, 33, "constant"),
+                (34, 42, "function.macro"),
+                (46, 48, "operator"),
+                (49, 54, "string"),
+            ]
+        );
+        assert!(states[2] == LineState::String);
+    }
+
+    #[test]
+    fn toml_tables_keys_and_multi_line_strings() {
+        let (spans, states) = highlight(
+            Language::Toml,
+            "[a.b]\nkey = \"\"\"x\ny\"\"\" # c\n\"q\" = 1 true 'lit' 1e3 -inf\nz = '''a\nb'''\n",
+        );
+        assert_eq!(spans[0], [(0, 5, "namespace")]);
+        assert_eq!(
+            spans[1],
+            [(0, 3, "property"), (4, 5, "operator"), (6, 10, "string")]
+        );
+        assert_eq!(spans[2], [(0, 4, "string"), (5, 8, "comment")]);
+        assert_eq!(
+            spans[3],
+            [
+                (0, 3, "property"),
+                (4, 5, "operator"),
+                (6, 7, "constant.numeric"),
+                (8, 12, "constant"),
+                (13, 18, "string"),
+                (19, 22, "constant.numeric"),
+                (23, 27, "constant.numeric"),
+            ]
+        );
+        assert_eq!(spans[5], [(0, 4, "string")]);
+        assert!(states[1] == LineState::MultiLineString { literal: false });
+        assert!(states[2] == LineState::Normal);
+        assert!(states[4] == LineState::MultiLineString { literal: true });
+        assert!(states[5] == LineState::Normal);
+    }
+
+    #[test]
+    fn markdown_code_fences_span_lines() {
+        let (spans, states) = highlight(
+            Language::Markdown,
+            "```rust\nlet x;\n  ```\n~~~~\n```\n~~~~\nafter\n",
+        );
+        assert_eq!(spans[1], [(0, 6, "markup.raw")]);
+        assert_eq!(spans[4], [(0, 3, "markup.raw")]);
+        assert!(spans[6].is_empty());
+        let fence = |marker, len| LineState::CodeFence { marker, len };
+        assert!(states[0] == fence('`', 3));
+        assert!(states[1] == fence('`', 3));
+        assert!(states[2] == LineState::Normal);
+        assert!(states[3] == fence('~', 4));
+        assert!(states[4] == fence('~', 4));
+        assert!(states[5] == LineState::Normal);
+    }
+
+    #[test]
+    fn markdown_blocks_and_inline_markup() {
+        let (spans, _) = highlight(
+            Language::Markdown,
+            "# Head\n- item `code` *em* **st** [l](u)\n> q\n---\n1. x <http://a>\n",
+        );
+        assert_eq!(spans[0], [(0, 6, "markup.heading")]);
+        assert_eq!(
+            spans[1],
+            [
+                (0, 1, "markup.list"),
+                (7, 13, "markup.raw"),
+                (14, 18, "markup.italic"),
+                (19, 25, "markup.bold"),
+                (26, 32, "markup.link"),
+            ]
+        );
+        assert_eq!(spans[2], [(0, 3, "markup.quote")]);
+        assert_eq!(spans[3], [(0, 3, "punctuation")]);
+        assert_eq!(spans[4], [(0, 2, "markup.list"), (5, 15, "markup.link")]);
+    }
+
+    #[test]
+    fn nix_strings_interpolation_and_comments() {
+        let (spans, states) = highlight(
+            Language::Nix,
+            "{ a = ''\n  x ${y} '''\n''; b = \"s${c}\" ./p <nixpkgs> https://x.y;\n/* c\n*/ import x == 1; }\n",
+        );
+        assert_eq!(
+            spans[0],
+            [(2, 3, "property"), (4, 5, "operator"), (6, 8, "string")]
+        );
+        assert_eq!(
+            spans[1],
+            [(0, 4, "string"), (4, 8, "string.escape"), (8, 12, "string")]
+        );
+        assert_eq!(
+            spans[2],
+            [
+                (0, 2, "string"),
+                (4, 5, "property"),
+                (6, 7, "operator"),
+                (8, 10, "string"),
+                (10, 14, "string.escape"),
+                (14, 15, "string"),
+                (16, 19, "string"),
+                (20, 29, "string"),
+                (30, 41, "string"),
+            ]
+        );
+        assert_eq!(
+            spans[4],
+            [
+                (0, 2, "comment"),
+                (3, 9, "function"),
+                (12, 14, "operator"),
+                (15, 16, "constant.numeric"),
+            ]
+        );
+        assert!(states[0] == LineState::IndentedString);
+        assert!(states[1] == LineState::IndentedString);
+        assert!(states[2] == LineState::Normal);
+        assert!(states[3] == LineState::BlockComment(1));
+        assert!(states[4] == LineState::Normal);
+    }
+
+    #[test]
+    fn invalidate_keeps_the_states_before_an_edit() {
+        let mut rope = Rope::from_str("a\nb\nc\nd\ne\n");
+        let mut highlighter = Highlighter::new(Language::Rust);
+        highlighter.update(&rope, 2);
+        assert_eq!(highlighter.states.len(), 3);
+        highlighter.update(&rope, 10);
+        assert_eq!(highlighter.states.len(), 6);
+        rope.insert(rope.line_to_char(2), "/*");
+        highlighter.invalidate(2);
+        assert_eq!(highlighter.states.len(), 3);
+        assert!(highlighter.spans(&rope, 4).is_empty());
+        highlighter.update(&rope, 4);
+        assert_eq!(highlighter.states.len(), 5);
+        assert!(highlighter.states[..3]
+            .iter()
+            .all(|state| *state == LineState::Normal));
+        assert!(highlighter.states[3..] == [LineState::BlockComment(1); 2]);
+        let spans = highlighter.spans(&rope, 4);
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0].range == (0..1) && spans[0].scope == Scope::Comment);
+    }
+}
@@ -0,0 +1,354 @@
+#![warn(clippy::all, clippy::pedantic)]
+use crate::Selection;
+use ropey::Rope;
+use std::ops::Range;
+
+/// A single change made to the contents of a `FileBuffer`.
+#[derive(Clone)]
+pub enum Edit {
+    Insert { char_idx: usize, text: String },
+    Remove { char_idx: usize, text: String },
+}
+
+impl Edit {
+    /// Takes itself and a rope.
+    /// Applies the edit to the rope. Returns the char range it
+    /// replaced and the number of chars it replaced it with.
+    fn apply(&self, rope: &mut Rope) -> (Range<usize>, usize) {
+        match self {
+            Edit::Insert { char_idx, text } => {
+                rope.insert(*char_idx, text);
+                (*char_idx..*char_idx, text.chars().count())
+            }
+            Edit::Remove { char_idx, text } => {
+                let range = *char_idx..char_idx.saturating_add(text.chars().count());
+                rope.remove(range.clone());
+                (range, 0)
+            }
+        }
+    }
+
+    /// Takes itself.
+    /// Returns the char index the edit starts at.
+    fn char_idx(&self) -> usize {
+        match self {
+            Edit::Insert { char_idx, .. } | Edit::Remove { char_idx, .. } => *char_idx,
+        }
+    }
+
+    /// Takes itself.
+    /// Returns the edit that reverts this one.
+    fn invert(&self) -> Self {
+        match self {
+            Edit::Insert { char_idx, text } => Edit::Remove {
+                char_idx: *char_idx,
+                text: text.clone(),
+            },
+            Edit::Remove { char_idx, text } => Edit::Insert {
+                char_idx: *char_idx,
+                text: text.clone(),
+            },
+        }
+    }
+}
+
+/// A group of edits that are undone and redone together,
+/// along with the selections from before they were made.
+struct Transaction {
+    edits: Vec<Edit>,
+    selections_before: Vec<Selection>,
+    primary_selection_idx_before: usize,
+}
+
+impl Transaction {
+    /// Takes itself.
+    /// Returns the first char index changed by any of its edits.
+    fn start(&self) -> usize {
+        self.edits.iter().map(Edit::char_idx).min().unwrap_or(0)
+    }
+}
+
+/// A committed transaction, which also remembers the
+/// selections from after it was made.
+struct Revision {
+    transaction: Transaction,
+    selections_after: Vec<Selection>,
+    primary_selection_idx_after: usize,
+}
+
+/// The undo/redo history of a `FileBuffer`.
+pub struct History {
+    revisions: Vec<Revision>,
+    current: usize,
+    saved: Option<usize>,
+    pending: Option<Transaction>,
+}
+
+impl Default for History {
+    /// Takes nothing.
+    /// Builds an empty `History` whose initial state matches the disk.
+    fn default() -> Self {
+        Self {
+            revisions: Vec::new(),
+            current: 0,
+            saved: Some(0),
+            pending: None,
+        }
+    }
+}
+
+impl History {
+    /// Takes itself, an edit and the selections from before the edit.
+    /// Adds the edit to the pending transaction, starting a new
+    /// transaction if there is none.
+    pub fn record(&mut self, edit: Edit, selections: &[Selection], primary_selection_idx: usize) {
+        self.pending
+            .get_or_insert_with(|| Transaction {
+                edits: Vec::new(),
+                selections_before: selections.to_vec(),
+                primary_selection_idx_before: primary_selection_idx,
+            })
+            .edits
+            .push(edit);
+    }
+
+    /// Takes itself and the selections from after the pending transaction.
+    /// Turns the pending transaction into a new revision, discarding
+    /// any revisions that could have been redone.
+    pub fn commit(&mut self, selections: &[Selection], primary_selection_idx: usize) {
+        let Some(transaction) = self.pending.take() else {
+            return;
+        };
+        if transaction.edits.is_empty() {
+            return;
+        }
+        self.revisions.truncate(self.current);
+        if self.saved.is_some_and(|saved| saved > self.current) {
+            self.saved = None;
+        }
+        self.revisions.push(Revision {
+            transaction,
+            selections_after: selections.to_vec(),
+            primary_selection_idx_after: primary_selection_idx,
+        });
+        self.current = self.revisions.len();
+    }
+
+    /// Takes itself, a rope and a function to call with the char
+    /// range each edit replaces and the number of chars it replaces
+    /// it with, as the edits are applied.
+    /// Reverts the current revision and returns the selections
+    /// from before it, or `None` if there is nothing to undo.
+    pub fn undo(
+        &mut self,
+        rope: &mut Rope,
+        mut on_change: impl FnMut(Range<usize>, usize),
+    ) -> Option<(Vec<Selection>, usize)> {
+        if self.current == 0 {
+            return None;
+        }
+        self.current = self.current.saturating_sub(1);
+        let transaction = &self.revisions[self.current].transaction;
+        for edit in transaction.edits.iter().rev() {
+            let (range, len) = edit.invert().apply(rope);
+            on_change(range, len);
+        }
+        Some((
+            transaction.selections_before.clone(),
+            transaction.primary_selection_idx_before,
+        ))
+    }
+
+    /// Takes itself, a rope and a function to call with the char
+    /// range each edit replaces and the number of chars it replaces
+    /// it with, as the edits are applied.
+    /// Reapplies the next revision and returns the selections
+    /// from after it, or `None` if there is nothing to redo.
+    pub fn redo(
+        &mut self,
+        rope: &mut Rope,
+        mut on_change: impl FnMut(Range<usize>, usize),
+    ) -> Option<(Vec<Selection>, usize)> {
+        let revision = self.revisions.get(self.current)?;
+        for edit in &revision.transaction.edits {
+            let (range, len) = edit.apply(rope);
+            on_change(range, len);
+        }
+        self.current = self.current.saturating_add(1);
+        Some((
+            revision.selections_after.clone(),
+            revision.primary_selection_idx_after,
+        ))
+    }
+
+    /// Takes itself.
+    /// Returns the first char index changed by the revision that
+    /// `undo` would revert, if there is one.
+    #[must_use]
+    pub fn undo_start(&self) -> Option<usize> {
+        let idx = self.current.checked_sub(1)?;
+        Some(self.revisions[idx].transaction.start())
+    }
+
+    /// Takes itself.
+    /// Returns the first char index changed by the revision that
+    /// `redo` would reapply, if there is one.
+    #[must_use]
+    pub fn redo_start(&self) -> Option<usize> {
+        self.revisions
+            .get(self.current)
+            .map(|revision| revision.transaction.start())
+    }
+
+    /// Takes itself.
+    /// Returns a bool representing whether there is an
+    /// uncommitted transaction.
+    #[must_use]
+    pub fn has_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Takes itself.
+    /// Marks the current revision as the one written to disk.
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.current);
+    }
+
+    /// Takes itself.
+    /// Returns a bool representing whether the current revision
+    /// is the one written to disk.
+    #[must_use]
+    pub fn is_at_saved(&self) -> bool {
+        self.saved == Some(self.current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(history: &mut History, rope: &mut Rope, char_idx: usize, text: &str) {
+        history.record(
+            Edit::Insert {
+                char_idx,
+                text: text.to_string(),
+            },
+            &[Selection::default()],
+            0,
+        );
+        rope.insert(char_idx, text);
+    }
+
+    #[test]
+    fn undo_and_redo_a_transaction_as_a_unit() {
+        let mut history = History::default();
+        let mut rope = Rope::from_str("ac");
+        insert(&mut history, &mut rope, 1, "b");
+        insert(&mut history, &mut rope, 3, "d");
+        history.commit(&[Selection::default()], 0);
+        assert_eq!(rope, "abcd");
+        assert!(history.undo(&mut rope, |_, _| ()).is_some());
+        assert_eq!(rope, "ac");
+        assert!(history.undo(&mut rope, |_, _| ()).is_none());
+        assert!(history.redo(&mut rope, |_, _| ()).is_some());
+        assert_eq!(rope, "abcd");
+        assert!(history.redo(&mut rope, |_, _| ()).is_none());
+    }
+
+    #[test]
+    fn remove_is_undone_by_reinserting_the_text() {
+        let mut history = History::default();
+        let mut rope = Rope::from_str("hello");
+        history.record(
+            Edit::Remove {
+                char_idx: 1,
+                text: String::from("ell"),
+            },
+            &[],
+            0,
+        );
+        rope.remove(1..4);
+        history.commit(&[], 0);
+        history.undo(&mut rope, |_, _| ());
+        assert_eq!(rope, "hello");
+    }
+
+    #[test]
+    fn committing_after_undo_discards_the_redo_branch() {
+        let mut history = History::default();
+        let mut rope = Rope::new();
+        insert(&mut history, &mut rope, 0, "a");
+        history.commit(&[], 0);
+        history.undo(&mut rope, |_, _| ());
+        insert(&mut history, &mut rope, 0, "b");
+        history.commit(&[], 0);
+        assert!(history.redo(&mut rope, |_, _| ()).is_none());
+        history.undo(&mut rope, |_, _| ());
+        assert_eq!(rope, "");
+    }
+
+    #[test]
+    fn empty_commit_adds_no_revision() {
+        let mut history = History::default();
+        history.commit(&[], 0);
+        assert!(history.undo_start().is_none());
+        assert!(history.is_at_saved());
+    }
+
+    #[test]
+    fn tracks_whether_the_saved_revision_is_current() {
+        let mut history = History::default();
+        let mut rope = Rope::new();
+        insert(&mut history, &mut rope, 0, "a");
+        history.commit(&[], 0);
+        assert!(!history.is_at_saved());
+        history.mark_saved();
+        insert(&mut history, &mut rope, 1, "b");
+        history.commit(&[], 0);
+        assert!(!history.is_at_saved());
+        history.undo(&mut rope, |_, _| ());
+        assert!(history.is_at_saved());
+        history.undo(&mut rope, |_, _| ());
+        insert(&mut history, &mut rope, 0, "c");
+        history.commit(&[], 0);
+        history.undo(&mut rope, |_, _| ());
+        history.redo(&mut rope, |_, _| ());
+        assert!(!history.is_at_saved());
+    }
+
+    #[test]
+    fn restores_selections_from_either_side_of_a_revision() {
+        let before = Selection::default();
+        let mut after = Selection::default();
+        after.cursor.x = 1;
+        let mut history = History::default();
+        let mut rope = Rope::new();
+        history.record(
+            Edit::Insert {
+                char_idx: 0,
+                text: String::from("a"),
+            },
+            &[before],
+            0,
+        );
+        rope.insert(0, "a");
+        history.commit(&[after], 0);
+        let (selections, _) = history.undo(&mut rope, |_, _| ()).unwrap();
+        assert!(selections[0].cursor == before.cursor);
+        let (selections, _) = history.redo(&mut rope, |_, _| ()).unwrap();
+        assert!(selections[0].cursor == after.cursor);
+    }
+
+    #[test]
+    fn start_is_the_first_changed_char() {
+        let mut history = History::default();
+        let mut rope = Rope::from_str("abc");
+        insert(&mut history, &mut rope, 3, "d");
+        insert(&mut history, &mut rope, 1, "x");
+        history.commit(&[], 0);
+        assert_eq!(history.undo_start(), Some(1));
+        history.undo(&mut rope, |_, _| ());
+        assert_eq!(history.redo_start(), Some(1));
+        assert_eq!(history.undo_start(), None);
+    }
+}
@@ -0,0 +1,613 @@
+#![warn(clippy::all, clippy::pedantic)]
+use crate::{movement::Motion, Mode};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::{collections::HashMap, fmt};
+
+/// The bindings of Normal mode, which Visual mode shares.
+const NORMAL_BINDINGS: [(&str, &str); 60] = [
+    ("<Esc>", "normal_mode"),
+    (":", "open_commandline"),
+    ("/", "search_forward"),
+    ("?", "search_backward"),
+    ("s", "select_regex"),
+    ("n", "search_next"),
+    ("N", "search_prev"),
+    ("i", "insert_mode"),
+    ("u", "undo"),
+    ("U", "redo"),
+    ("v", "visual_mode"),
+    ("V", "visual_line_mode"),
+    ("\"", "select_register"),
+    ("r", "replace"),
+    ("d", "delete"),
+    ("c", "change"),
+    ("y", "yank"),
+    ("p", "paste_after"),
+    ("P", "paste_before"),
+    ("<Left>", "move_left"),
+    ("<Right>", "move_right"),
+    ("<Up>", "move_up"),
+    ("<Down>", "move_down"),
+    ("w", "next_word_start"),
+    ("b", "prev_word_start"),
+    ("e", "next_word_end"),
+    ("W", "next_long_word_start"),
+    ("B", "prev_long_word_start"),
+    ("E", "next_long_word_end"),
+    ("0", "line_start"),
+    ("^", "line_first_non_whitespace"),
+    ("$", "line_end"),
+    ("gg", "file_start"),
+    ("G", "file_end"),
+    ("{", "prev_paragraph"),
+    ("}", "next_paragraph"),
+    ("C", "copy_selection_below"),
+    ("<A-C>", "copy_selection_above"),
+    ("<A-c>", "copy_selection_above"),
+    ("<A-s>", "split_selection_on_lines"),
+    (",", "keep_primary_selection"),
+    ("(", "prev_selection"),
+    (")", "next_selection"),
+    ("<C-w>s", "window_split"),
+    ("<C-w>S", "window_split"),
+    ("<C-w>v", "window_vsplit"),
+    ("<C-w>w", "window_next"),
+    ("<C-w>W", "window_prev"),
+    ("<C-w>h", "window_left"),
+    ("<C-w>j", "window_down"),
+    ("<C-w>k", "window_up"),
+    ("<C-w>l", "window_right"),
+    ("<C-w>c", "window_close"),
+    ("<C-w>q", "window_close"),
+    ("<C-w>o", "window_only"),
+    ("<C-w>+", "window_taller"),
+    ("<C-w>-", "window_shorter"),
+    ("<C-w>>", "window_wider"),
+    ("<C-w><lt>", "window_narrower"),
+    ("<C-w>=", "window_equalize"),
+];
+
+/// The bindings of Insert mode. Keys without a binding insert
+/// the char they type.
+const INSERT_BINDINGS: [(&str, &str); 9] = [
+    ("<Esc>", "normal_mode"),
+    ("<CR>", "insert_newline"),
+    ("<Tab>", "insert_indent"),
+    ("<BS>", "delete_backward"),
+    ("<Del>", "delete_forward"),
+    ("<Left>", "move_left"),
+    ("<Right>", "move_right"),
+    ("<Up>", "move_up"),
+    ("<Down>", "move_down"),
+];
+
+/// The bindings of the commandline. Keys without a binding insert
+/// the char they type.
+const COMMAND_BINDINGS: [(&str, &str); 6] = [
+    ("<Esc>", "normal_mode"),
+    ("<CR>", "submit_commandline"),
+    ("<BS>", "delete_backward"),
+    ("<Del>", "delete_forward"),
+    ("<Left>", "move_left"),
+    ("<Right>", "move_right"),
+];
+
+/// A key pressed along with the modifiers held.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl From<KeyEvent> for Key {
+    /// Takes a `KeyEvent`.
+    /// Builds the `Key` it presses. Shift is dropped from chars,
+    /// since it is already part of the char.
+    fn from(event: KeyEvent) -> Self {
+        let mut modifiers =
+            event.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        if matches!(event.code, KeyCode::Char(_) | KeyCode::BackTab) {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        Self {
+            code: control_char(event.code, modifiers),
+            modifiers,
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.code {
+            KeyCode::Char(' ') => String::from("Space"),
+            KeyCode::Char('<') => String::from("lt"),
+            KeyCode::Char(c) if self.modifiers.is_empty() => return write!(f, "{c}"),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::Esc => String::from("Esc"),
+            KeyCode::Enter => String::from("CR"),
+            KeyCode::Tab => String::from("Tab"),
+            KeyCode::BackTab => String::from("S-Tab"),
+            KeyCode::Backspace => String::from("BS"),
+            KeyCode::Delete => String::from("Del"),
+            KeyCode::Insert => String::from("Insert"),
+            KeyCode::Left => String::from("Left"),
+            KeyCode::Right => String::from("Right"),
+            KeyCode::Up => String::from("Up"),
+            KeyCode::Down => String::from("Down"),
+            KeyCode::Home => String::from("Home"),
+            KeyCode::End => String::from("End"),
+            KeyCode::PageUp => String::from("PageUp"),
+            KeyCode::PageDown => String::from("PageDown"),
+            KeyCode::F(n) => format!("F{n}"),
+            _ => String::from("?"),
+        };
+        write!(f, "<")?;
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "C-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "A-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "S-")?;
+        }
+        write!(f, "{name}>")
+    }
+}
+
+/// Takes a key sequence written as in `gg` or `<C-w>s`.
+/// Returns the keys it presses.
+///
+/// # Errors
+///
+/// Returns an error message if the sequence is empty or names a
+/// key that does not exist.
+pub fn parse_keys(sequence: &str) -> Result<Vec<Key>, String> {
+    let mut keys = Vec::new();
+    let mut rest = sequence;
+    while let Some(c) = rest.chars().next() {
+        let special = rest
+            .strip_prefix('<')
+            .and_then(|inner| inner.find('>').map(|end| &inner[..end]))
+            .filter(|inner| !inner.is_empty());
+        if let Some(inner) = special {
+            keys.push(parse_special_key(inner)?);
+            rest = &rest[inner.len() + 2..];
+        } else {
+            keys.push(Key {
+                code: KeyCode::Char(c),
+                modifiers: KeyModifiers::NONE,
+            });
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    if keys.is_empty() {
+        Err(String::from("empty key sequence"))
+    } else {
+        Ok(keys)
+    }
+}
+
+/// Takes the inside of a `<...>` key, such as `C-w` or `Esc`.
+/// Returns the key it names.
+fn parse_special_key(inner: &str) -> Result<Key, String> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut name = inner;
+    loop {
+        let mut chars = name.chars();
+        let (Some(prefix), Some('-'), Some(_)) = (chars.next(), chars.next(), chars.next()) else {
+            break;
+        };
+        modifiers |= match prefix.to_ascii_uppercase() {
+            'C' => KeyModifiers::CONTROL,
+            'A' | 'M' => KeyModifiers::ALT,
+            'S' => KeyModifiers::SHIFT,
+            _ => break,
+        };
+        name = &name[2..];
+    }
+    let mut chars = name.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) if modifiers.contains(KeyModifiers::SHIFT) => {
+            modifiers.remove(KeyModifiers::SHIFT);
+            KeyCode::Char(c.to_ascii_uppercase())
+        }
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match name.to_ascii_lowercase().as_str() {
+            "space" => KeyCode::Char(' '),
+            "lt" => KeyCode::Char('<'),
+            "esc" => KeyCode::Esc,
+            "cr" | "enter" | "return" => KeyCode::Enter,
+            "tab" if modifiers.contains(KeyModifiers::SHIFT) => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::BackTab
+            }
+            "tab" => KeyCode::Tab,
+            "bs" | "backspace" => KeyCode::Backspace,
+            "del" | "delete" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            lower => lower
+                .strip_prefix('f')
+                .and_then(|n| n.parse().ok())
+                .filter(|n| (1..=12).contains(n))
+                .map(KeyCode::F)
+                .ok_or_else(|| format!("unknown key: <{inner}>"))?,
+        },
+    };
+    Ok(Key {
+        code: control_char(code, modifiers),
+        modifiers,
+    })
+}
+
+/// Takes a key code and the modifiers held with it.
+/// Returns the code with chars typed with Control in lowercase,
+/// since terminals cannot tell Ctrl-W from Ctrl-w.
+fn control_char(code: KeyCode, modifiers: KeyModifiers) -> KeyCode {
+    match code {
+        KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => {
+            KeyCode::Char(c.to_ascii_lowercase())
+        }
+        _ => code,
+    }
+}
+
+/// A named action that keys can be bound to.
+#[derive(Clone)]
+pub enum Command {
+    NormalMode,
+    InsertMode,
+    VisualMode,
+    VisualLineMode,
+    OpenCommandline,
+    SearchForward,
+    SearchBackward,
+    SelectRegex,
+    SearchNext,
+    SearchPrev,
+    Undo,
+    Redo,
+    /// Waits for the name of a register to use for the next
+    /// yank, delete or paste.
+    SelectRegister,
+    /// Waits for a char to replace the selected text with.
+    Replace,
+    Delete,
+    Change,
+    Yank,
+    PasteAfter,
+    PasteBefore,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Move(Motion),
+    CopySelectionBelow,
+    CopySelectionAbove,
+    SplitSelectionOnLines,
+    KeepPrimarySelection,
+    PrevSelection,
+    NextSelection,
+    WindowSplit,
+    WindowVsplit,
+    WindowNext,
+    WindowPrev,
+    WindowLeft,
+    WindowDown,
+    WindowUp,
+    WindowRight,
+    WindowClose,
+    WindowOnly,
+    WindowTaller,
+    WindowShorter,
+    WindowWider,
+    WindowNarrower,
+    WindowEqualize,
+    InsertNewline,
+    InsertIndent,
+    DeleteBackward,
+    DeleteForward,
+    SubmitCommandline,
+    /// Runs a commandline command, written `:command`.
+    Ex(String),
+    /// Does nothing, for unbinding keys.
+    NoOp,
+}
+
+impl Command {
+    /// Takes the name of a command, or a commandline command
+    /// starting with `:`.
+    /// Returns the `Command` it names.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if there is no command by that name.
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        if let Some(command) = name.strip_prefix(':') {
+            return Ok(Command::Ex(command.to_string()));
+        }
+        Ok(match name {
+            "normal_mode" => Command::NormalMode,
+            "insert_mode" => Command::InsertMode,
+            "visual_mode" => Command::VisualMode,
+            "visual_line_mode" => Command::VisualLineMode,
+            "open_commandline" => Command::OpenCommandline,
+            "search_forward" => Command::SearchForward,
+            "search_backward" => Command::SearchBackward,
+            "select_regex" => Command::SelectRegex,
+            "search_next" => Command::SearchNext,
+            "search_prev" => Command::SearchPrev,
+            "undo" => Command::Undo,
+            "redo" => Command::Redo,
+            "select_register" => Command::SelectRegister,
+            "replace" => Command::Replace,
+            "delete" => Command::Delete,
+            "change" => Command::Change,
+            "yank" => Command::Yank,
+            "paste_after" => Command::PasteAfter,
+            "paste_before" => Command::PasteBefore,
+            "move_left" => Command::MoveLeft,
+            "move_right" => Command::MoveRight,
+            "move_up" => Command::MoveUp,
+            "move_down" => Command::MoveDown,
+            "next_word_start" => Command::Move(Motion::NextWordStart),
+            "prev_word_start" => Command::Move(Motion::PrevWordStart),
+            "next_word_end" => Command::Move(Motion::NextWordEnd),
+            "next_long_word_start" => Command::Move(Motion::NextLongWordStart),
+            "prev_long_word_start" => Command::Move(Motion::PrevLongWordStart),
+            "next_long_word_end" => Command::Move(Motion::NextLongWordEnd),
+            "line_start" => Command::Move(Motion::LineStart),
+            "line_first_non_whitespace" => Command::Move(Motion::LineFirstNonWhitespace),
+            "line_end" => Command::Move(Motion::LineEnd),
+            "file_start" => Command::Move(Motion::FileStart),
+            "file_end" => Command::Move(Motion::FileEnd),
+            "prev_paragraph" => Command::Move(Motion::PrevParagraph),
+            "next_paragraph" => Command::Move(Motion::NextParagraph),
+            "copy_selection_below" => Command::CopySelectionBelow,
+            "copy_selection_above" => Command::CopySelectionAbove,
+            "split_selection_on_lines" => Command::SplitSelectionOnLines,
+            "keep_primary_selection" => Command::KeepPrimarySelection,
+            "prev_selection" => Command::PrevSelection,
+            "next_selection" => Command::NextSelection,
+            "window_split" => Command::WindowSplit,
+            "window_vsplit" => Command::WindowVsplit,
+            "window_next" => Command::WindowNext,
+            "window_prev" => Command::WindowPrev,
+            "window_left" => Command::WindowLeft,
+            "window_down" => Command::WindowDown,
+            "window_up" => Command::WindowUp,
+            "window_right" => Command::WindowRight,
+            "window_close" => Command::WindowClose,
+            "window_only" => Command::WindowOnly,
+            "window_taller" => Command::WindowTaller,
+            "window_shorter" => Command::WindowShorter,
+            "window_wider" => Command::WindowWider,
+            "window_narrower" => Command::WindowNarrower,
+            "window_equalize" => Command::WindowEqualize,
+            "insert_newline" => Command::InsertNewline,
+            "insert_indent" => Command::InsertIndent,
+            "delete_backward" => Command::DeleteBackward,
+            "delete_forward" => Command::DeleteForward,
+            "submit_commandline" => Command::SubmitCommandline,
+            "no_op" => Command::NoOp,
+            _ => return Err(format!("unknown command: {name}")),
+        })
+    }
+}
+
+/// What a sequence of keys does in a mode.
+pub enum Lookup<'a> {
+    /// The keys are bound to a command.
+    Command(&'a Command),
+    /// The keys begin a longer binding, so more keys are needed.
+    /// Holds the command the keys are bound to on their own, if
+    /// any, which runs if no more keys are typed in time.
+    Pending(Option<&'a Command>),
+    /// The keys are not bound.
+    Unbound,
+}
+
+/// The key bindings of every mode.
+pub struct Keymap {
+    bindings: HashMap<Mode, HashMap<Vec<Key>, Command>>,
+}
+
+impl Default for Keymap {
+    /// Takes nothing.
+    /// Builds the `Keymap` with the default bindings.
+    fn default() -> Self {
+        let mut keymap = Self {
+            bindings: HashMap::new(),
+        };
+        let modes = [
+            (Mode::Normal, &NORMAL_BINDINGS[..]),
+            (Mode::Visual, &NORMAL_BINDINGS[..]),
+            (Mode::Insert, &INSERT_BINDINGS[..]),
+            (Mode::Command, &COMMAND_BINDINGS[..]),
+        ];
+        for (mode, bindings) in modes {
+            for (keys, command) in bindings {
+                keymap
+                    .bind(mode, keys, command)
+                    .expect("default bindings are valid");
+            }
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    /// Takes itself, a mode, a key sequence and the name of a command.
+    /// Binds the keys to the command in the mode.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if the keys or the command cannot
+    /// be parsed.
+    pub fn bind(&mut self, mode: Mode, keys: &str, command: &str) -> Result<(), String> {
+        let keys = parse_keys(keys)?;
+        let command = Command::from_name(command)?;
+        self.bindings
+            .entry(keymap_mode(mode))
+            .or_default()
+            .insert(keys, command);
+        Ok(())
+    }
+
+    /// Takes itself, a mode and the keys typed so far.
+    /// Returns what the keys do in the mode. Keys that are bound and
+    /// also begin a longer binding are pending along with their
+    /// command.
+    #[must_use]
+    pub fn lookup(&self, mode: Mode, keys: &[Key]) -> Lookup<'_> {
+        let Some(bindings) = self.bindings.get(&keymap_mode(mode)) else {
+            return Lookup::Unbound;
+        };
+        if bindings
+            .keys()
+            .any(|sequence| sequence.len() > keys.len() && sequence.starts_with(keys))
+        {
+            Lookup::Pending(bindings.get(keys))
+        } else if let Some(command) = bindings.get(keys) {
+            Lookup::Command(command)
+        } else {
+            Lookup::Unbound
+        }
+    }
+}
+
+/// Takes the name of a mode as written in the config.
+/// Returns the `Mode` it names.
+///
+/// # Errors
+///
+/// Returns an error message if there is no mode by that name.
+pub fn parse_mode(name: &str) -> Result<Mode, String> {
+    match name {
+        "normal" => Ok(Mode::Normal),
+        "insert" => Ok(Mode::Insert),
+        "visual" => Ok(Mode::Visual),
+        "command" => Ok(Mode::Command),
+        _ => Err(format!("unknown mode: {name}")),
+    }
+}
+
+/// Takes a mode.
+/// Returns the mode whose bindings it uses.
+fn keymap_mode(mode: Mode) -> Mode {
+    match mode {
+        Mode::VisualLine => Mode::Visual,
+        _ => mode,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> Key {
+        Key { code, modifiers }
+    }
+
+    fn char_key(c: char) -> Key {
+        key(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn parse_key_sequences() {
+        assert!(parse_keys("gg") == Ok(vec![char_key('g'), char_key('g')]));
+        assert!(
+            parse_keys("<C-W>s")
+                == Ok(vec![
+                    key(KeyCode::Char('w'), KeyModifiers::CONTROL),
+                    char_key('s')
+                ])
+        );
+        assert!(
+            parse_keys("<S-a><lt><S-Tab>")
+                == Ok(vec![
+                    char_key('A'),
+                    char_key('<'),
+                    key(KeyCode::BackTab, KeyModifiers::NONE)
+                ])
+        );
+        assert!(parse_keys("<>") == Ok(vec![char_key('<'), char_key('>')]));
+        assert!(parse_keys("").is_err());
+        assert!(parse_keys("<F13>").is_err());
+        assert!(parse_keys("<C-nope>").is_err());
+    }
+
+    #[test]
+    fn display_parses_back_to_the_same_key() {
+        for sequence in ["a", "<Space>", "<lt>", "<C-w>", "<A-S-Left>", "<F5>"] {
+            let keys = parse_keys(sequence).unwrap();
+            assert_eq!(keys.len(), 1);
+            assert_eq!(keys[0].to_string(), sequence);
+        }
+    }
+
+    #[test]
+    fn key_events_drop_shift_from_chars() {
+        let event = KeyEvent::new(KeyCode::Char('A'), KeyModifiers::SHIFT);
+        assert!(Key::from(event) == char_key('A'));
+        let event = KeyEvent::new(
+            KeyCode::Char('W'),
+            KeyModifiers::CONTROL | KeyModifiers::SHIFT,
+        );
+        assert!(Key::from(event) == key(KeyCode::Char('w'), KeyModifiers::CONTROL));
+    }
+
+    #[test]
+    fn lookup_waits_for_longer_bindings() {
+        let mut keymap = Keymap::default();
+        let g = [char_key('g')];
+        assert!(matches!(
+            keymap.lookup(Mode::Normal, &g),
+            Lookup::Pending(None)
+        ));
+        assert!(matches!(
+            keymap.lookup(Mode::Normal, &[char_key('g'), char_key('g')]),
+            Lookup::Command(Command::Move(Motion::FileStart))
+        ));
+        assert!(matches!(
+            keymap.lookup(Mode::Normal, &[char_key('g'), char_key('x')]),
+            Lookup::Unbound
+        ));
+        keymap.bind(Mode::Normal, "g", "file_end").unwrap();
+        assert!(matches!(
+            keymap.lookup(Mode::Normal, &g),
+            Lookup::Pending(Some(Command::Move(Motion::FileEnd)))
+        ));
+    }
+
+    #[test]
+    fn visual_line_mode_uses_visual_bindings() {
+        let mut keymap = Keymap::default();
+        keymap.bind(Mode::Visual, "x", "yank").unwrap();
+        let x = [char_key('x')];
+        assert!(matches!(
+            keymap.lookup(Mode::VisualLine, &x),
+            Lookup::Command(Command::Yank)
+        ));
+        assert!(matches!(keymap.lookup(Mode::Normal, &x), Lookup::Unbound));
+    }
+
+    #[test]
+    fn bind_rejects_unknown_commands() {
+        let mut keymap = Keymap::default();
+        assert!(keymap.bind(Mode::Normal, "x", "explode").is_err());
+        assert!(keymap.bind(Mode::Normal, "x", ":w").is_ok());
+        assert!(matches!(
+            keymap.lookup(Mode::Normal, &[char_key('x')]),
+            Lookup::Command(Command::Ex(command)) if command == "w"
+        ));
+    }
+}
@@ -1,15 +1,62 @@
 #![warn(clippy::all, clippy::pedantic)]
+mod args;
+mod command;
+mod config;
+mod disk;
 mod editor;
+mod encoding;
 mod filebuffer;
+mod frame;
+mod grapheme;
+mod gutter;
+mod highlight;
+mod history;
+mod keymap;
+mod movement;
+mod register;
+mod save;
+mod search;
 mod terminal;
+#[cfg(test)]
+mod testing;
+mod theme;
+mod window;
 
 use editor::Editor;
 pub use editor::Mode;
 pub use filebuffer::FileBuffer;
 pub use filebuffer::Position;
+pub use filebuffer::Selection;
+pub use filebuffer::View;
+pub use frame::Frame;
 pub use terminal::Size;
 pub use terminal::Terminal;
 
+use args::Action;
+use std::{env, process};
+
 fn main() {
-    Editor::default().run();
+    let files = match args::parse(env::args_os().skip(1)) {
+        Ok(Action::Edit(files)) => files,
+        Ok(Action::Help) => {
+            println!("{}", args::USAGE);
+            return;
+        }
+        Ok(Action::Version) => {
+            println!("rflx {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(message) => {
+            eprintln!("rflx: {message}\n\n{}", args::USAGE);
+            process::exit(2);
+        }
+    };
+    let file_buffers = match args::open_files(&files) {
+        Ok(file_buffers) => file_buffers,
+        Err(message) => {
+            eprintln!("rflx: {message}");
+            process::exit(1);
+        }
+    };
+    Editor::new(file_buffers).run();
 }
@@ -0,0 +1,273 @@
+#![warn(clippy::all, clippy::pedantic)]
+use ropey::Rope;
+use std::cmp;
+
+/// A Normal mode motion that moves or extends a selection.
+#[derive(Clone, Copy)]
+pub enum Motion {
+    NextWordStart,
+    PrevWordStart,
+    NextWordEnd,
+    NextLongWordStart,
+    PrevLongWordStart,
+    NextLongWordEnd,
+    LineStart,
+    LineFirstNonWhitespace,
+    LineEnd,
+    FileStart,
+    FileEnd,
+    PrevParagraph,
+    NextParagraph,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum CharCategory {
+    Whitespace,
+    Eol,
+    Word,
+    Punctuation,
+}
+
+/// Takes a char and whether punctuation counts as part of a word.
+/// Returns the category the char belongs to.
+fn categorize(c: char, long: bool) -> CharCategory {
+    if c == '\n' || c == '\r' {
+        CharCategory::Eol
+    } else if c.is_whitespace() {
+        CharCategory::Whitespace
+    } else if long || c.is_alphanumeric() || c == '_' {
+        CharCategory::Word
+    } else {
+        CharCategory::Punctuation
+    }
+}
+
+/// Takes a motion, a rope and the char index of a cursor.
+/// Returns the new `(anchor, cursor)` char indices of the selection.
+/// Motions that only move the cursor return the same index twice.
+#[must_use]
+pub fn apply(motion: Motion, rope: &Rope, idx: usize) -> (usize, usize) {
+    match motion {
+        Motion::NextWordStart => next_word_start(rope, idx, false),
+        Motion::PrevWordStart => prev_word_start(rope, idx, false),
+        Motion::NextWordEnd => next_word_end(rope, idx, false),
+        Motion::NextLongWordStart => next_word_start(rope, idx, true),
+        Motion::PrevLongWordStart => prev_word_start(rope, idx, true),
+        Motion::NextLongWordEnd => next_word_end(rope, idx, true),
+        Motion::LineStart => {
+            let start = rope.line_to_char(rope.char_to_line(idx));
+            (start, start)
+        }
+        Motion::LineFirstNonWhitespace => {
+            let line_idx = rope.char_to_line(idx);
+            let start = rope.line_to_char(line_idx);
+            let indent = rope
+                .line(line_idx)
+                .chars()
+                .take_while(|c| categorize(*c, false) == CharCategory::Whitespace)
+                .count();
+            (start + indent, start + indent)
+        }
+        Motion::LineEnd => {
+            let end = line_end(rope, rope.char_to_line(idx));
+            (end, end)
+        }
+        Motion::FileStart => (0, 0),
+        Motion::FileEnd => {
+            let start = rope.line_to_char(rope.len_lines().saturating_sub(1));
+            (start, start)
+        }
+        Motion::PrevParagraph => {
+            let mut line_idx = rope.char_to_line(idx);
+            while line_idx > 0 && is_blank(rope, line_idx) {
+                line_idx -= 1;
+            }
+            while line_idx > 0 && !is_blank(rope, line_idx) {
+                line_idx -= 1;
+            }
+            let start = rope.line_to_char(line_idx);
+            (start, start)
+        }
+        Motion::NextParagraph => {
+            let last_line = rope.len_lines().saturating_sub(1);
+            let mut line_idx = rope.char_to_line(idx);
+            while line_idx < last_line && is_blank(rope, line_idx) {
+                line_idx += 1;
+            }
+            while line_idx < last_line && !is_blank(rope, line_idx) {
+                line_idx += 1;
+            }
+            let start = rope.line_to_char(line_idx);
+            (start, start)
+        }
+    }
+}
+
+/// Takes a rope and the index of a line.
+/// Returns a bool representing whether the line contains
+/// nothing but whitespace.
+fn is_blank(rope: &Rope, line_idx: usize) -> bool {
+    rope.line(line_idx).chars().all(char::is_whitespace)
+}
+
+/// Takes a rope and the index of a line.
+/// Returns the char index of the last char of the line
+/// that is not a line break.
+#[must_use]
+pub fn line_end(rope: &Rope, line_idx: usize) -> usize {
+    let start = rope.line_to_char(line_idx);
+    let len = rope
+        .line(line_idx)
+        .chars()
+        .take_while(|c| categorize(*c, false) != CharCategory::Eol)
+        .count();
+    start + len.saturating_sub(1)
+}
+
+/// Takes a rope, a char index and whether to use long words.
+/// Returns the selection from the cursor to the char before the
+/// start of the next word.
+fn next_word_start(rope: &Rope, idx: usize, long: bool) -> (usize, usize) {
+    let len = rope.len_chars();
+    let category = |i: usize| categorize(rope.char(i), long);
+    if idx.saturating_add(1) >= len {
+        return (idx, idx);
+    }
+    let mut start = idx;
+    if category(start) != category(start + 1) {
+        start += 1;
+    }
+    while start < len && category(start) == CharCategory::Eol {
+        start += 1;
+    }
+    if start >= len {
+        return (idx, idx);
+    }
+    let first = category(start);
+    let mut end = start;
+    if first != CharCategory::Whitespace {
+        while end + 1 < len && category(end + 1) == first {
+            end += 1;
+        }
+    }
+    while end + 1 < len && category(end + 1) == CharCategory::Whitespace {
+        end += 1;
+    }
+    (start, end)
+}
+
+/// Takes a rope, a char index and whether to use long words.
+/// Returns the selection from the cursor to the end of the
+/// current or next word.
+fn next_word_end(rope: &Rope, idx: usize, long: bool) -> (usize, usize) {
+    let len = rope.len_chars();
+    let category = |i: usize| categorize(rope.char(i), long);
+    if idx.saturating_add(1) >= len {
+        return (idx, idx);
+    }
+    let mut start = idx;
+    if category(start) != category(start + 1) {
+        start += 1;
+    }
+    while start < len && category(start) == CharCategory::Eol {
+        start += 1;
+    }
+    if start >= len {
+        return (idx, idx);
+    }
+    let mut end = start;
+    while end + 1 < len && matches!(category(end), CharCategory::Whitespace | CharCategory::Eol) {
+        end += 1;
+    }
+    let word = category(end);
+    while end + 1 < len && category(end + 1) == word {
+        end += 1;
+    }
+    (start, end)
+}
+
+/// Takes a rope, a char index and whether to use long words.
+/// Returns the selection from the cursor back to the start
+/// of the current or previous word.
+fn prev_word_start(rope: &Rope, idx: usize, long: bool) -> (usize, usize) {
+    let len = rope.len_chars();
+    if len == 0 || idx == 0 {
+        return (0, 0);
+    }
+    let category = |i: usize| categorize(rope.char(i), long);
+    let mut start = cmp::min(idx, len - 1);
+    if category(start) != category(start - 1) {
+        start -= 1;
+    }
+    while start > 0 && category(start) == CharCategory::Eol {
+        start -= 1;
+    }
+    let mut end = start;
+    while end > 0 && matches!(category(end), CharCategory::Whitespace | CharCategory::Eol) {
+        end -= 1;
+    }
+    let word = category(end);
+    while end > 0 && category(end - 1) == word {
+        end -= 1;
+    }
+    (start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn motion(motion: Motion, text: &str, idx: usize) -> (usize, usize) {
+        apply(motion, &Rope::from_str(text), idx)
+    }
+
+    #[test]
+    fn word_motions_select_the_word_and_its_whitespace() {
+        assert_eq!(motion(Motion::NextWordStart, "one two three", 0), (0, 3));
+        assert_eq!(motion(Motion::NextWordStart, "one two three", 3), (4, 7));
+        assert_eq!(motion(Motion::NextWordEnd, "one two three", 0), (0, 2));
+        assert_eq!(motion(Motion::NextWordEnd, "one two three", 2), (3, 6));
+        assert_eq!(motion(Motion::PrevWordStart, "one two three", 6), (6, 4));
+        assert_eq!(motion(Motion::PrevWordStart, "one two three", 4), (3, 0));
+    }
+
+    #[test]
+    fn long_words_include_punctuation() {
+        assert_eq!(motion(Motion::NextWordStart, "a.b c", 0), (1, 1));
+        assert_eq!(motion(Motion::NextLongWordStart, "a.b c", 0), (0, 3));
+        assert_eq!(motion(Motion::NextLongWordEnd, "a.b c", 0), (0, 2));
+        assert_eq!(motion(Motion::PrevLongWordStart, "a b.c", 4), (4, 2));
+    }
+
+    #[test]
+    fn word_motions_skip_line_breaks() {
+        assert_eq!(motion(Motion::NextWordStart, "ab\ncd", 1), (3, 4));
+        assert_eq!(motion(Motion::PrevWordStart, "ab\ncd", 3), (1, 0));
+    }
+
+    #[test]
+    fn word_motions_stay_put_at_the_ends_of_the_file() {
+        assert_eq!(motion(Motion::NextWordStart, "", 0), (0, 0));
+        assert_eq!(motion(Motion::NextWordEnd, "ab", 1), (1, 1));
+        assert_eq!(motion(Motion::PrevWordStart, "ab", 0), (0, 0));
+    }
+
+    #[test]
+    fn line_motions() {
+        assert_eq!(motion(Motion::LineStart, "ab\ncd", 4), (3, 3));
+        assert_eq!(motion(Motion::LineFirstNonWhitespace, "  x", 0), (2, 2));
+        assert_eq!(motion(Motion::LineEnd, "ab\ncd\n", 0), (1, 1));
+        assert_eq!(motion(Motion::LineEnd, "ab\r\ncd", 0), (1, 1));
+        assert_eq!(motion(Motion::FileStart, "a\nb", 2), (0, 0));
+        assert_eq!(motion(Motion::FileEnd, "a\nb", 0), (2, 2));
+    }
+
+    #[test]
+    fn paragraph_motions_stop_at_blank_lines() {
+        let text = "a\nb\n\nc\n";
+        assert_eq!(motion(Motion::NextParagraph, text, 0), (4, 4));
+        assert_eq!(motion(Motion::NextParagraph, text, 4), (7, 7));
+        assert_eq!(motion(Motion::PrevParagraph, text, 5), (4, 4));
+        assert_eq!(motion(Motion::PrevParagraph, text, 4), (0, 0));
+    }
+}
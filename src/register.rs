@@ -0,0 +1,108 @@
+#![warn(clippy::all, clippy::pedantic)]
+use std::collections::HashMap;
+
+pub const UNNAMED_REGISTER: char = '"';
+pub const BLACK_HOLE_REGISTER: char = '_';
+
+/// Stores the values yanked or deleted by operators.
+/// Each register holds one value per selection.
+#[derive(Default)]
+pub struct Registers {
+    values: HashMap<char, Vec<String>>,
+}
+
+impl Registers {
+    /// Takes a char.
+    /// Returns a bool representing whether the char names a register.
+    #[must_use]
+    pub fn is_valid_name(name: char) -> bool {
+        name == UNNAMED_REGISTER || name == BLACK_HOLE_REGISTER || name.is_ascii_lowercase()
+    }
+
+    /// Takes itself and the name of a register.
+    /// Returns the values stored in the register, if there are any.
+    #[must_use]
+    pub fn read(&self, name: char) -> Option<&[String]> {
+        if name == BLACK_HOLE_REGISTER {
+            return None;
+        }
+        self.values
+            .get(&name)
+            .map(Vec::as_slice)
+            .filter(|values| !values.is_empty())
+    }
+
+    /// Takes itself, the name of a register and the values to store.
+    /// Stores the values in the register. Named registers also
+    /// update the unnamed register, and the black-hole register
+    /// discards everything written to it.
+    pub fn write(&mut self, name: char, values: Vec<String>) {
+        if name == BLACK_HOLE_REGISTER || !Self::is_valid_name(name) {
+            return;
+        }
+        if name != UNNAMED_REGISTER {
+            self.values.insert(UNNAMED_REGISTER, values.clone());
+        }
+        self.values.insert(name, values);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(values: &[&str]) -> Vec<String> {
+        values.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn unnamed_register_holds_the_last_write() {
+        let mut registers = Registers::default();
+        assert!(registers.read(UNNAMED_REGISTER).is_none());
+        registers.write(UNNAMED_REGISTER, values(&["a", "b"]));
+        assert_eq!(
+            registers.read(UNNAMED_REGISTER),
+            Some(&values(&["a", "b"])[..])
+        );
+        assert!(registers.read('a').is_none());
+    }
+
+    #[test]
+    fn named_writes_also_update_the_unnamed_register() {
+        let mut registers = Registers::default();
+        registers.write('a', values(&["x"]));
+        registers.write(UNNAMED_REGISTER, values(&["y"]));
+        assert_eq!(registers.read('a'), Some(&values(&["x"])[..]));
+        assert_eq!(registers.read(UNNAMED_REGISTER), Some(&values(&["y"])[..]));
+        registers.write('b', values(&["z"]));
+        assert_eq!(registers.read(UNNAMED_REGISTER), Some(&values(&["z"])[..]));
+        assert_eq!(registers.read('a'), Some(&values(&["x"])[..]));
+    }
+
+    #[test]
+    fn black_hole_register_discards_writes() {
+        let mut registers = Registers::default();
+        registers.write(UNNAMED_REGISTER, values(&["kept"]));
+        registers.write(BLACK_HOLE_REGISTER, values(&["lost"]));
+        assert!(registers.read(BLACK_HOLE_REGISTER).is_none());
+        assert_eq!(
+            registers.read(UNNAMED_REGISTER),
+            Some(&values(&["kept"])[..])
+        );
+    }
+
+    #[test]
+    fn only_some_chars_name_registers() {
+        assert!(Registers::is_valid_name('"'));
+        assert!(Registers::is_valid_name('_'));
+        assert!(Registers::is_valid_name('q'));
+        assert!(!Registers::is_valid_name('Q'));
+        assert!(!Registers::is_valid_name('1'));
+        let mut registers = Registers::default();
+        registers.write('Q', values(&["x"]));
+        assert!(registers.read('Q').is_none());
+        assert!(registers.read(UNNAMED_REGISTER).is_none());
+        registers.write('a', Vec::new());
+        assert!(registers.read('a').is_none());
+    }
+}
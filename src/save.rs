@@ -0,0 +1,278 @@
+#![warn(clippy::all, clippy::pedantic)]
+use ropey::RopeSlice;
+use std::{
+    ffi::OsString,
+    fs::{self, File, Metadata, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Takes the path of a file, the contents to write to it and
+/// whether to keep a copy of the old contents.
+/// Writes the contents to a temporary file next to the file and
+/// renames it into place, so that a failed save never leaves the
+/// file half-written. The new file keeps the permissions and
+/// ownership of the old one. When no file can be created next to
+/// it, or the ownership cannot be kept, writes to the file in
+/// place instead. Returns a bool representing whether the file
+/// was replaced atomically.
+///
+/// # Errors
+///
+/// Returns an error if the file, its backup or the temporary file
+/// cannot be written.
+pub fn write_atomically(path: &Path, contents: &[u8], backup: bool) -> io::Result<bool> {
+    // Write through symlinks rather than replacing them.
+    let path = match fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(error) if error.kind() == ErrorKind::NotFound => path.to_path_buf(),
+        Err(error) => return Err(error),
+    };
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => Some(metadata),
+        Err(error) if error.kind() == ErrorKind::NotFound => None,
+        Err(error) => return Err(error),
+    };
+    if backup && metadata.is_some() {
+        fs::copy(&path, backup_path(&path))?;
+    }
+    let temp_path = temp_path(&path);
+    let temp_file = match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)
+    {
+        Ok(file) => file,
+        Err(error) if error.kind() == ErrorKind::PermissionDenied => {
+            return write_in_place(&path, contents).map(|()| false);
+        }
+        Err(error) => return Err(error),
+    };
+    let result = write_temp_file(temp_file, &temp_path, contents, metadata.as_ref());
+    let result = match result {
+        Ok(true) => fs::rename(&temp_path, &path),
+        Ok(false) => {
+            let _ = fs::remove_file(&temp_path);
+            return write_in_place(&path, contents).map(|()| false);
+        }
+        Err(error) => Err(error),
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.map(|()| true)
+}
+
+/// Takes the temporary file, its path, the contents to write and
+/// the metadata of the file it will replace, if there is one.
+/// Writes the contents and copies the ownership and permissions of
+/// the old file. Returns a bool representing whether the ownership
+/// could be kept.
+fn write_temp_file(
+    mut file: File,
+    temp_path: &Path,
+    contents: &[u8],
+    metadata: Option<&Metadata>,
+) -> io::Result<bool> {
+    file.write_all(contents)?;
+    if let Some(metadata) = metadata {
+        if !keep_owner(temp_path, metadata)? {
+            return Ok(false);
+        }
+        file.set_permissions(metadata.permissions())?;
+    }
+    file.sync_all()?;
+    Ok(true)
+}
+
+/// Takes the path of the temporary file and the metadata of the
+/// file it will replace.
+/// Gives the temporary file the owner and group of the old file.
+/// Returns a bool representing whether they match afterwards.
+#[cfg(unix)]
+fn keep_owner(temp_path: &Path, metadata: &Metadata) -> io::Result<bool> {
+    use std::os::unix::fs::{self as unix_fs, MetadataExt};
+    // Only root may give files away, so a failed chown is not an
+    // error; the caller falls back to writing in place.
+    let _ = unix_fs::chown(temp_path, Some(metadata.uid()), Some(metadata.gid()));
+    let temp_metadata = fs::metadata(temp_path)?;
+    Ok(temp_metadata.uid() == metadata.uid() && temp_metadata.gid() == metadata.gid())
+}
+
+/// Takes the path of the temporary file and the metadata of the
+/// file it will replace.
+/// Returns true, as ownership is not kept on this platform.
+#[cfg(not(unix))]
+fn keep_owner(_temp_path: &Path, _metadata: &Metadata) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// Takes the path of a file and the contents to write to it.
+/// Truncates the file and writes the contents to it.
+fn write_in_place(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Takes the path of a file and the contents to add to it.
+/// Appends the contents to the end of the file, creating it if it
+/// does not exist.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or written.
+pub fn append(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Takes the text that was written to a file and the number of
+/// bytes it took up.
+/// Returns how many lines and bytes were written, as in `3L, 42B`.
+#[must_use]
+pub fn summary(text: RopeSlice, bytes: usize) -> String {
+    let len_chars = text.len_chars();
+    let ends_with_newline = len_chars > 0 && text.char(len_chars - 1) == '\n';
+    let lines = text.len_lines() - usize::from(ends_with_newline || len_chars == 0);
+    format!("{lines}L, {bytes}B")
+}
+
+/// Takes whether a file was replaced atomically.
+/// Returns a note to add to the message saying it was written, to
+/// warn that an interrupted save could have left it half-written.
+#[must_use]
+pub fn in_place_note(atomic: bool) -> &'static str {
+    if atomic {
+        ""
+    } else {
+        " in place (not atomically)"
+    }
+}
+
+/// Takes the path of a file.
+/// Returns the path of its backup copy, which has a `~` appended.
+fn backup_path(path: &Path) -> PathBuf {
+    let mut backup_path = OsString::from(path);
+    backup_path.push("~");
+    PathBuf::from(backup_path)
+}
+
+/// Takes the path of a file.
+/// Returns the path of a hidden temporary file in the same
+/// directory, so that renaming it over the file is atomic.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(path.file_name().unwrap_or_default());
+    file_name.push(format!(".{}.tmp", process::id()));
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use ropey::Rope;
+
+    fn summary_of(text: &str) -> String {
+        summary(Rope::from_str(text).slice(..), text.len())
+    }
+
+    #[test]
+    fn summary_counts_lines_and_bytes() {
+        assert_eq!(summary_of("a\nb\n"), "2L, 4B");
+        assert_eq!(summary_of("a\nb"), "2L, 3B");
+        assert_eq!(summary_of("\n"), "1L, 1B");
+        assert_eq!(summary_of("héllo\n"), "1L, 7B");
+    }
+
+    #[test]
+    fn summary_of_an_empty_file() {
+        assert_eq!(summary_of(""), "0L, 0B");
+    }
+
+    #[test]
+    fn summary_uses_the_bytes_written() {
+        let rope = Rope::from_str("a\nb\n");
+        assert_eq!(summary(rope.slice(..), 6), "2L, 6B");
+    }
+
+    #[test]
+    fn backup_and_temp_files_sit_next_to_the_file() {
+        let path = Path::new("/tmp/dir/notes.txt");
+        assert_eq!(backup_path(path), Path::new("/tmp/dir/notes.txt~"));
+        let temp_path = temp_path(path);
+        assert_eq!(temp_path.parent(), path.parent());
+        let name = temp_path.file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with(".notes.txt.") && name.ends_with(".tmp"));
+    }
+
+    #[test]
+    fn write_replaces_the_file() {
+        let dir = TempDir::new("save-replace");
+        let path = dir.0.join("file");
+        fs::write(&path, "old\n").unwrap();
+        assert!(write_atomically(&path, b"new\n", false).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert_eq!(dir.file_names(), ["file"]);
+    }
+
+    #[test]
+    fn write_creates_a_new_file() {
+        let dir = TempDir::new("save-create");
+        let path = dir.0.join("file");
+        assert!(write_atomically(&path, b"new\n", true).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert_eq!(dir.file_names(), ["file"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_keeps_the_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TempDir::new("save-mode");
+        let path = dir.0.join("file");
+        fs::write(&path, "old\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        write_atomically(&path, b"new\n", false).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[test]
+    fn write_with_backup_keeps_the_old_contents() {
+        let dir = TempDir::new("save-backup");
+        let path = dir.0.join("file");
+        fs::write(&path, "old\n").unwrap();
+        write_atomically(&path, b"new\n", true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert_eq!(fs::read_to_string(dir.0.join("file~")).unwrap(), "old\n");
+        assert_eq!(dir.file_names(), ["file", "file~"]);
+    }
+
+    #[test]
+    fn failed_write_leaves_no_temporary_file() {
+        let dir = TempDir::new("save-fail");
+        let path = dir.0.join("dir");
+        fs::create_dir(&path).unwrap();
+        assert!(write_atomically(&path, b"new\n", false).is_err());
+        assert!(path.is_dir());
+        assert_eq!(dir.file_names(), ["dir"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_through_a_symlink_updates_its_target() {
+        let dir = TempDir::new("save-symlink");
+        let target = dir.0.join("target");
+        let link = dir.0.join("link");
+        fs::write(&target, "old\n").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        write_atomically(&link, b"new\n", false).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new\n");
+        assert_eq!(dir.file_names(), ["link", "target"]);
+    }
+}
@@ -0,0 +1,206 @@
+#![warn(clippy::all, clippy::pedantic)]
+use regex::Regex;
+use ropey::{Rope, RopeSlice};
+use std::{borrow::Cow, ops::Range};
+
+/// Takes a `RopeSlice`.
+/// Returns the text of the slice, borrowing it when the slice
+/// is stored contiguously in the rope.
+fn slice_text(slice: RopeSlice) -> Cow<str> {
+    match slice.as_str() {
+        Some(text) => Cow::Borrowed(text),
+        None => Cow::Owned(slice.to_string()),
+    }
+}
+
+/// Takes a rope and the index of a line.
+/// Returns the byte index of the start of the line and its text
+/// without the line break that ends it, so that `$` matches
+/// before the line break rather than after it.
+fn line_text(rope: &Rope, line_idx: usize) -> Option<(usize, Cow<'_, str>)> {
+    let line = rope.get_line(line_idx)?;
+    let mut len = line.len_chars();
+    if len > 0 && line.char(len - 1) == '\n' {
+        len -= 1;
+        if len > 0 && line.char(len - 1) == '\r' {
+            len -= 1;
+        }
+    }
+    Some((rope.line_to_byte(line_idx), slice_text(line.slice(..len))))
+}
+
+/// Takes a rope, a regex and the index of a line.
+/// Returns the char ranges of every match on the line.
+/// Only one line is copied at a time, so this stays cheap
+/// on large ropes.
+#[must_use]
+pub fn line_matches(rope: &Rope, regex: &Regex, line_idx: usize) -> Vec<Range<usize>> {
+    let Some((line_byte, text)) = line_text(rope, line_idx) else {
+        return Vec::new();
+    };
+    regex
+        .find_iter(&text)
+        .filter(|found| !found.is_empty())
+        .map(|found| {
+            rope.byte_to_char(line_byte + found.start())..rope.byte_to_char(line_byte + found.end())
+        })
+        .collect()
+}
+
+/// Takes a rope, a regex and a range of char indices.
+/// Returns the char ranges of every match that lies entirely
+/// inside the range.
+#[must_use]
+pub fn find_in_range(rope: &Rope, regex: &Regex, range: &Range<usize>) -> Vec<Range<usize>> {
+    if range.is_empty() {
+        return Vec::new();
+    }
+    let first_line = rope.char_to_line(range.start);
+    let last_line = rope.char_to_line(range.end.saturating_sub(1));
+    (first_line..=last_line)
+        .flat_map(|line_idx| line_matches(rope, regex, line_idx))
+        .filter(|found| found.start >= range.start && found.end <= range.end)
+        .collect()
+}
+
+/// Takes a rope, a regex, the char index to search from and
+/// whether to search backwards.
+/// Returns the char range of the nearest match strictly after
+/// (or before) the char index, wrapping around the end of the rope.
+#[must_use]
+pub fn find_next(
+    rope: &Rope,
+    regex: &Regex,
+    char_idx: usize,
+    backward: bool,
+) -> Option<Range<usize>> {
+    let len_lines = rope.len_lines();
+    let start_line = rope.char_to_line(char_idx);
+    for step in 0..=len_lines {
+        let line_idx = if backward {
+            (start_line + len_lines * 2 - step) % len_lines
+        } else {
+            (start_line + step) % len_lines
+        };
+        let matches = line_matches(rope, regex, line_idx);
+        let found = if backward {
+            matches
+                .into_iter()
+                .rev()
+                .find(|found| step > 0 || found.start < char_idx)
+        } else {
+            matches
+                .into_iter()
+                .find(|found| step > 0 || found.start > char_idx)
+        };
+        if step == len_lines {
+            return found.filter(|found| {
+                if backward {
+                    found.start >= char_idx
+                } else {
+                    found.start <= char_idx
+                }
+            });
+        }
+        if found.is_some() {
+            return found;
+        }
+    }
+    None
+}
+
+/// Takes a rope, a regex, the index of a line, a replacement
+/// template and whether to replace every match on the line.
+/// Returns the char range of each match on the line along with
+/// its replacement, with capture groups expanded.
+#[must_use]
+pub fn line_substitutions(
+    rope: &Rope,
+    regex: &Regex,
+    line_idx: usize,
+    replacement: &str,
+    global: bool,
+) -> Vec<(Range<usize>, String)> {
+    // The empty line after a final line break is not part of the file.
+    let last_line = rope.len_lines().saturating_sub(1);
+    if line_idx == last_line && line_idx > 0 && rope.line(line_idx).len_chars() == 0 {
+        return Vec::new();
+    }
+    let Some((line_byte, text)) = line_text(rope, line_idx) else {
+        return Vec::new();
+    };
+    let mut substitutions = Vec::new();
+    for captures in regex.captures_iter(&text) {
+        let Some(found) = captures.get(0) else {
+            continue;
+        };
+        let mut expanded = String::new();
+        captures.expand(replacement, &mut expanded);
+        substitutions.push((
+            rope.byte_to_char(line_byte + found.start())
+                ..rope.byte_to_char(line_byte + found.end()),
+            expanded,
+        ));
+        if !global {
+            break;
+        }
+    }
+    substitutions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(rope: &Rope, pattern: &str, line_idx: usize) -> Vec<(usize, usize)> {
+        line_matches(rope, &Regex::new(pattern).unwrap(), line_idx)
+            .into_iter()
+            .map(|found| (found.start, found.end))
+            .collect()
+    }
+
+    #[test]
+    fn line_matches_leave_out_the_line_break() {
+        let rope = Rope::from_str("ax\nxa\r\nx");
+        assert_eq!(matches(&rope, "x$", 0), [(1, 2)]);
+        assert!(matches(&rope, "x$", 1).is_empty());
+        assert_eq!(matches(&rope, "x$", 2), [(7, 8)]);
+        assert!(matches(&rope, r"a\s", 1).is_empty());
+    }
+
+    #[test]
+    fn find_next_wraps_around_the_rope() {
+        let rope = Rope::from_str("ab\nab\n");
+        let regex = Regex::new("a").unwrap();
+        assert_eq!(find_next(&rope, &regex, 0, false), Some(3..4));
+        assert_eq!(find_next(&rope, &regex, 3, false), Some(0..1));
+        assert_eq!(find_next(&rope, &regex, 3, true), Some(0..1));
+        assert_eq!(find_next(&rope, &regex, 0, true), Some(3..4));
+    }
+
+    fn substitute(text: &str, pattern: &str, replacement: &str) -> String {
+        let mut rope = Rope::from_str(text);
+        let regex = Regex::new(pattern).unwrap();
+        let substitutions: Vec<_> = (0..rope.len_lines())
+            .flat_map(|line_idx| line_substitutions(&rope, &regex, line_idx, replacement, true))
+            .collect();
+        for (range, replacement) in substitutions.into_iter().rev() {
+            rope.remove(range.clone());
+            rope.insert(range.start, &replacement);
+        }
+        rope.to_string()
+    }
+
+    #[test]
+    fn substitutions_stop_before_the_line_break() {
+        assert_eq!(substitute("a\nb\n", "$", ";"), "a;\nb;\n");
+        assert_eq!(substitute("a\nb", "$", ";"), "a;\nb;");
+        assert_eq!(substitute("a\n\nb\n", "^", ">"), ">a\n>\n>b\n");
+        assert_eq!(substitute("", "^", ">"), ">");
+    }
+
+    #[test]
+    fn substitutions_expand_capture_groups() {
+        assert_eq!(substitute("ab ab", "(a)(b)", "${2}${1}"), "ba ba");
+    }
+}
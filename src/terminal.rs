@@ -1,48 +1,94 @@
 #![warn(clippy::all, clippy::pedantic)]
-use crate::Position;
+use crate::{Frame, Position};
 use crossterm::{
     cursor::{Hide, MoveTo, Show},
-    event::{read, Event, KeyEvent},
+    event::{poll, read, Event, KeyEvent},
     execute,
     terminal::{
         disable_raw_mode, enable_raw_mode, Clear, EnterAlternateScreen, LeaveAlternateScreen,
     },
 };
-use std::io::{self, Write};
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
 
 pub struct Size {
     pub width: u16,
     pub height: u16,
 }
 
+/// What the terminal reports while the editor waits for a key.
+pub enum Input {
+    Key(KeyEvent),
+    /// The terminal was resized, so the screen must be redrawn.
+    Resize,
+}
+
 pub struct Terminal {
     size: Size,
     pub raw_mode: bool,
     pub alt_screen: bool,
+    /// The frame on the screen, or `None` if something else has
+    /// been drawn since.
+    frame: Option<Frame>,
 }
 
 impl Terminal {
-    /// Takes nothing.
+    /// Takes whether to draw on the alternate screen.
     /// Creates a new `Terminal`.
     ///
     /// # Errors
     ///
     /// Will return an error if the terminal abstraction
     /// cannot be created.
-    pub fn new() -> Result<Self, std::io::Error> {
+    pub fn new(alt_screen: bool) -> Result<Self, std::io::Error> {
         let size = crossterm::terminal::size()?;
         let raw_ok = Self::enter_raw_mode();
-        let alt_ok = Self::enter_alt_screen();
+        let alt_ok = alt_screen && Self::enter_alt_screen().is_ok();
         Ok(Self {
             size: Size {
                 width: size.0,
                 height: size.1.saturating_sub(1),
             },
             raw_mode: raw_ok.is_ok(),
-            alt_screen: alt_ok.is_ok(),
+            alt_screen: alt_ok,
+            frame: None,
         })
     }
 
+    /// Takes itself and whether to draw on the alternate screen.
+    /// Enters or exits the alternate screen.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the alternate screen
+    /// cannot be entered or exited.
+    pub fn set_alt_screen(&mut self, alt_screen: bool) -> io::Result<()> {
+        if alt_screen && !self.alt_screen {
+            Self::enter_alt_screen()?;
+        } else if !alt_screen && self.alt_screen {
+            Self::clear_screen();
+            Self::exit_alt_screen()?;
+        }
+        self.alt_screen = alt_screen;
+        self.frame = None;
+        Ok(())
+    }
+
+    /// Takes itself and a `Frame`.
+    /// Draws the frame, writing only the cells that differ from the
+    /// frame on the screen.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if stdout cannot be written to.
+    pub fn draw(&mut self, frame: Frame) -> io::Result<()> {
+        frame.render(self.frame.as_ref(), &mut io::stdout().lock())?;
+        self.frame = Some(frame);
+        Ok(())
+    }
+
     /// Takes itself.
     /// Returns the terminal's size.
     #[must_use]
@@ -142,17 +188,52 @@ impl Terminal {
         print!("{Show}");
     }
 
-    /// Takes nothing.
-    /// Returns a `KeyEvent`.
+    /// Takes itself.
+    /// Waits for a key or a resize of the terminal, and returns it.
     ///
     /// # Errors
     ///
     /// Will return an error if the event cannot be read.
-    pub fn read_event() -> Result<KeyEvent, std::io::Error> {
+    pub fn read_event(&mut self) -> Result<Input, std::io::Error> {
         loop {
-            if let Event::Key(event) = read()? {
-                return Ok(event);
+            if let Some(input) = self.input(&read()?) {
+                return Ok(input);
+            }
+        }
+    }
+
+    /// Takes itself and how long to wait for a key.
+    /// Returns the next key or resize of the terminal, or `None` if
+    /// there was neither in time.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the event cannot be read.
+    pub fn poll_event(&mut self, timeout: Duration) -> Result<Option<Input>, std::io::Error> {
+        while poll(timeout)? {
+            if let Some(input) = self.input(&read()?) {
+                return Ok(Some(input));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Takes itself and an event read from the terminal.
+    /// Returns the `Input` it makes, if any. A resize updates the
+    /// size and forgets the frame on the screen, which the terminal
+    /// may have reflowed.
+    fn input(&mut self, event: &Event) -> Option<Input> {
+        match event {
+            Event::Key(event) => Some(Input::Key(*event)),
+            &Event::Resize(width, height) => {
+                self.size = Size {
+                    width,
+                    height: height.saturating_sub(1),
+                };
+                self.frame = None;
+                Some(Input::Resize)
             }
+            _ => None,
         }
     }
 }
@@ -0,0 +1,67 @@
+#![warn(clippy::all, clippy::pedantic)]
+use std::{
+    fs::{self, File},
+    path::PathBuf,
+    process,
+    time::SystemTime,
+};
+
+/// A file in the temporary directory that is removed when the
+/// test is done with it.
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    /// Takes a name that is unique among the tests and the contents
+    /// of the file.
+    /// Returns the file, after writing the contents to it.
+    pub fn new(name: &str, contents: impl AsRef<[u8]>) -> Self {
+        let path = std::env::temp_dir().join(format!("reflex-{}-{name}", process::id()));
+        fs::write(&path, contents).unwrap();
+        Self(path)
+    }
+
+    /// Takes itself and a time.
+    /// Sets the modification time of the file.
+    pub fn set_modified(&self, modified: SystemTime) {
+        let file = File::options().write(true).open(&self.0).unwrap();
+        file.set_modified(modified).unwrap();
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// A directory in the temporary directory that is removed, with
+/// everything in it, when the test is done with it.
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    /// Takes a name that is unique among the tests.
+    /// Returns the directory, after creating it.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("reflex-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        Self(path)
+    }
+
+    /// Takes itself.
+    /// Returns the names of the files in the directory, sorted.
+    pub fn file_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(&self.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,496 @@
+#![warn(clippy::all, clippy::pedantic)]
+use crate::config;
+use crossterm::style::{Attribute, Color, ContentStyle};
+use std::{collections::HashMap, env, fs, io::ErrorKind};
+use toml::{Table, Value};
+
+/// The themes built into the editor, by name.
+const BUILTIN_THEMES: [(&str, &str); 2] = [
+    ("default", include_str!("../themes/default.toml")),
+    ("light", include_str!("../themes/light.toml")),
+];
+
+/// How many themes may inherit from each other before loading gives up.
+const MAX_INHERITANCE_DEPTH: usize = 16;
+
+/// The 16 standard terminal colors, with the values xterm gives them.
+const ANSI_COLORS: [(Color, [u8; 3]); 16] = [
+    (Color::Black, [0, 0, 0]),
+    (Color::DarkRed, [205, 0, 0]),
+    (Color::DarkGreen, [0, 205, 0]),
+    (Color::DarkYellow, [205, 205, 0]),
+    (Color::DarkBlue, [0, 0, 238]),
+    (Color::DarkMagenta, [205, 0, 205]),
+    (Color::DarkCyan, [0, 205, 205]),
+    (Color::Grey, [229, 229, 229]),
+    (Color::DarkGrey, [127, 127, 127]),
+    (Color::Red, [255, 0, 0]),
+    (Color::Green, [0, 255, 0]),
+    (Color::Yellow, [255, 255, 0]),
+    (Color::Blue, [92, 92, 255]),
+    (Color::Magenta, [255, 0, 255]),
+    (Color::Cyan, [0, 255, 255]),
+    (Color::White, [255, 255, 255]),
+];
+
+/// How many colors the terminal can draw.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColorSupport {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+impl ColorSupport {
+    /// Takes nothing.
+    /// Returns the colors the terminal supports, judging by the
+    /// `COLORTERM` and `TERM` environment variables.
+    #[must_use]
+    pub fn detect() -> Self {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorSupport::TrueColor
+        } else if term.contains("256color") {
+            ColorSupport::Ansi256
+        } else {
+            ColorSupport::Ansi16
+        }
+    }
+
+    /// Takes itself and a `Color`.
+    /// Returns the nearest color the terminal can draw.
+    #[must_use]
+    pub fn downgrade(self, color: Color) -> Color {
+        match (self, color) {
+            (ColorSupport::Ansi256, Color::Rgb { r, g, b }) => {
+                Color::AnsiValue(nearest_ansi256([r, g, b]))
+            }
+            (ColorSupport::Ansi16, Color::Rgb { r, g, b }) => nearest_ansi16([r, g, b]),
+            (ColorSupport::Ansi16, Color::AnsiValue(value)) => {
+                nearest_ansi16(ansi256_to_rgb(value))
+            }
+            _ => color,
+        }
+    }
+}
+
+/// A color scheme, mapping UI elements and syntax scopes to styles.
+///
+/// Styles are looked up by dotted names such as `ui.cursor.primary`
+/// or `constant.numeric`. A name the theme does not style falls back
+/// to its parent, so `ui.cursor.primary` falls back to `ui.cursor`.
+pub struct Theme {
+    pub name: String,
+    styles: HashMap<String, ContentStyle>,
+}
+
+impl Default for Theme {
+    /// Takes nothing.
+    /// Builds the built-in default `Theme`.
+    fn default() -> Self {
+        Self::load("default", ColorSupport::detect()).unwrap_or_else(|_| Self {
+            name: String::from("default"),
+            styles: HashMap::new(),
+        })
+    }
+}
+
+impl Theme {
+    /// Takes the name of a theme and the colors the terminal supports.
+    /// Loads `$XDG_CONFIG_HOME/reflex/themes/<name>.toml`, or the
+    /// built-in theme of that name if there is no such file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if the theme cannot be found, read
+    /// or parsed.
+    pub fn load(name: &str, color_support: ColorSupport) -> Result<Self, String> {
+        let mut theme = Self {
+            name: name.to_string(),
+            styles: HashMap::new(),
+        };
+        theme.merge(name, false, color_support, 0)?;
+        Ok(theme)
+    }
+
+    /// Takes itself and a dotted name.
+    /// Returns the style for the name, falling back to its parents
+    /// and then to the terminal's own colors.
+    #[must_use]
+    pub fn get(&self, name: &str) -> ContentStyle {
+        let mut name = name;
+        loop {
+            if let Some(style) = self.styles.get(name) {
+                return *style;
+            }
+            match name.rfind('.') {
+                Some(idx) => name = &name[..idx],
+                None => return ContentStyle::new(),
+            }
+        }
+    }
+
+    /// Takes itself, the name of a theme, whether to skip user themes,
+    /// the colors the terminal supports and how many themes deep the
+    /// inheritance is.
+    /// Adds the styles of the theme, and of any theme it inherits
+    /// from, to its own.
+    fn merge(
+        &mut self,
+        name: &str,
+        builtin_only: bool,
+        color_support: ColorSupport,
+        depth: usize,
+    ) -> Result<(), String> {
+        if depth > MAX_INHERITANCE_DEPTH {
+            return Err(format!("Theme inherits from itself: {name}"));
+        }
+        let source = theme_source(name, builtin_only)?;
+        let mut table: Table = source.parse().map_err(|error: toml::de::Error| {
+            format!("Invalid theme {name}: {}", error.message())
+        })?;
+        if let Some(parent) = table.remove("inherits") {
+            let Value::String(parent) = parent else {
+                return Err(format!("Invalid theme {name}: inherits must be a string"));
+            };
+            // A user theme may extend the built-in theme it shadows.
+            let builtin_only = builtin_only || parent == name;
+            self.merge(
+                &parent,
+                builtin_only,
+                color_support,
+                depth.saturating_add(1),
+            )?;
+        }
+        let palette = match table.remove("palette") {
+            Some(Value::Table(palette)) => palette,
+            Some(_) => return Err(format!("Invalid theme {name}: palette must be a table")),
+            None => Table::new(),
+        };
+        let styles = Styles {
+            palette: &palette,
+            color_support,
+        };
+        styles
+            .collect("", &table, &mut self.styles)
+            .map_err(|message| format!("Invalid theme {name}: {message}"))
+    }
+}
+
+/// The context needed to turn theme entries into styles.
+struct Styles<'a> {
+    palette: &'a Table,
+    color_support: ColorSupport,
+}
+
+impl Styles<'_> {
+    /// Takes itself, the dotted name of a table, the table and the
+    /// map to fill.
+    /// Adds a style for every entry in the table, descending into
+    /// nested tables that are not styles themselves.
+    fn collect(
+        &self,
+        prefix: &str,
+        table: &Table,
+        styles: &mut HashMap<String, ContentStyle>,
+    ) -> Result<(), String> {
+        for (key, value) in table {
+            let name = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{prefix}.{key}")
+            };
+            match value {
+                Value::Table(table) if !is_style(table) => self.collect(&name, table, styles)?,
+                _ => {
+                    let style = self.style(&name, value)?;
+                    styles.insert(name, style);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes itself, the name of a style and its value, either a
+    /// foreground color or a table of `fg`, `bg` and `modifiers`.
+    /// Returns the style the value describes.
+    fn style(&self, name: &str, value: &Value) -> Result<ContentStyle, String> {
+        let mut style = ContentStyle::new();
+        let Value::Table(table) = value else {
+            style.foreground_color = Some(self.color(name, value)?);
+            return Ok(style);
+        };
+        for (key, value) in table {
+            match key.as_str() {
+                "fg" => style.foreground_color = Some(self.color(name, value)?),
+                "bg" => style.background_color = Some(self.color(name, value)?),
+                "modifiers" => {
+                    let Value::Array(modifiers) = value else {
+                        return Err(format!("modifiers of {name} must be a list"));
+                    };
+                    for modifier in modifiers {
+                        let attribute = modifier
+                            .as_str()
+                            .and_then(attribute)
+                            .ok_or_else(|| format!("unknown modifier for {name}: {modifier}"))?;
+                        style.attributes.set(attribute);
+                    }
+                }
+                _ => return Err(format!("unknown key in {name}: {key}")),
+            }
+        }
+        Ok(style)
+    }
+
+    /// Takes itself, the name of a style and a color value: a palette
+    /// entry, a color name, `#rrggbb` or a 256-color index.
+    /// Returns the nearest color the terminal can draw.
+    fn color(&self, name: &str, value: &Value) -> Result<Color, String> {
+        let invalid = || format!("invalid color for {name}: {value}");
+        let value = match value {
+            Value::String(color) => self.palette.get(color).unwrap_or(value),
+            _ => value,
+        };
+        let color = match value {
+            Value::Integer(index) => Color::AnsiValue(u8::try_from(*index).map_err(|_| invalid())?),
+            Value::String(color) => {
+                if let Some(hex) = color.strip_prefix('#') {
+                    parse_hex(hex).ok_or_else(invalid)?
+                } else if color == "reset" {
+                    Color::Reset
+                } else {
+                    Color::try_from(color.as_str()).map_err(|()| invalid())?
+                }
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(self.color_support.downgrade(color))
+    }
+}
+
+/// Takes a table from a theme.
+/// Returns a bool representing whether the table is a style rather
+/// than a group of nested names.
+fn is_style(table: &Table) -> bool {
+    ["fg", "bg", "modifiers"]
+        .iter()
+        .any(|key| table.contains_key(*key))
+}
+
+/// Takes the name of a modifier.
+/// Returns the `Attribute` it refers to.
+fn attribute(name: &str) -> Option<Attribute> {
+    match name {
+        "bold" => Some(Attribute::Bold),
+        "dim" => Some(Attribute::Dim),
+        "italic" => Some(Attribute::Italic),
+        "underlined" => Some(Attribute::Underlined),
+        "slow_blink" => Some(Attribute::SlowBlink),
+        "rapid_blink" => Some(Attribute::RapidBlink),
+        "reversed" => Some(Attribute::Reverse),
+        "hidden" => Some(Attribute::Hidden),
+        "crossed_out" => Some(Attribute::CrossedOut),
+        _ => None,
+    }
+}
+
+/// Takes the six hex digits of a color.
+/// Returns the color they describe.
+fn parse_hex(hex: &str) -> Option<Color> {
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |idx: usize| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok();
+    Some(Color::Rgb {
+        r: channel(0)?,
+        g: channel(2)?,
+        b: channel(4)?,
+    })
+}
+
+/// Takes the name of a theme and whether to skip user themes.
+/// Returns the contents of the theme file.
+fn theme_source(name: &str, builtin_only: bool) -> Result<String, String> {
+    if let (false, Some(dir)) = (builtin_only, config::config_dir()) {
+        let dir = dir.join("themes");
+        let path = dir.join(format!("{name}.toml"));
+        match fs::read_to_string(&path) {
+            Ok(source) => return Ok(source),
+            Err(error) if error.kind() == ErrorKind::NotFound => (),
+            Err(error) => return Err(format!("{}: {error}", path.display())),
+        }
+    }
+    BUILTIN_THEMES
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, source)| (*source).to_string())
+        .ok_or_else(|| format!("Theme not found: {name}"))
+}
+
+/// Takes a base style and a style drawn on top of it.
+/// Returns the base style with the colors and modifiers that the
+/// top style sets.
+#[must_use]
+pub fn overlay(base: ContentStyle, top: ContentStyle) -> ContentStyle {
+    let mut attributes = base.attributes;
+    attributes.extend(top.attributes);
+    ContentStyle {
+        foreground_color: top.foreground_color.or(base.foreground_color),
+        background_color: top.background_color.or(base.background_color),
+        underline_color: top.underline_color.or(base.underline_color),
+        attributes,
+    }
+}
+
+/// Takes a 256-color index.
+/// Returns the color it refers to in xterm's palette.
+fn ansi256_to_rgb(index: u8) -> [u8; 3] {
+    match index {
+        0..=15 => ANSI_COLORS[usize::from(index)].1,
+        16..=231 => {
+            let level = |value: u8| if value == 0 { 0 } else { 55 + 40 * value };
+            let index = index - 16;
+            [level(index / 36), level(index / 6 % 6), level(index % 6)]
+        }
+        232..=255 => {
+            let grey = 8 + 10 * (index - 232);
+            [grey; 3]
+        }
+    }
+}
+
+/// Takes a pair of colors.
+/// Returns the squared distance between them.
+fn distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| u32::from(a.abs_diff(b)).pow(2))
+        .sum()
+}
+
+/// Takes a color.
+/// Returns the index of the nearest color in the 6x6x6 cube or the
+/// grey ramp of the 256-color palette.
+fn nearest_ansi256(rgb: [u8; 3]) -> u8 {
+    let cube_level = |value: u8| match value {
+        0..=47 => 0,
+        48..=114 => 1,
+        _ => (value - 35) / 40,
+    };
+    let [r, g, b] = rgb.map(cube_level);
+    let cube = 16 + 36 * r + 6 * g + b;
+    let average = rgb.iter().map(|value| u16::from(*value)).sum::<u16>() / 3;
+    let grey = 232 + u8::try_from(average.saturating_sub(3) / 10).map_or(23, |step| step.min(23));
+    if distance(rgb, ansi256_to_rgb(grey)) < distance(rgb, ansi256_to_rgb(cube)) {
+        grey
+    } else {
+        cube
+    }
+}
+
+/// Takes a color.
+/// Returns the nearest of the 16 standard terminal colors.
+fn nearest_ansi16(rgb: [u8; 3]) -> Color {
+    ANSI_COLORS
+        .iter()
+        .min_by_key(|(_, value)| distance(rgb, *value))
+        .map_or(Color::Reset, |(color, _)| *color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = Color::Rgb { r: 255, g: 0, b: 0 };
+
+    #[test]
+    fn true_color_keeps_every_color() {
+        assert_eq!(ColorSupport::TrueColor.downgrade(RED), RED);
+        let value = Color::AnsiValue(100);
+        assert_eq!(ColorSupport::TrueColor.downgrade(value), value);
+    }
+
+    #[test]
+    fn downgrade_to_256_colors() {
+        let support = ColorSupport::Ansi256;
+        assert_eq!(support.downgrade(RED), Color::AnsiValue(196));
+        let black = Color::Rgb { r: 0, g: 0, b: 0 };
+        assert_eq!(support.downgrade(black), Color::AnsiValue(16));
+        let grey = Color::Rgb {
+            r: 128,
+            g: 128,
+            b: 128,
+        };
+        assert_eq!(support.downgrade(grey), Color::AnsiValue(244));
+        assert_eq!(
+            support.downgrade(Color::AnsiValue(42)),
+            Color::AnsiValue(42)
+        );
+    }
+
+    #[test]
+    fn downgrade_to_16_colors() {
+        let support = ColorSupport::Ansi16;
+        assert_eq!(support.downgrade(RED), Color::Red);
+        let dark_red = Color::Rgb {
+            r: 200,
+            g: 10,
+            b: 0,
+        };
+        assert_eq!(support.downgrade(dark_red), Color::DarkRed);
+        assert_eq!(support.downgrade(Color::AnsiValue(196)), Color::Red);
+        assert_eq!(support.downgrade(Color::AnsiValue(1)), Color::DarkRed);
+        assert_eq!(support.downgrade(Color::AnsiValue(255)), Color::Grey);
+        assert_eq!(support.downgrade(Color::Blue), Color::Blue);
+    }
+
+    #[test]
+    fn palette_matches_xterm() {
+        assert_eq!(ansi256_to_rgb(9), [255, 0, 0]);
+        assert_eq!(ansi256_to_rgb(16), [0, 0, 0]);
+        assert_eq!(ansi256_to_rgb(196), [255, 0, 0]);
+        assert_eq!(ansi256_to_rgb(231), [255, 255, 255]);
+        assert_eq!(ansi256_to_rgb(232), [8, 8, 8]);
+        assert_eq!(ansi256_to_rgb(255), [238, 238, 238]);
+    }
+
+    #[test]
+    fn styles_fall_back_to_their_parents() {
+        let style = ContentStyle {
+            foreground_color: Some(RED),
+            ..ContentStyle::new()
+        };
+        let theme = Theme {
+            name: String::from("test"),
+            styles: HashMap::from([(String::from("ui.cursor"), style)]),
+        };
+        assert_eq!(theme.get("ui.cursor.primary"), style);
+        assert_eq!(theme.get("ui.cursor"), style);
+        assert_eq!(theme.get("ui"), ContentStyle::new());
+    }
+
+    #[test]
+    fn overlay_keeps_what_the_top_style_leaves_unset() {
+        let base = ContentStyle {
+            foreground_color: Some(Color::White),
+            background_color: Some(Color::Black),
+            ..ContentStyle::new()
+        };
+        let top = ContentStyle {
+            foreground_color: Some(RED),
+            ..ContentStyle::new()
+        };
+        let style = overlay(base, top);
+        assert_eq!(style.foreground_color, Some(RED));
+        assert_eq!(style.background_color, Some(Color::Black));
+    }
+
+    #[test]
+    fn builtin_themes_parse() {
+        for (name, _) in BUILTIN_THEMES {
+            let theme = Theme::load(name, ColorSupport::Ansi16);
+            assert!(theme.is_ok(), "{name}: {:?}", theme.err());
+        }
+    }
+}
@@ -0,0 +1,429 @@
+#![warn(clippy::all, clippy::pedantic)]
+use crate::filebuffer::View;
+use std::{cmp, mem};
+
+/// A view onto one of the open `FileBuffer`s. Several windows may
+/// show the same buffer, each with its own selections and viewport.
+pub struct Window {
+    pub file_buffer_idx: usize,
+    pub view: View,
+}
+
+/// How a split divides its area between its two halves.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SplitDirection {
+    /// One window above the other, as made by `:split`.
+    Horizontal,
+    /// One window beside the other, as made by `:vsplit`.
+    Vertical,
+}
+
+/// A side of a window, used to move between neighbouring windows.
+#[derive(Clone, Copy)]
+pub enum Side {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// An area of the terminal, in cells.
+#[derive(Default, Clone, Copy)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// How the windows are arranged on the screen. Each split holds
+/// the size of its first half, or `None` to share the area equally.
+pub enum Layout {
+    Window(usize),
+    Split {
+        direction: SplitDirection,
+        first_size: Option<u16>,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    /// Takes itself and the index of a window.
+    /// Returns a bool representing whether the window is part
+    /// of the layout.
+    #[must_use]
+    pub fn contains(&self, window_idx: usize) -> bool {
+        match self {
+            Layout::Window(idx) => *idx == window_idx,
+            Layout::Split { first, second, .. } => {
+                first.contains(window_idx) || second.contains(window_idx)
+            }
+        }
+    }
+
+    /// Takes itself, the window to split, the new window and the
+    /// direction to split in.
+    /// Shares the area of the window with the new one, which is
+    /// placed above or to the left of it.
+    pub fn split(&mut self, window_idx: usize, new_window_idx: usize, direction: SplitDirection) {
+        match self {
+            Layout::Window(idx) if *idx == window_idx => {
+                *self = Layout::Split {
+                    direction,
+                    first_size: None,
+                    first: Box::new(Layout::Window(new_window_idx)),
+                    second: Box::new(Layout::Window(window_idx)),
+                };
+            }
+            Layout::Window(_) => (),
+            Layout::Split { first, second, .. } => {
+                first.split(window_idx, new_window_idx, direction);
+                second.split(window_idx, new_window_idx, direction);
+            }
+        }
+    }
+
+    /// Takes itself and the index of a window.
+    /// Removes the window, giving its area to its sibling, and
+    /// renumbers the windows after it.
+    pub fn remove(&mut self, window_idx: usize) {
+        self.remove_window(window_idx);
+        self.renumber(window_idx);
+    }
+
+    /// Takes itself and the index of a window.
+    /// Replaces the split holding the window with its other half.
+    /// Returns a bool representing whether the window was found.
+    fn remove_window(&mut self, window_idx: usize) -> bool {
+        let Layout::Split { first, second, .. } = self else {
+            return false;
+        };
+        let sibling = if matches!(**first, Layout::Window(idx) if idx == window_idx) {
+            mem::replace(&mut **second, Layout::Window(0))
+        } else if matches!(**second, Layout::Window(idx) if idx == window_idx) {
+            mem::replace(&mut **first, Layout::Window(0))
+        } else {
+            return first.remove_window(window_idx) || second.remove_window(window_idx);
+        };
+        *self = sibling;
+        true
+    }
+
+    /// Takes itself and the index of a removed window.
+    /// Shifts the indices of the windows after it down by one.
+    fn renumber(&mut self, removed_idx: usize) {
+        match self {
+            Layout::Window(idx) => {
+                if *idx > removed_idx {
+                    *idx -= 1;
+                }
+            }
+            Layout::Split { first, second, .. } => {
+                first.renumber(removed_idx);
+                second.renumber(removed_idx);
+            }
+        }
+    }
+
+    /// Takes itself and the area of the screen to fill.
+    /// Returns the area of every window, along with the columns
+    /// separating side-by-side windows.
+    #[must_use]
+    pub fn rects(&self, area: Rect) -> (Vec<(usize, Rect)>, Vec<Rect>) {
+        let mut windows = Vec::new();
+        let mut separators = Vec::new();
+        self.collect_rects(area, &mut windows, &mut separators);
+        (windows, separators)
+    }
+
+    /// Takes itself, its area and the lists to fill.
+    /// Adds the areas of its windows and separators to the lists.
+    fn collect_rects(
+        &self,
+        area: Rect,
+        windows: &mut Vec<(usize, Rect)>,
+        separators: &mut Vec<Rect>,
+    ) {
+        match self {
+            Layout::Window(idx) => windows.push((*idx, area)),
+            Layout::Split {
+                direction,
+                first_size,
+                first,
+                second,
+            } => {
+                let (first_area, separator, second_area) = divide(*direction, *first_size, area);
+                first.collect_rects(first_area, windows, separators);
+                if let Some(separator) = separator {
+                    separators.push(separator);
+                }
+                second.collect_rects(second_area, windows, separators);
+            }
+        }
+    }
+
+    /// Takes itself, the index of a window and the area of the screen.
+    /// Returns the area of the window, if it is part of the layout.
+    #[must_use]
+    pub fn rect(&self, window_idx: usize, area: Rect) -> Option<Rect> {
+        self.rects(area)
+            .0
+            .into_iter()
+            .find(|(idx, _)| *idx == window_idx)
+            .map(|(_, rect)| rect)
+    }
+
+    /// Takes itself, the index of a window, the direction to resize
+    /// in, the number of cells to grow by and the area of the screen.
+    /// Grows (or shrinks) the window by moving the edge of the
+    /// innermost split in that direction. Returns a bool representing
+    /// whether there was such a split.
+    pub fn resize(
+        &mut self,
+        window_idx: usize,
+        direction: SplitDirection,
+        delta: i16,
+        area: Rect,
+    ) -> bool {
+        let Layout::Split {
+            direction: split_direction,
+            first_size,
+            first,
+            second,
+        } = self
+        else {
+            return false;
+        };
+        let (first_area, _, second_area) = divide(*split_direction, *first_size, area);
+        let in_first = first.contains(window_idx);
+        if !in_first && !second.contains(window_idx) {
+            return false;
+        }
+        let resized = if in_first {
+            first.resize(window_idx, direction, delta, first_area)
+        } else {
+            second.resize(window_idx, direction, delta, second_area)
+        };
+        if resized || *split_direction != direction {
+            return resized;
+        }
+        let (current, total, min) = match direction {
+            SplitDirection::Horizontal => (first_area.height, area.height, 2),
+            SplitDirection::Vertical => (first_area.width, area.width.saturating_sub(1), 1),
+        };
+        let delta = if in_first { delta } else { -delta };
+        *first_size = Some(clamp_size(current.saturating_add_signed(delta), total, min));
+        true
+    }
+
+    /// Takes itself.
+    /// Shares the area of every split equally between its halves.
+    pub fn equalize(&mut self) {
+        if let Layout::Split {
+            first_size,
+            first,
+            second,
+            ..
+        } = self
+        {
+            *first_size = None;
+            first.equalize();
+            second.equalize();
+        }
+    }
+}
+
+/// Takes the direction of a split, the size of its first half and its area.
+/// Returns the areas of both halves, and of the separator between
+/// them for side-by-side windows.
+fn divide(
+    direction: SplitDirection,
+    first_size: Option<u16>,
+    area: Rect,
+) -> (Rect, Option<Rect>, Rect) {
+    match direction {
+        SplitDirection::Horizontal => {
+            let total = area.height;
+            let first = clamp_size(first_size.unwrap_or(total / 2), total, 2);
+            (
+                Rect {
+                    height: first,
+                    ..area
+                },
+                None,
+                Rect {
+                    y: area.y.saturating_add(first),
+                    height: total - first,
+                    ..area
+                },
+            )
+        }
+        SplitDirection::Vertical => {
+            let total = area.width.saturating_sub(1);
+            let first = clamp_size(first_size.unwrap_or(total / 2), total, 1);
+            (
+                Rect {
+                    width: first,
+                    ..area
+                },
+                Some(Rect {
+                    x: area.x.saturating_add(first),
+                    width: cmp::min(area.width, 1),
+                    ..area
+                }),
+                Rect {
+                    x: area.x.saturating_add(first).saturating_add(1),
+                    width: total - first,
+                    ..area
+                },
+            )
+        }
+    }
+}
+
+/// Takes the size of the first half of a split, the size of the
+/// whole split and the smallest size a half may have.
+/// Returns the size clamped so that both halves fit.
+fn clamp_size(size: u16, total: u16, min: u16) -> u16 {
+    if total < min.saturating_mul(2) {
+        total / 2
+    } else {
+        size.clamp(min, total - min)
+    }
+}
+
+/// Takes the areas of the windows, the index of a window and a side.
+/// Returns the nearest window on that side of the given one which
+/// overlaps it, if there is one.
+#[must_use]
+pub fn neighbour(rects: &[(usize, Rect)], window_idx: usize, side: Side) -> Option<usize> {
+    let (_, current) = rects.iter().find(|(idx, _)| *idx == window_idx)?;
+    let overlaps = |start: u16, len: u16, other_start: u16, other_len: u16| {
+        start < other_start.saturating_add(other_len) && other_start < start.saturating_add(len)
+    };
+    rects
+        .iter()
+        .filter(|(idx, _)| *idx != window_idx)
+        .filter_map(|(idx, rect)| {
+            let distance = match side {
+                Side::Left => current.x.checked_sub(rect.x.saturating_add(rect.width))?,
+                Side::Right => rect
+                    .x
+                    .checked_sub(current.x.saturating_add(current.width))?,
+                Side::Up => current.y.checked_sub(rect.y.saturating_add(rect.height))?,
+                Side::Down => rect
+                    .y
+                    .checked_sub(current.y.saturating_add(current.height))?,
+            };
+            let offset = match side {
+                Side::Left | Side::Right => {
+                    if !overlaps(current.y, current.height, rect.y, rect.height) {
+                        return None;
+                    }
+                    current.y.abs_diff(rect.y)
+                }
+                Side::Up | Side::Down => {
+                    if !overlaps(current.x, current.width, rect.x, rect.width) {
+                        return None;
+                    }
+                    current.x.abs_diff(rect.x)
+                }
+            };
+            Some((distance, offset, *idx))
+        })
+        .min()
+        .map(|(_, _, idx)| idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: 80,
+        height: 24,
+    };
+
+    fn bounds(rect: Rect) -> (u16, u16, u16, u16) {
+        (rect.x, rect.y, rect.width, rect.height)
+    }
+
+    fn windows(layout: &Layout) -> Vec<(usize, (u16, u16, u16, u16))> {
+        let (windows, _) = layout.rects(AREA);
+        windows
+            .into_iter()
+            .map(|(idx, rect)| (idx, bounds(rect)))
+            .collect()
+    }
+
+    /// Window 1 on the left, with window 2 above window 0 on the right.
+    fn three_windows() -> Layout {
+        let mut layout = Layout::Window(0);
+        layout.split(0, 1, SplitDirection::Vertical);
+        layout.split(0, 2, SplitDirection::Horizontal);
+        layout
+    }
+
+    #[test]
+    fn splits_share_the_area() {
+        let layout = three_windows();
+        assert_eq!(
+            windows(&layout),
+            [
+                (1, (0, 0, 39, 24)),
+                (2, (40, 0, 40, 12)),
+                (0, (40, 12, 40, 12)),
+            ]
+        );
+        let (_, separators) = layout.rects(AREA);
+        let separators: Vec<_> = separators.into_iter().map(bounds).collect();
+        assert_eq!(separators, [(39, 0, 1, 24)]);
+    }
+
+    #[test]
+    fn resize_moves_the_innermost_split_edge() {
+        let mut layout = three_windows();
+        assert!(layout.resize(2, SplitDirection::Horizontal, 3, AREA));
+        assert_eq!(
+            windows(&layout)[1..],
+            [(2, (40, 0, 40, 15)), (0, (40, 15, 40, 9))]
+        );
+        assert!(layout.resize(0, SplitDirection::Vertical, 5, AREA));
+        assert_eq!(windows(&layout)[0], (1, (0, 0, 34, 24)));
+        assert!(layout.resize(1, SplitDirection::Vertical, 100, AREA));
+        assert_eq!(windows(&layout)[0], (1, (0, 0, 78, 24)));
+        layout.equalize();
+        assert_eq!(windows(&layout), windows(&three_windows()));
+    }
+
+    #[test]
+    fn resize_without_a_split_in_that_direction_does_nothing() {
+        let mut layout = Layout::Window(0);
+        assert!(!layout.resize(0, SplitDirection::Vertical, 1, AREA));
+        layout.split(0, 1, SplitDirection::Horizontal);
+        assert!(!layout.resize(0, SplitDirection::Vertical, 1, AREA));
+    }
+
+    #[test]
+    fn remove_gives_the_area_to_the_sibling_and_renumbers() {
+        let mut layout = three_windows();
+        layout.remove(1);
+        assert_eq!(
+            windows(&layout),
+            [(1, (0, 0, 80, 12)), (0, (0, 12, 80, 12))]
+        );
+        assert!(!layout.contains(2));
+    }
+
+    #[test]
+    fn neighbour_finds_the_nearest_overlapping_window() {
+        let (rects, _) = three_windows().rects(AREA);
+        assert_eq!(neighbour(&rects, 1, Side::Right), Some(2));
+        assert_eq!(neighbour(&rects, 0, Side::Up), Some(2));
+        assert_eq!(neighbour(&rects, 0, Side::Left), Some(1));
+        assert_eq!(neighbour(&rects, 1, Side::Left), None);
+    }
+}